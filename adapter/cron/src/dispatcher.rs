@@ -0,0 +1,106 @@
+//! Runs due flows concurrently, bounded by a global limit and by the overlap
+//! policy of every flow.
+//!
+//! A run is only started on the replica that claimed it, see
+//! [`crate::fire_lock`]. The overlap policy is applied after claiming, with
+//! the running runs marked in the shared lock bucket, so it holds across
+//! replicas.
+
+use base::store::{AdapterStore, FlowExecutionResult};
+use chrono::Utc;
+use prometheus::IntCounter;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::fire_lock::FireLock;
+use crate::fire_log::FireLog;
+use crate::input::RunInput;
+use crate::scheduler::DueRun;
+
+pub struct Dispatcher {
+    store: Arc<AdapterStore>,
+    lock: FireLock,
+    fire_log: FireLog,
+    /// `None` runs any number of executions at once.
+    permits: Option<Arc<Semaphore>>,
+    skipped_runs: IntCounter,
+}
+
+impl Dispatcher {
+    /// `max_concurrent` of 0 disables the limit.
+    pub fn new(
+        store: Arc<AdapterStore>,
+        lock: FireLock,
+        fire_log: FireLog,
+        max_concurrent: usize,
+        skipped_runs: IntCounter,
+    ) -> Self {
+        Self {
+            store,
+            lock,
+            fire_log,
+            permits: (max_concurrent > 0).then(|| Arc::new(Semaphore::new(max_concurrent))),
+            skipped_runs,
+        }
+    }
+
+    /// Executions run detached from the scheduler loop, so a shutdown that
+    /// stops the loop lets them finish within the drain timeout.
+    pub fn dispatch(&self, run: DueRun) {
+        let store = Arc::clone(&self.store);
+        let lock = self.lock.clone();
+        let fire_log = self.fire_log.clone();
+        let permits = self.permits.clone();
+        let skipped_runs = self.skipped_runs.clone();
+
+        tokio::spawn(async move {
+            let flow_id = run.flow.flow_id;
+            if !lock.claim(flow_id, run.at).await {
+                return;
+            }
+            // Waiting for the flow's own turn first keeps queued runs from
+            // holding on to a permit other flows could use.
+            let Some(running) = lock.enter(flow_id, run.overlap).await else {
+                log::info!(
+                    "Skipping run of flow with id: {} due at {}, previous run still executing",
+                    flow_id,
+                    run.at
+                );
+                skipped_runs.inc();
+                return;
+            };
+            let _permit = match permits {
+                Some(permits) => match permits.acquire_owned().await {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        running.release().await;
+                        return;
+                    }
+                },
+                None => None,
+            };
+            // Only the replica that executes the run counts it.
+            let occurrence = fire_log.count_run(flow_id).await.unwrap_or_default();
+
+            let input = RunInput {
+                scheduled_at: run.at,
+                triggered_at: Utc::now(),
+                timezone: run.timezone,
+                expression: &run.expression,
+                occurrence,
+                catch_up: run.catch_up,
+            }
+            .to_value();
+            let result = store.execute_flow_with_emitter(run.flow, Some(input)).await;
+            // Failed runs stay unrecorded until a later run of the flow
+            // succeeds, see `crate::fire_log`.
+            if matches!(
+                result,
+                FlowExecutionResult::Ongoing(_) | FlowExecutionResult::FinishedWithoutOngoing
+            ) {
+                fire_log.record_fired(flow_id, run.at).await;
+            }
+            running.release().await;
+        });
+    }
+}
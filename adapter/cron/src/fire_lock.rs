@@ -0,0 +1,240 @@
+//! Claims of flow runs, shared by all replicas of the adapter in a NATS KV
+//! bucket.
+//!
+//! Every replica schedules every flow, but a run only fires on the replica
+//! that claims it first: the claim creates the key `<flow_id>.<fire time>`,
+//! which fails for everyone else. Claims expire with the bucket's max age, so
+//! there is no leader to hand over; when a replica dies, the others simply
+//! keep claiming the next runs.
+//!
+//! The bucket also holds the overlap policy of every flow: a run that is not
+//! allowed to overlap marks the flow as running under `<flow_id>.running`
+//! until it finished, and a queued run waits under `<flow_id>.queued`. The
+//! markers expire like the claims, so a run outliving `CRON_LOCK_TTL_SECONDS`
+//! may be overlapped.
+
+use async_nats::jetstream::kv;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+use crate::schedule::OverlapPolicy;
+
+/// Delay between the attempts of a queued run to start.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct FireLock {
+    kv: kv::Store,
+    /// Written into the claims to see which replica fired a run.
+    replica: String,
+}
+
+impl FireLock {
+    pub fn new(kv: kv::Store, replica: String) -> Self {
+        Self { kv, replica }
+    }
+
+    /// Whether this replica fires the run of the flow at `at`.
+    ///
+    /// Runs that can't be claimed are not fired, as firing them could run
+    /// them twice.
+    pub async fn claim(&self, flow_id: i64, at: DateTime<Utc>) -> bool {
+        let key = format!("{}.{}", flow_id, at.timestamp_millis());
+        match self.kv.create(&key, self.replica.clone().into()).await {
+            Ok(_) => true,
+            Err(err) if err.kind() == kv::CreateErrorKind::AlreadyExists => {
+                log::debug!(
+                    "Run of flow with id: {} at {} is fired by another replica",
+                    flow_id,
+                    at
+                );
+                false
+            }
+            Err(err) => {
+                log::warn!(
+                    "Could not claim run of flow with id: {} at {}: {}",
+                    flow_id,
+                    at,
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    /// Waits until a run of the flow may start following its overlap policy,
+    /// or returns `None` if the run is skipped.
+    ///
+    /// Runs whose marker can't be written are skipped, as running them could
+    /// overlap.
+    pub async fn enter(&self, flow_id: i64, overlap: OverlapPolicy) -> Option<Running> {
+        if overlap == OverlapPolicy::Allow {
+            return Some(Running { marker: None });
+        }
+
+        let running_key = format!("{}.running", flow_id);
+        match self.mark(&running_key).await {
+            Mark::Created(revision) => return Some(self.running(running_key, revision)),
+            Mark::Exists if overlap == OverlapPolicy::Queue => {}
+            Mark::Exists | Mark::Failed => return None,
+        }
+
+        // Only one run waits at a time, on any replica.
+        let queued_key = format!("{}.queued", flow_id);
+        let Mark::Created(queued) = self.mark(&queued_key).await else {
+            return None;
+        };
+        let running = loop {
+            tokio::time::sleep(QUEUE_POLL_INTERVAL).await;
+            match self.mark(&running_key).await {
+                Mark::Created(revision) => break Some(self.running(running_key, revision)),
+                Mark::Exists => {}
+                Mark::Failed => break None,
+            }
+        };
+        unmark(&self.kv, &queued_key, queued).await;
+        running
+    }
+
+    fn running(&self, key: String, revision: u64) -> Running {
+        Running {
+            marker: Some((self.kv.clone(), key, revision)),
+        }
+    }
+
+    async fn mark(&self, key: &str) -> Mark {
+        match self.kv.create(key, self.replica.clone().into()).await {
+            Ok(revision) => Mark::Created(revision),
+            Err(err) if err.kind() == kv::CreateErrorKind::AlreadyExists => Mark::Exists,
+            Err(err) => {
+                log::warn!("Could not mark {}: {}", key, err);
+                Mark::Failed
+            }
+        }
+    }
+}
+
+enum Mark {
+    Created(u64),
+    Exists,
+    Failed,
+}
+
+/// Held while a run of a flow executes, see [`FireLock::enter`].
+pub struct Running {
+    /// Bucket, key and revision of the marker, if the flow's runs may not
+    /// overlap.
+    marker: Option<(kv::Store, String, u64)>,
+}
+
+impl Running {
+    /// Lets the next run of the flow start.
+    pub async fn release(self) {
+        if let Some((kv, key, revision)) = self.marker {
+            unmark(&kv, &key, revision).await;
+        }
+    }
+}
+
+/// Removes a marker, unless it expired and was written again meanwhile.
+async fn unmark(kv: &kv::Store, key: &str, revision: u64) {
+    if let Err(err) = kv.delete_expect_revision(key, Some(revision)).await {
+        log::warn!("Could not remove {}: {}", key, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Runs against a locally started `nats-server`, so the tests are ignored
+    //! by default. Set `NATS_SERVER_BIN` to use a binary outside of `PATH`.
+
+    use super::FireLock;
+    use crate::schedule::OverlapPolicy;
+    use async_nats::jetstream;
+    use base::nats::testing::NatsServer;
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    async fn lock(server: &NatsServer, replica: &str) -> FireLock {
+        let client = async_nats::connect(&server.url).await.unwrap();
+        let kv = jetstream::new(client)
+            .create_key_value(jetstream::kv::Config {
+                bucket: "cron_locks".into(),
+                max_age: Duration::from_secs(60),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        FireLock::new(kv, replica.into())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a `nats-server` binary"]
+    async fn only_one_replica_claims_a_run() {
+        let server = NatsServer::start(&[]);
+        let first = lock(&server, "cron-a").await;
+        let second = lock(&server, "cron-b").await;
+        let at = Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap();
+
+        assert!(first.claim(1, at).await);
+        assert!(!second.claim(1, at).await);
+        assert!(!first.claim(1, at).await);
+
+        // Other flows and fire times are claimed independently.
+        assert!(second.claim(2, at).await);
+        assert!(second.claim(1, at + chrono::Duration::minutes(1)).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a `nats-server` binary"]
+    async fn another_replica_takes_over_when_one_dies() {
+        let server = NatsServer::start(&[]);
+        let at = Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap();
+
+        let leader = lock(&server, "cron-a").await;
+        let standby = lock(&server, "cron-b").await;
+        assert!(leader.claim(1, at).await);
+        drop(leader);
+
+        assert!(!standby.claim(1, at).await);
+        assert!(standby.claim(1, at + chrono::Duration::minutes(1)).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a `nats-server` binary"]
+    async fn skipped_runs_see_runs_of_other_replicas() {
+        let server = NatsServer::start(&[]);
+        let first = lock(&server, "cron-a").await;
+        let second = lock(&server, "cron-b").await;
+
+        let running = first.enter(1, OverlapPolicy::Skip).await.unwrap();
+        assert!(second.enter(1, OverlapPolicy::Skip).await.is_none());
+        assert!(second.enter(1, OverlapPolicy::Allow).await.is_some());
+        assert!(second.enter(2, OverlapPolicy::Skip).await.is_some());
+
+        running.release().await;
+        assert!(second.enter(1, OverlapPolicy::Skip).await.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a `nats-server` binary"]
+    async fn one_run_is_queued_across_replicas() {
+        let server = NatsServer::start(&[]);
+        let first = lock(&server, "cron-a").await;
+        let second = lock(&server, "cron-b").await;
+        let third = lock(&server, "cron-c").await;
+
+        let running = first.enter(1, OverlapPolicy::Queue).await.unwrap();
+        let queued = tokio::spawn(async move { second.enter(1, OverlapPolicy::Queue).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!queued.is_finished());
+        assert!(third.enter(1, OverlapPolicy::Queue).await.is_none());
+
+        running.release().await;
+        let queued = tokio::time::timeout(Duration::from_secs(5), queued)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(queued.is_some());
+    }
+}
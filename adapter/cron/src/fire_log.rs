@@ -0,0 +1,190 @@
+//! Last successful fire time of every flow, persisted in a NATS KV bucket.
+//!
+//! It survives restarts of the adapter, so runs missed while the adapter was
+//! down can be caught up after a restart according to the flow's misfire
+//! policy. Only the latest fire time is kept: a failed run is caught up after
+//! a restart as long as no later run of the flow succeeded. Next to the time
+//! it counts the executed runs of the flow, which is passed to every run as
+//! its occurrence.
+
+use async_nats::jetstream::kv;
+use chrono::{DateTime, Utc};
+
+/// Attempts to update a flow's entry while other replicas update it as well.
+const MAX_UPDATE_ATTEMPTS: usize = 5;
+
+#[derive(Clone)]
+pub struct FireLog {
+    kv: kv::Store,
+}
+
+impl FireLog {
+    pub fn new(kv: kv::Store) -> Self {
+        Self { kv }
+    }
+
+    /// The last fire time of the flow, if it ever fired.
+    pub async fn last_fired(&self, flow_id: i64) -> Option<DateTime<Utc>> {
+        let value = self
+            .get(&fired_key(flow_id), flow_id, "last fire time")
+            .await?;
+        let fired = parse_fired(&value);
+        if fired.is_none() {
+            log::warn!("Ignoring invalid last fire time of flow {}", flow_id);
+        }
+        fired
+    }
+
+    /// Marks every fire time of the flow up to `at` as handled. Fire times
+    /// never move backwards, as runs are recorded out of order.
+    pub async fn record_fired(&self, flow_id: i64, at: DateTime<Utc>) {
+        self.update(&fired_key(flow_id), flow_id, "fire time", |last| {
+            let at = last.and_then(parse_fired).map_or(at, |last| last.max(at));
+            at.to_rfc3339()
+        })
+        .await;
+    }
+
+    /// Counts a run that is about to execute and returns its occurrence, or
+    /// `None` if it couldn't be counted.
+    pub async fn count_run(&self, flow_id: i64) -> Option<u64> {
+        self.update(&runs_key(flow_id), flow_id, "run count", |last| {
+            let runs = last.and_then(|runs| runs.parse::<u64>().ok()).unwrap_or(0);
+            (runs + 1).to_string()
+        })
+        .await
+        .and_then(|runs| runs.parse().ok())
+    }
+
+    async fn get(&self, key: &str, flow_id: i64, what: &str) -> Option<String> {
+        match self.kv.entry(key).await {
+            Ok(entry) => entry.and_then(decode),
+            Err(err) => {
+                log::warn!("Could not read {} of flow {}: {}", what, flow_id, err);
+                None
+            }
+        }
+    }
+
+    /// Replaces an entry of the flow, retrying when it was changed in between.
+    async fn update(
+        &self,
+        key: &str,
+        flow_id: i64,
+        what: &str,
+        next: impl Fn(Option<&str>) -> String,
+    ) -> Option<String> {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let entry = match self.kv.entry(key).await {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::warn!("Could not read {} of flow {}: {}", what, flow_id, err);
+                    return None;
+                }
+            };
+            let revision = entry.as_ref().map(|entry| entry.revision);
+            let value = next(entry.and_then(decode).as_deref());
+
+            let conflict = match revision {
+                Some(revision) => match self.kv.update(key, value.clone().into(), revision).await {
+                    Ok(_) => return Some(value),
+                    Err(err) if err.kind() == kv::UpdateErrorKind::WrongLastRevision => true,
+                    Err(err) => {
+                        log::warn!("Could not record {} of flow {}: {}", what, flow_id, err);
+                        false
+                    }
+                },
+                None => match self.kv.create(key, value.clone().into()).await {
+                    Ok(_) => return Some(value),
+                    Err(err) if err.kind() == kv::CreateErrorKind::AlreadyExists => true,
+                    Err(err) => {
+                        log::warn!("Could not record {} of flow {}: {}", what, flow_id, err);
+                        false
+                    }
+                },
+            };
+            if !conflict {
+                return None;
+            }
+        }
+
+        log::warn!(
+            "Could not record {} of flow {}, it kept changing",
+            what,
+            flow_id
+        );
+        None
+    }
+}
+
+fn fired_key(flow_id: i64) -> String {
+    flow_id.to_string()
+}
+
+fn runs_key(flow_id: i64) -> String {
+    format!("{}.runs", flow_id)
+}
+
+/// Value of a live entry; deleted and purged entries count as missing.
+fn decode(entry: kv::Entry) -> Option<String> {
+    if entry.operation != kv::Operation::Put {
+        return None;
+    }
+    String::from_utf8(entry.value.to_vec()).ok()
+}
+
+fn parse_fired(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FireLog, fired_key, parse_fired, runs_key};
+    use async_nats::jetstream;
+    use base::nats::testing::NatsServer;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn fire_times_round_trip_through_their_encoding() {
+        let at = Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap();
+
+        assert_eq!(parse_fired(&at.to_rfc3339()), Some(at));
+        assert_eq!(parse_fired("42 2025-01-01T10:00:00+00:00"), None);
+    }
+
+    #[test]
+    fn fire_time_and_run_count_use_separate_keys() {
+        assert_eq!(fired_key(7), "7");
+        assert_eq!(runs_key(7), "7.runs");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a `nats-server` binary"]
+    async fn runs_are_counted_and_fire_times_only_move_forward() {
+        let server = NatsServer::start(&[]);
+        let client = async_nats::connect(&server.url).await.unwrap();
+        let kv = jetstream::new(client)
+            .create_key_value(jetstream::kv::Config {
+                bucket: "cron_state".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let fire_log = FireLog::new(kv);
+        let at = Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap();
+
+        assert_eq!(fire_log.last_fired(1).await, None);
+        fire_log.record_fired(1, at).await;
+        fire_log
+            .record_fired(1, at - chrono::Duration::minutes(1))
+            .await;
+        assert_eq!(fire_log.last_fired(1).await, Some(at));
+
+        assert_eq!(fire_log.count_run(1).await, Some(1));
+        assert_eq!(fire_log.count_run(1).await, Some(2));
+        assert_eq!(fire_log.count_run(2).await, Some(1));
+        assert_eq!(fire_log.last_fired(1).await, Some(at));
+    }
+}
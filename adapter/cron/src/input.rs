@@ -0,0 +1,121 @@
+//! Input value a scheduled flow is executed with.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use tucana::shared::{NumberValue, Struct, Value, number_value, value::Kind};
+
+/// Details of the run that are passed to the flow.
+pub struct RunInput<'a> {
+    /// Fire time of the run.
+    pub scheduled_at: DateTime<Utc>,
+    /// When the run was actually started, later than `scheduled_at` for
+    /// caught up or queued runs.
+    pub triggered_at: DateTime<Utc>,
+    pub timezone: Tz,
+    pub expression: &'a str,
+    /// Number of the run, counting from 1; 0 if it couldn't be counted.
+    pub occurrence: u64,
+    /// Whether the run was missed while the adapter was down.
+    pub catch_up: bool,
+}
+
+impl RunInput<'_> {
+    pub fn to_value(&self) -> Value {
+        let fields = HashMap::from([
+            (
+                String::from("scheduled_at"),
+                string_value(self.scheduled_at.to_rfc3339()),
+            ),
+            (
+                String::from("triggered_at"),
+                string_value(self.triggered_at.to_rfc3339()),
+            ),
+            (
+                String::from("timezone"),
+                string_value(self.timezone.name().to_string()),
+            ),
+            (
+                String::from("expression"),
+                string_value(self.expression.to_string()),
+            ),
+            (
+                String::from("occurrence"),
+                Value {
+                    kind: Some(Kind::NumberValue(NumberValue {
+                        number: Some(number_value::Number::Integer(
+                            i64::try_from(self.occurrence).unwrap_or(i64::MAX),
+                        )),
+                    })),
+                },
+            ),
+            (
+                String::from("catch_up"),
+                Value {
+                    kind: Some(Kind::BoolValue(self.catch_up)),
+                },
+            ),
+        ]);
+
+        Value {
+            kind: Some(Kind::StructValue(Struct { fields })),
+        }
+    }
+}
+
+fn string_value(value: String) -> Value {
+    Value {
+        kind: Some(Kind::StringValue(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunInput;
+    use chrono::{TimeZone, Utc};
+    use tucana::shared::{NumberValue, Struct, Value, number_value, value::Kind};
+
+    #[test]
+    fn input_describes_the_run() {
+        let input = RunInput {
+            scheduled_at: Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap(),
+            triggered_at: Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 2).unwrap(),
+            timezone: chrono_tz::Europe::Berlin,
+            expression: "0 0 11 * * *",
+            occurrence: 7,
+            catch_up: true,
+        };
+
+        let Value {
+            kind: Some(Kind::StructValue(Struct { fields })),
+        } = input.to_value()
+        else {
+            panic!("expected struct value");
+        };
+        let field = |name: &str| fields.get(name).and_then(|value| value.kind.clone());
+
+        assert_eq!(
+            field("scheduled_at"),
+            Some(Kind::StringValue("2025-01-01T10:00:00+00:00".into()))
+        );
+        assert_eq!(
+            field("triggered_at"),
+            Some(Kind::StringValue("2025-01-01T10:00:02+00:00".into()))
+        );
+        assert_eq!(
+            field("timezone"),
+            Some(Kind::StringValue("Europe/Berlin".into()))
+        );
+        assert_eq!(
+            field("expression"),
+            Some(Kind::StringValue("0 0 11 * * *".into()))
+        );
+        assert_eq!(
+            field("occurrence"),
+            Some(Kind::NumberValue(NumberValue {
+                number: Some(number_value::Number::Integer(7)),
+            }))
+        );
+        assert_eq!(field("catch_up"), Some(Kind::BoolValue(true)));
+    }
+}
@@ -1,159 +1,290 @@
+mod dispatcher;
+mod fire_lock;
+mod fire_log;
+mod input;
+mod schedule;
+mod scheduler;
+
 use async_trait::async_trait;
+use base::health::ComponentStatus;
 use base::runner::{ServerContext, ServerRunner};
-use base::store::FlowIdentifyResult;
-use base::traits::{IdentifiableFlow, LoadConfig, Server};
-use chrono::{DateTime, Datelike, Timelike, Utc};
-use cron::Schedule;
-use std::str::FromStr;
-use tucana::shared::ValidationFlow;
-use tucana::shared::value::Kind;
+use base::store::FlowChange;
+use base::traits::{LoadConfig, Server};
+use chrono::{DateTime, Utc};
+use code0_flow::flow_config::env_with_default;
+use code0_flow::flow_service::ModuleDefinitionAppendix;
+use futures_lite::StreamExt;
+use prometheus::{IntCounter, IntGauge, Registry};
+use std::sync::Arc;
+use std::time::Duration;
+use tucana::shared::ModuleDefinition;
+
+use crate::dispatcher::Dispatcher;
+use crate::fire_lock::FireLock;
+use crate::fire_log::FireLog;
+use crate::scheduler::Scheduler;
+
+/// Keys of the cron flows in the flow store.
+const FLOW_SUBJECT: &str = "CRON.>";
+const FLOW_KEY_PREFIX: &str = "CRON.";
+const FLOW_TYPE: &str = "CRON";
+const SCHEDULER_COMPONENT: &str = "scheduler";
+/// Delay before watching the flows again after the watch failed.
+const REWATCH_DELAY: Duration = Duration::from_secs(1);
 
 #[derive(Default)]
-struct Cron {}
+struct Cron {
+    metrics: Option<CronMetrics>,
+    fire_log: Option<FireLog>,
+    dispatcher: Option<Dispatcher>,
+}
+
+struct CronMetrics {
+    ticks: IntCounter,
+    matched_flows: IntCounter,
+    scheduled_flows: IntGauge,
+    invalid_flows: IntGauge,
+    skipped_runs: IntCounter,
+}
+
+impl CronMetrics {
+    fn register(registry: &Registry) -> anyhow::Result<Self> {
+        let ticks = IntCounter::new("cron_ticks_total", "Scheduler ticks of the cron adapter")?;
+        let matched_flows = IntCounter::new(
+            "cron_matched_flows_total",
+            "Flows that matched a scheduler tick",
+        )?;
+        let scheduled_flows =
+            IntGauge::new("cron_scheduled_flows", "Flows known to the scheduler")?;
+        let invalid_flows = IntGauge::new(
+            "cron_invalid_flows",
+            "Flows not scheduled because of an invalid schedule",
+        )?;
+        let skipped_runs = IntCounter::new(
+            "cron_skipped_runs_total",
+            "Runs skipped because they were missed or the previous run of the flow was still executing",
+        )?;
+
+        registry.register(Box::new(ticks.clone()))?;
+        registry.register(Box::new(matched_flows.clone()))?;
+        registry.register(Box::new(scheduled_flows.clone()))?;
+        registry.register(Box::new(invalid_flows.clone()))?;
+        registry.register(Box::new(skipped_runs.clone()))?;
+
+        Ok(Self {
+            ticks,
+            matched_flows,
+            scheduled_flows,
+            invalid_flows,
+            skipped_runs,
+        })
+    }
+}
 
 #[derive(Clone)]
-struct CronConfig {}
+struct CronConfig {
+    /// KV bucket holding the last fire time of every flow.
+    state_bucket: String,
+    /// Flow executions running at once, 0 for no limit.
+    max_concurrent_executions: usize,
+    /// KV bucket holding the claims of flow runs shared by all replicas.
+    lock_bucket: String,
+    /// How long claims are kept; longer than replicas can be apart in firing
+    /// the same run.
+    lock_ttl: Duration,
+    /// Name of this replica in its claims.
+    replica: String,
+}
 
 impl LoadConfig for CronConfig {
     fn load() -> Self {
-        Self {}
+        Self {
+            state_bucket: env_with_default("CRON_STATE_BUCKET", String::from("cron_state")),
+            max_concurrent_executions: env_with_default("CRON_MAX_CONCURRENT_EXECUTIONS", 32),
+            lock_bucket: env_with_default("CRON_LOCK_BUCKET", String::from("cron_locks")),
+            lock_ttl: Duration::from_secs(env_with_default("CRON_LOCK_TTL_SECONDS", 3600_u64)),
+            replica: env_with_default(
+                "CRON_REPLICA_NAME",
+                env_with_default("HOSTNAME", format!("cron-{}", std::process::id())),
+            ),
+        }
     }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     let server = Cron::default();
-    let runner = ServerRunner::new(server).await.unwrap();
+    let runner = ServerRunner::new(server).await?;
 
-    runner.serve(vec![]).await.unwrap();
+    // Cron flows are triggered by the adapter itself, so the definition has
+    // no endpoint to call.
+    let configs = vec![ModuleDefinitionAppendix {
+        module_identifier: String::from("draco-cron"),
+        definitions: vec![ModuleDefinition {
+            flow_type_identifier: vec![String::from(FLOW_TYPE)],
+            value: None,
+        }],
+    }];
+    runner.serve(configs).await
 }
 
-struct Time {
-    now: DateTime<Utc>,
+/// Publishes the scheduled and invalid flows to the metrics and diagnostics.
+fn report_flows(ctx: &ServerContext<CronConfig>, metrics: &CronMetrics, scheduler: &Scheduler) {
+    let invalid = scheduler.invalid_flows();
+    metrics.scheduled_flows.set(scheduler.len() as i64);
+    metrics.invalid_flows.set(invalid.len() as i64);
+    ctx.diagnostics.set(SCHEDULER_COMPONENT, invalid.clone());
 }
 
-fn extract_flow_setting_field(flow: &ValidationFlow, name: &str) -> Option<String> {
-    flow.settings
-        .iter()
-        .find(|s| s.flow_setting_id == name)
-        .and_then(|s| s.value.as_ref())
-        .and_then(|v| v.kind.as_ref())
-        .and_then(|k| match k {
-            Kind::StringValue(s) => Some(s.clone()),
-            _ => None,
-        })
+/// Runs a newly scheduled flow for the fire times it missed since it was
+/// fired last, e.g. while the adapter was down.
+async fn catch_up(
+    dispatcher: &Dispatcher,
+    fire_log: &FireLog,
+    scheduler: &Scheduler,
+    metrics: &CronMetrics,
+    key: &str,
+    flow_id: i64,
+) {
+    let last_fired = fire_log.last_fired(flow_id).await;
+    let missed = scheduler.missed_runs(key, last_fired, Utc::now());
+    if missed.runs.is_empty() && missed.dropped == 0 {
+        return;
+    }
+
+    log::info!(
+        "Catching up {} missed run(s) of flow with id: {}, dropping {} (last fired at {})",
+        missed.runs.len(),
+        flow_id,
+        missed.dropped,
+        last_fired.map_or_else(|| String::from("never"), |at| at.to_string())
+    );
+    metrics.skipped_runs.inc_by(missed.dropped as u64);
+    // Only the dropped runs are handled here, the dispatched ones are
+    // recorded once they executed.
+    if let Some(dropped_until) = missed.dropped_until {
+        fire_log.record_fired(flow_id, dropped_until).await;
+    }
+    for run in missed.runs {
+        dispatcher.dispatch(run);
+    }
 }
 
-impl IdentifiableFlow for Time {
-    fn identify(&self, flow: &tucana::shared::ValidationFlow) -> bool {
-        log::debug!("Trying to identify flow with flow id: {}", flow.flow_id);
-        let Some(minute) = extract_flow_setting_field(flow, "cronMinute") else {
-            return false;
-        };
-        let Some(hour) = extract_flow_setting_field(flow, "cronHour") else {
-            return false;
-        };
-        let Some(dom) = extract_flow_setting_field(flow, "cronDayOfMonth") else {
-            return false;
-        };
-        let Some(month) = extract_flow_setting_field(flow, "cronMonth") else {
-            return false;
-        };
-        let Some(dow) = extract_flow_setting_field(flow, "cronDayOfWeek") else {
-            return false;
-        };
-
-        log::debug!(
-            "flow with id: {} has the following cron configuration {} {} {} {} {}",
-            flow.flow_id,
-            minute,
-            hour,
-            dom,
-            month,
-            dow
-        );
-
-        let expression = format!("* {} {} {} {} {}", minute, hour, dom, month, dow);
-        let schedule = match Schedule::from_str(expression.as_str()) {
-            Ok(s) => s,
-            Err(e) => {
-                log::error!(
-                    "Could not create schedule from expression ({}). Reason: {:?}",
-                    expression,
-                    e
-                );
-                return false;
-            }
-        };
-        let next = match schedule.upcoming(Utc).next() {
-            Some(n) => n,
-            None => {
-                log::error!("Could not find any upcomming schedules");
-                return false;
-            }
-        };
-
-        let is_match = self.now.year() == next.year()
-            && self.now.month() == next.month()
-            && self.now.day() == next.day()
-            && self.now.hour() == next.hour()
-            && self.now.minute() == next.minute();
-
-        if is_match {
-            log::debug!("Flow with id: {} was a match", flow.flow_id);
-        } else {
-            log::debug!("Flow with id: {} was no match", flow.flow_id);
+/// Sleeps until `at`, or forever if nothing is scheduled.
+async fn sleep_until(at: Option<DateTime<Utc>>) {
+    match at {
+        Some(at) => {
+            let until = (at - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(until).await;
         }
-
-        is_match
+        None => std::future::pending().await,
     }
 }
 
 #[async_trait]
 impl Server<CronConfig> for Cron {
-    async fn init(&mut self, _ctx: &ServerContext<CronConfig>) -> anyhow::Result<()> {
+    async fn init(&mut self, ctx: &ServerContext<CronConfig>) -> anyhow::Result<()> {
+        let config = &ctx.server_config;
+        let metrics = CronMetrics::register(ctx.metrics.registry())?;
+        let fire_log = FireLog::new(ctx.adapter_store.key_value(&config.state_bucket).await?);
+        let locks = ctx
+            .adapter_store
+            .expiring_key_value(&config.lock_bucket, config.lock_ttl)
+            .await?;
+
+        self.dispatcher = Some(Dispatcher::new(
+            Arc::clone(&ctx.adapter_store),
+            FireLock::new(locks, config.replica.clone()),
+            fire_log.clone(),
+            config.max_concurrent_executions,
+            metrics.skipped_runs.clone(),
+        ));
+        self.fire_log = Some(fire_log);
+        self.metrics = Some(metrics);
         Ok(())
     }
 
     async fn run(&mut self, ctx: &ServerContext<CronConfig>) -> anyhow::Result<()> {
         log::info!("Starting Cron adapter");
-        let expression = "0 * * * * *";
-        let schedule = Schedule::from_str(expression)?;
-        let pattern = "CRON.*";
-
-        loop {
-            let now = Utc::now();
-            log::info!("Scheduled: {:?}", now);
-            if let Some(next) = schedule.upcoming(Utc).take(1).next() {
-                let until_next = next - now;
-                tokio::time::sleep(until_next.to_std()?).await;
-
-                let time = Time { now: next };
-                match ctx
-                    .adapter_store
-                    .get_possible_flow_match(pattern.to_string(), time)
-                    .await
-                {
-                    FlowIdentifyResult::None => {
-                        log::debug!("No Flow identified for this schedule");
-                    }
-                    FlowIdentifyResult::Single(flow) => {
-                        log::debug!("One Flow identified for this schedule");
-                        ctx.adapter_store
-                            .validate_and_execute_flow(flow, None)
-                            .await;
+        let metrics = self
+            .metrics
+            .as_ref()
+            .expect("metrics not initialized; init() must run first");
+        let fire_log = self
+            .fire_log
+            .as_ref()
+            .expect("fire log not initialized; init() must run first");
+        let dispatcher = self
+            .dispatcher
+            .as_ref()
+            .expect("dispatcher not initialized; init() must run first");
+        let mut scheduler = Scheduler::default();
+
+        while !ctx.shutdown.is_requested() {
+            let mut changes = match ctx.adapter_store.watch_flows(FLOW_SUBJECT).await {
+                Ok(changes) => changes,
+                Err(err) => {
+                    ctx.readiness.set(
+                        SCHEDULER_COMPONENT,
+                        ComponentStatus::Degraded(format!("cannot watch cron flows: {}", err)),
+                    );
+                    tokio::time::sleep(REWATCH_DELAY).await;
+                    continue;
+                }
+            };
+            // The watch starts with the current flows, which keep their
+            // schedule. Flows deleted in the meantime aren't part of it.
+            match ctx.adapter_store.flow_keys(FLOW_KEY_PREFIX).await {
+                Ok(keys) => scheduler.retain(|key| keys.contains(key)),
+                Err(err) => {
+                    ctx.readiness.set(
+                        SCHEDULER_COMPONENT,
+                        ComponentStatus::Degraded(format!("cannot list cron flows: {}", err)),
+                    );
+                    tokio::time::sleep(REWATCH_DELAY).await;
+                    continue;
+                }
+            }
+            report_flows(ctx, metrics, &scheduler);
+            ctx.readiness
+                .set(SCHEDULER_COMPONENT, ComponentStatus::Ready);
+
+            loop {
+                tokio::select! {
+                    _ = ctx.shutdown.requested() => {
+                        log::info!("Shutdown requested, no longer scheduling runs");
+                        return Ok(());
                     }
-                    FlowIdentifyResult::Multiple(flows) => {
-                        log::debug!("Multiple Flows identified for this schedule");
-                        for flow in flows {
-                            ctx.adapter_store
-                                .validate_and_execute_flow(flow, None)
-                                .await;
+                    change = changes.next() => match change {
+                        Some(FlowChange::Upsert { key, flow }) => {
+                            let is_new = !scheduler.contains(&key);
+                            let flow_id = flow.flow_id;
+                            scheduler.upsert(key.clone(), flow, Utc::now());
+                            if is_new {
+                                catch_up(dispatcher, fire_log, &scheduler, metrics, &key, flow_id).await;
+                            }
+                        }
+                        Some(FlowChange::Removed { key }) => scheduler.remove(&key),
+                        None => {
+                            log::warn!("Cron flow watch ended, watching again");
+                            break;
+                        }
+                    },
+                    _ = sleep_until(scheduler.next_due()) => {
+                        let due = scheduler.take_due(Utc::now());
+                        log::debug!("{} flow(s) due", due.runs.len());
+                        metrics.ticks.inc();
+                        metrics.matched_flows.inc_by(due.runs.len() as u64);
+                        metrics.skipped_runs.inc_by(due.dropped as u64);
+                        for run in due.runs {
+                            dispatcher.dispatch(run);
                         }
                     }
                 }
+                report_flows(ctx, metrics, &scheduler);
             }
         }
+        Ok(())
     }
 
     async fn shutdown(&mut self, _ctx: &ServerContext<CronConfig>) -> anyhow::Result<()> {
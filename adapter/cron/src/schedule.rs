@@ -0,0 +1,962 @@
+//! Schedules configured in the settings of a flow.
+//!
+//! A flow either sets a whole expression as `cronExpression` or the single
+//! fields `cronMinute`, `cronHour`, `cronDayOfMonth`, `cronMonth` and
+//! `cronDayOfWeek`, optionally with `cronSecond` (defaults to `0`) and
+//! `cronYear` (defaults to every year). `cronExpression` takes precedence.
+//!
+//! Instead of an expression a flow may set one of
+//!
+//! - `cronInterval` to run every `30s`, `15m`, `2h` or `1d` (plain numbers are
+//!   seconds). Runs are counted from `cronIntervalStart`, or from the Unix
+//!   epoch, so every replica agrees on the fire times.
+//! - `cronRunAt` to run once at the given time.
+//!
+//! Setting more than one kind of schedule, e.g. `cronInterval` next to
+//! `cronMinute`, makes the flow invalid.
+//!
+//! Times are RFC 3339 timestamps, or local times like `2025-06-01T09:00:00`
+//! in the zone of the flow.
+//!
+//! Expressions have the form `sec min hour day-of-month month day-of-week [year]`.
+//! A classic five field crontab expression fires at second `0`, and shorthands
+//! like `@daily` are accepted as well.
+//!
+//! # Time zones
+//!
+//! Expressions are evaluated in the IANA zone set as `cronTimezone` (e.g.
+//! `Europe/Berlin`), or in UTC. Around daylight saving time transitions:
+//!
+//! - Local times skipped when clocks go forward run once at the end of the
+//!   gap: with clocks jumping from 02:00 to 03:00, a flow at 02:30 runs at
+//!   03:00. Several fire times inside the same gap collapse into that run.
+//! - Local times repeated when clocks go back run once, at their first
+//!   occurrence. A flow firing every 15 minutes therefore pauses for the
+//!   repeated hour.
+//!
+//! # Misfires
+//!
+//! Runs missed while the adapter was down are handled by `cronMisfirePolicy`:
+//! `skip` (the default) drops them, `once` runs the flow once for all of them,
+//! at the most recent missed fire time, and `all` runs every missed fire time,
+//! but only the last `cronMisfireMaxRuns` (default 10, at least 1) of them.
+//!
+//! # Overlaps
+//!
+//! `cronOverlapPolicy` decides what happens when a flow is due while its
+//! previous run is still executing: `allow` (the default) runs it anyway,
+//! `skip` drops the new run and `queue` starts it once the previous run
+//! finished. Only one run waits at a time, further runs are skipped meanwhile.
+
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use tucana::shared::ValidationFlow;
+use tucana::shared::value::Kind;
+
+const EXPRESSION_SETTING: &str = "cronExpression";
+const SECOND_SETTING: &str = "cronSecond";
+const YEAR_SETTING: &str = "cronYear";
+const TIMEZONE_SETTING: &str = "cronTimezone";
+const MISFIRE_POLICY_SETTING: &str = "cronMisfirePolicy";
+const MISFIRE_MAX_RUNS_SETTING: &str = "cronMisfireMaxRuns";
+const DEFAULT_MISFIRE_MAX_RUNS: usize = 10;
+const OVERLAP_POLICY_SETTING: &str = "cronOverlapPolicy";
+const INTERVAL_SETTING: &str = "cronInterval";
+const INTERVAL_START_SETTING: &str = "cronIntervalStart";
+const RUN_AT_SETTING: &str = "cronRunAt";
+
+/// Longest DST gap searched for its end. Real gaps are an hour or less.
+const MAX_GAP_MINUTES: i64 = 24 * 60;
+
+/// Dropped runs of cron expressions counted at most, as they can only be
+/// counted one by one.
+const MAX_COUNTED_RUNS: usize = 10_000;
+
+/// Required settings of the field form, in expression order.
+const CRON_SETTINGS: [&str; 5] = [
+    "cronMinute",
+    "cronHour",
+    "cronDayOfMonth",
+    "cronMonth",
+    "cronDayOfWeek",
+];
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    MissingSetting(&'static str),
+    InvalidExpression {
+        expression: String,
+        reason: String,
+    },
+    InvalidTimezone(String),
+    InvalidSetting {
+        setting: &'static str,
+        value: String,
+    },
+    /// More than one kind of schedule is set.
+    ConflictingSettings(Vec<&'static str>),
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingSetting(setting) => write!(f, "missing setting {}", setting),
+            Self::InvalidExpression { expression, reason } => {
+                write!(f, "invalid cron expression '{}': {}", expression, reason)
+            }
+            Self::InvalidTimezone(timezone) => write!(f, "unknown time zone '{}'", timezone),
+            Self::InvalidSetting { setting, value } => {
+                write!(f, "invalid value '{}' for setting {}", value, setting)
+            }
+            Self::ConflictingSettings(settings) => {
+                write!(f, "only one of {} may be set", settings.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// What happens to runs missed while the adapter was down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisfirePolicy {
+    Skip,
+    RunOnce,
+    RunAll { max_runs: usize },
+}
+
+/// What happens when a flow is due while its previous run still executes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    Allow,
+    Skip,
+    Queue,
+}
+
+/// Fire times missed by a flow, split by its misfire policy.
+#[derive(Debug, PartialEq)]
+pub struct MissedRuns<T = DateTime<Utc>> {
+    /// Runs to catch up, oldest first.
+    pub runs: Vec<T>,
+    /// Number of fire times dropped by the policy. For cron expressions, the
+    /// ones before the latest dropped run are counted up to `MAX_COUNTED_RUNS`.
+    pub dropped: usize,
+    /// The latest dropped fire time. Dropped fire times precede all runs.
+    pub dropped_until: Option<DateTime<Utc>>,
+}
+
+impl<T> Default for MissedRuns<T> {
+    fn default() -> Self {
+        Self {
+            runs: Vec::new(),
+            dropped: 0,
+            dropped_until: None,
+        }
+    }
+}
+
+impl<T> MissedRuns<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> MissedRuns<U> {
+        MissedRuns {
+            runs: self.runs.into_iter().map(f).collect(),
+            dropped: self.dropped,
+            dropped_until: self.dropped_until,
+        }
+    }
+}
+
+/// When a flow fires.
+enum Trigger {
+    Cron(Box<Schedule>),
+    Interval {
+        every: TimeDelta,
+        start: DateTime<Utc>,
+    },
+    Once(DateTime<Utc>),
+}
+
+/// A trigger evaluated in a time zone.
+pub struct FlowSchedule {
+    trigger: Trigger,
+    timezone: Tz,
+    misfire: MisfirePolicy,
+    pub overlap: OverlapPolicy,
+}
+
+impl FlowSchedule {
+    /// The cron expression, with seconds and optional years, or `@every`
+    /// and `@at` for intervals and one-shot runs.
+    pub fn expression(&self) -> String {
+        match &self.trigger {
+            Trigger::Cron(cron) => cron.source().to_string(),
+            Trigger::Interval { every, start } if *start == DateTime::<Utc>::UNIX_EPOCH => {
+                format!("@every {}s", every.num_seconds())
+            }
+            Trigger::Interval { every, start } => {
+                format!(
+                    "@every {}s from {}",
+                    every.num_seconds(),
+                    start.to_rfc3339()
+                )
+            }
+            Trigger::Once(at) => format!("@at {}", at.to_rfc3339()),
+        }
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    /// Fire times missed between `last_fired` and `now`, split by the misfire
+    /// policy into the ones to run and the dropped ones.
+    ///
+    /// Without a last fire time only one-shot runs are missed; for the other
+    /// triggers it's unknown since when.
+    pub fn missed_runs(&self, last_fired: Option<DateTime<Utc>>, now: DateTime<Utc>) -> MissedRuns {
+        let limit = match self.misfire {
+            MisfirePolicy::Skip => 0,
+            MisfirePolicy::RunOnce => 1,
+            MisfirePolicy::RunAll { max_runs } => max_runs,
+        };
+        let mut missed = MissedRuns::default();
+        let mut at = match (last_fired, &self.trigger) {
+            (Some(last_fired), _) => last_fired,
+            (None, Trigger::Once(_)) => DateTime::<Utc>::MIN_UTC,
+            (None, _) => return missed,
+        };
+
+        // Only the most recent runs are kept. Older ones are dropped without
+        // walking through them, which would take long for frequent schedules
+        // after a long downtime.
+        if let Some(dropped_until) = self.nth_latest(at, now, limit) {
+            missed.dropped = self.count_runs(at, dropped_until);
+            missed.dropped_until = Some(dropped_until);
+            at = dropped_until;
+        }
+        let mut runs = VecDeque::with_capacity(limit + 1);
+        while let Some(next) = self.next_after(at)
+            && next <= now
+        {
+            runs.push_back(next);
+            if runs.len() > limit {
+                missed.dropped_until = runs.pop_front();
+                missed.dropped += 1;
+            }
+            at = next;
+        }
+        missed.runs = runs.into();
+
+        if let MisfirePolicy::RunAll { max_runs } = self.misfire
+            && let Some(dropped_until) = missed.dropped_until
+        {
+            log::warn!(
+                "More than {} runs were missed, dropping {} older run(s) up to {}",
+                max_runs,
+                missed.dropped,
+                dropped_until
+            );
+        }
+        missed
+    }
+
+    /// The first fire time strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match &self.trigger {
+            Trigger::Cron(cron) => self.next_cron_after(cron, after),
+            Trigger::Interval { every, start } => {
+                if after < *start {
+                    return Some(*start);
+                }
+                let every = every.num_milliseconds();
+                let periods = (after - *start).num_milliseconds() / every + 1;
+                start.checked_add_signed(TimeDelta::try_milliseconds(periods.checked_mul(every)?)?)
+            }
+            Trigger::Once(at) => Some(*at).filter(|at| *at > after),
+        }
+    }
+
+    /// A fire time in `(after, until]` followed by at least `n` more up to
+    /// `until`, or `None` if there are not that many.
+    fn nth_latest(
+        &self,
+        after: DateTime<Utc>,
+        until: DateTime<Utc>,
+        n: usize,
+    ) -> Option<DateTime<Utc>> {
+        match &self.trigger {
+            Trigger::Cron(cron) => {
+                // Walks back over the local candidates like `next_cron_after`
+                // walks forward. Times collapsed into the end of a DST gap
+                // show up several times in a row.
+                let local =
+                    until.with_timezone(&self.timezone).naive_local() + TimeDelta::seconds(1);
+                let mut found = 0;
+                let mut earliest: Option<DateTime<Utc>> = None;
+                for candidate in cron.after(&Utc.from_utc_datetime(&local)).rev() {
+                    let Some(at) = self.resolve(candidate.naive_utc()) else {
+                        continue;
+                    };
+                    if at <= after {
+                        return None;
+                    }
+                    if at > until || earliest == Some(at) {
+                        continue;
+                    }
+                    found += 1;
+                    earliest = Some(earliest.map_or(at, |earliest| earliest.min(at)));
+                    if found > n {
+                        return earliest;
+                    }
+                }
+                None
+            }
+            Trigger::Interval { every, start } => {
+                let periods =
+                    interval_runs(*every, *start, until).checked_sub(i64::try_from(n).ok()? + 1)?;
+                let at = start.checked_add_signed(TimeDelta::try_milliseconds(
+                    periods.checked_mul(every.num_milliseconds())?,
+                )?)?;
+                Some(at).filter(|at| *at > after)
+            }
+            Trigger::Once(_) => None,
+        }
+    }
+
+    /// Number of fire times in `(after, until]`, counted up to
+    /// `MAX_COUNTED_RUNS` for cron expressions.
+    fn count_runs(&self, after: DateTime<Utc>, until: DateTime<Utc>) -> usize {
+        if let Trigger::Interval { every, start } = &self.trigger {
+            let count = interval_runs(*every, *start, until) - interval_runs(*every, *start, after);
+            return usize::try_from(count).unwrap_or_default();
+        }
+
+        let mut count = 0;
+        let mut at = after;
+        while count < MAX_COUNTED_RUNS
+            && let Some(next) = self.next_after(at)
+            && next <= until
+        {
+            count += 1;
+            at = next;
+        }
+        count
+    }
+
+    fn next_cron_after(&self, cron: &Schedule, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        // Candidates are enumerated as local wall clock times. Treating them
+        // as UTC, which has no transitions, yields every one of them in order;
+        // they are mapped into the zone afterwards.
+        let local = after.with_timezone(&self.timezone).naive_local();
+        cron.after(&Utc.from_utc_datetime(&local))
+            .find_map(|candidate| self.resolve(candidate.naive_utc()).filter(|at| *at > after))
+    }
+
+    /// Maps a local time to an instant following the DST policy of this module.
+    fn resolve(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self.timezone.from_local_datetime(&local) {
+            LocalResult::Single(at) => Some(at.with_timezone(&Utc)),
+            LocalResult::Ambiguous(first, _) => Some(first.with_timezone(&Utc)),
+            LocalResult::None => self.gap_end(local),
+        }
+    }
+
+    /// The first valid local minute after a time skipped by a DST gap.
+    fn gap_end(&self, skipped: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let minute = skipped.with_second(0)?.with_nanosecond(0)?;
+        (1..=MAX_GAP_MINUTES).find_map(|offset| {
+            self.timezone
+                .from_local_datetime(&(minute + TimeDelta::minutes(offset)))
+                .earliest()
+                .map(|at| at.with_timezone(&Utc))
+        })
+    }
+}
+
+/// Number of fire times of an interval up to and including `until`.
+fn interval_runs(every: TimeDelta, start: DateTime<Utc>, until: DateTime<Utc>) -> i64 {
+    if until < start {
+        return 0;
+    }
+    (until - start).num_milliseconds() / every.num_milliseconds() + 1
+}
+
+/// Builds the schedule of a flow from its `cron*` settings.
+pub fn flow_schedule(flow: &ValidationFlow) -> Result<FlowSchedule, ScheduleError> {
+    let timezone = match extract_flow_setting_field(flow, TIMEZONE_SETTING) {
+        Some(name) => {
+            Tz::from_str(name.trim()).map_err(|_| ScheduleError::InvalidTimezone(name))?
+        }
+        None => Tz::UTC,
+    };
+
+    Ok(FlowSchedule {
+        trigger: trigger(flow, timezone)?,
+        timezone,
+        misfire: misfire_policy(flow)?,
+        overlap: overlap_policy(flow)?,
+    })
+}
+
+fn trigger(flow: &ValidationFlow, timezone: Tz) -> Result<Trigger, ScheduleError> {
+    let expression = extract_flow_setting_field(flow, EXPRESSION_SETTING);
+    let interval = extract_flow_setting_field(flow, INTERVAL_SETTING);
+    let run_at = extract_flow_setting_field(flow, RUN_AT_SETTING);
+
+    let mut set: Vec<_> = [
+        (EXPRESSION_SETTING, expression.is_some()),
+        (INTERVAL_SETTING, interval.is_some()),
+        (RUN_AT_SETTING, run_at.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(setting, is_set)| is_set.then_some(setting))
+    .collect();
+    // The field settings only make up an expression, which the other kinds
+    // of schedule would silently ignore.
+    if set.iter().any(|setting| *setting != EXPRESSION_SETTING) {
+        set.extend(
+            [SECOND_SETTING]
+                .into_iter()
+                .chain(CRON_SETTINGS)
+                .chain([YEAR_SETTING])
+                .filter(|setting| extract_flow_setting_field(flow, setting).is_some()),
+        );
+    }
+    if set.len() > 1 {
+        return Err(ScheduleError::ConflictingSettings(set));
+    }
+
+    let invalid = |setting, value: String| ScheduleError::InvalidSetting { setting, value };
+
+    if let Some(value) = run_at {
+        let at = parse_time(&value, timezone).ok_or_else(|| invalid(RUN_AT_SETTING, value))?;
+        return Ok(Trigger::Once(at));
+    }
+
+    if let Some(value) = interval {
+        let every = parse_interval(&value).ok_or_else(|| invalid(INTERVAL_SETTING, value))?;
+        let start = match extract_flow_setting_field(flow, INTERVAL_START_SETTING) {
+            Some(value) => parse_time(&value, timezone)
+                .ok_or_else(|| invalid(INTERVAL_START_SETTING, value))?,
+            None => DateTime::<Utc>::UNIX_EPOCH,
+        };
+        return Ok(Trigger::Interval { every, start });
+    }
+
+    let expression = match expression {
+        Some(expression) => normalize_expression(&expression),
+        None => fields_expression(flow)?,
+    };
+    log::debug!(
+        "flow with id: {} has the cron expression {}",
+        flow.flow_id,
+        expression
+    );
+
+    Schedule::from_str(&expression)
+        .map(|schedule| Trigger::Cron(Box::new(schedule)))
+        .map_err(|err| ScheduleError::InvalidExpression {
+            expression,
+            reason: err.to_string(),
+        })
+}
+
+/// Parses `<n>s`, `<n>m`, `<n>h`, `<n>d` or plain seconds.
+fn parse_interval(value: &str) -> Option<TimeDelta> {
+    let value = value.trim();
+    let (amount, unit_seconds) = match value.char_indices().last()? {
+        (at, 's') => (&value[..at], 1),
+        (at, 'm') => (&value[..at], 60),
+        (at, 'h') => (&value[..at], 60 * 60),
+        (at, 'd') => (&value[..at], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    let amount: i64 = amount.trim().parse().ok().filter(|amount| *amount > 0)?;
+    TimeDelta::try_seconds(amount.checked_mul(unit_seconds)?)
+}
+
+/// Parses an RFC 3339 timestamp, or a local time in `timezone`.
+fn parse_time(value: &str, timezone: Tz) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc));
+    }
+    let local = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .ok()?;
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+fn overlap_policy(flow: &ValidationFlow) -> Result<OverlapPolicy, ScheduleError> {
+    match extract_flow_setting_field(flow, OVERLAP_POLICY_SETTING) {
+        None => Ok(OverlapPolicy::Allow),
+        Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "allow" => Ok(OverlapPolicy::Allow),
+            "skip" => Ok(OverlapPolicy::Skip),
+            "queue" => Ok(OverlapPolicy::Queue),
+            _ => Err(ScheduleError::InvalidSetting {
+                setting: OVERLAP_POLICY_SETTING,
+                value,
+            }),
+        },
+    }
+}
+
+fn misfire_policy(flow: &ValidationFlow) -> Result<MisfirePolicy, ScheduleError> {
+    let invalid = |setting, value: String| ScheduleError::InvalidSetting { setting, value };
+
+    let max_runs = match extract_flow_setting_field(flow, MISFIRE_MAX_RUNS_SETTING) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|max_runs| *max_runs > 0)
+            .ok_or_else(|| invalid(MISFIRE_MAX_RUNS_SETTING, value))?,
+        None => DEFAULT_MISFIRE_MAX_RUNS,
+    };
+
+    match extract_flow_setting_field(flow, MISFIRE_POLICY_SETTING) {
+        None => Ok(MisfirePolicy::Skip),
+        Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "skip" => Ok(MisfirePolicy::Skip),
+            "once" => Ok(MisfirePolicy::RunOnce),
+            "all" => Ok(MisfirePolicy::RunAll { max_runs }),
+            _ => Err(invalid(MISFIRE_POLICY_SETTING, value)),
+        },
+    }
+}
+
+/// Expands a five field crontab expression with a leading seconds field.
+fn normalize_expression(expression: &str) -> String {
+    let expression = expression.trim();
+    if !expression.starts_with('@') && expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    }
+}
+
+fn fields_expression(flow: &ValidationFlow) -> Result<String, ScheduleError> {
+    let mut fields = Vec::with_capacity(CRON_SETTINGS.len() + 2);
+    fields.push(
+        extract_flow_setting_field(flow, SECOND_SETTING).unwrap_or_else(|| String::from("0")),
+    );
+    for setting in CRON_SETTINGS {
+        let value = extract_flow_setting_field(flow, setting)
+            .ok_or(ScheduleError::MissingSetting(setting))?;
+        fields.push(value);
+    }
+    if let Some(year) = extract_flow_setting_field(flow, YEAR_SETTING) {
+        fields.push(year);
+    }
+
+    Ok(fields.join(" "))
+}
+
+/// Value of a string setting, empty values count as unset.
+fn extract_flow_setting_field(flow: &ValidationFlow, name: &str) -> Option<String> {
+    flow.settings
+        .iter()
+        .find(|s| s.flow_setting_id == name)
+        .and_then(|s| s.value.as_ref())
+        .and_then(|v| v.kind.as_ref())
+        .and_then(|k| match k {
+            Kind::StringValue(s) => Some(s.clone()),
+            _ => None,
+        })
+        .filter(|s| !s.trim().is_empty())
+}
+
+/// A flow with the given string settings.
+#[cfg(test)]
+pub fn flow_with_settings(flow_id: i64, settings: &[(&str, &str)]) -> ValidationFlow {
+    use tucana::shared::{FlowSetting, Value};
+
+    ValidationFlow {
+        flow_id,
+        settings: settings
+            .iter()
+            .map(|(id, value)| FlowSetting {
+                database_id: None,
+                flow_setting_id: id.to_string(),
+                value: Some(Value {
+                    kind: Some(Kind::StringValue(value.to_string())),
+                }),
+                cast: None,
+            })
+            .collect(),
+        ..ValidationFlow::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::{
+        MAX_COUNTED_RUNS, MisfirePolicy, OverlapPolicy, ScheduleError, flow_schedule,
+        flow_with_settings,
+    };
+
+    const MINUTELY: [(&str, &str); 5] = [
+        ("cronMinute", "*"),
+        ("cronHour", "*"),
+        ("cronDayOfMonth", "*"),
+        ("cronMonth", "*"),
+        ("cronDayOfWeek", "*"),
+    ];
+
+    fn source(settings: &[(&str, &str)]) -> String {
+        flow_schedule(&flow_with_settings(1, settings))
+            .unwrap()
+            .expression()
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    /// Fire times after `start` of an expression in `timezone`.
+    fn fire_times(expression: &str, timezone: &str, start: &str, count: usize) -> Vec<String> {
+        let schedule = flow_schedule(&flow_with_settings(
+            1,
+            &[("cronExpression", expression), ("cronTimezone", timezone)],
+        ))
+        .unwrap();
+
+        let mut at = utc(start);
+        (0..count)
+            .map(|_| {
+                at = schedule.next_after(at).unwrap();
+                at.to_rfc3339()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fields_default_to_second_zero_and_every_year() {
+        assert_eq!(source(&MINUTELY), "0 * * * * *");
+    }
+
+    #[test]
+    fn second_and_year_settings_are_used() {
+        let mut settings = MINUTELY.to_vec();
+        settings.push(("cronSecond", "*/15"));
+        settings.push(("cronYear", "2030"));
+
+        assert_eq!(source(&settings), "*/15 * * * * * 2030");
+    }
+
+    #[test]
+    fn expression_setting_takes_precedence() {
+        let mut settings = MINUTELY.to_vec();
+        settings.push(("cronExpression", "30 0 9 * * Mon-Fri"));
+
+        assert_eq!(source(&settings), "30 0 9 * * Mon-Fri");
+    }
+
+    #[test]
+    fn five_field_expressions_fire_at_second_zero() {
+        assert_eq!(source(&[("cronExpression", " 0 9 * * * ")]), "0 0 9 * * *");
+        assert_eq!(source(&[("cronExpression", "@daily")]), "@daily");
+    }
+
+    #[test]
+    fn local_times_follow_the_offset_of_the_zone() {
+        // 09:00 in Berlin is 08:00 UTC in winter and 07:00 UTC in summer.
+        assert_eq!(
+            fire_times("0 0 9 * * *", "Europe/Berlin", "2025-03-29T00:00:00Z", 2),
+            ["2025-03-29T08:00:00+00:00", "2025-03-30T07:00:00+00:00"]
+        );
+    }
+
+    #[test]
+    fn skipped_local_times_run_at_the_end_of_the_gap() {
+        // On 2025-03-30 Berlin clocks jump from 02:00 CET to 03:00 CEST (01:00 UTC).
+        assert_eq!(
+            fire_times("0 30 2 * * *", "Europe/Berlin", "2025-03-29T12:00:00Z", 2),
+            ["2025-03-30T01:00:00+00:00", "2025-03-31T00:30:00+00:00"]
+        );
+        assert_eq!(
+            fire_times("0 */30 * * * *", "Europe/Berlin", "2025-03-30T00:15:00Z", 3),
+            [
+                "2025-03-30T00:30:00+00:00",
+                "2025-03-30T01:00:00+00:00",
+                "2025-03-30T01:30:00+00:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_local_times_run_once() {
+        // On 2025-10-26 Berlin clocks go back from 03:00 CEST to 02:00 CET (01:00 UTC).
+        assert_eq!(
+            fire_times("0 30 2 * * *", "Europe/Berlin", "2025-10-25T12:00:00Z", 2),
+            ["2025-10-26T00:30:00+00:00", "2025-10-27T01:30:00+00:00"]
+        );
+        assert_eq!(
+            fire_times("0 */30 * * * *", "Europe/Berlin", "2025-10-26T00:15:00Z", 3),
+            [
+                "2025-10-26T00:30:00+00:00",
+                "2025-10-26T02:00:00+00:00",
+                "2025-10-26T02:30:00+00:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn policies_default_to_skip_misfires_and_allow_overlaps() {
+        let schedule = flow_schedule(&flow_with_settings(1, &MINUTELY)).unwrap();
+
+        assert_eq!(schedule.misfire, MisfirePolicy::Skip);
+        assert_eq!(schedule.overlap, OverlapPolicy::Allow);
+        let missed = schedule.missed_runs(
+            Some(utc("2025-01-01T10:00:00Z")),
+            utc("2025-01-01T10:05:30Z"),
+        );
+        assert!(missed.runs.is_empty());
+        assert_eq!(missed.dropped, 5);
+        assert_eq!(missed.dropped_until, Some(utc("2025-01-01T10:05:00Z")));
+    }
+
+    #[test]
+    fn misfire_policies_limit_the_missed_runs() {
+        let missed = |policy: &str, max_runs: &str| {
+            let mut settings = MINUTELY.to_vec();
+            settings.push(("cronMisfirePolicy", policy));
+            settings.push(("cronMisfireMaxRuns", max_runs));
+            flow_schedule(&flow_with_settings(1, &settings))
+                .unwrap()
+                .missed_runs(
+                    Some(utc("2025-01-01T10:00:00Z")),
+                    utc("2025-01-01T10:05:30Z"),
+                )
+        };
+
+        let once = missed("once", "10");
+        assert_eq!(once.runs, [utc("2025-01-01T10:05:00Z")]);
+        assert_eq!(once.dropped, 4);
+        assert_eq!(once.dropped_until, Some(utc("2025-01-01T10:04:00Z")));
+
+        let all = missed("all", "10");
+        assert_eq!(all.runs.len(), 5);
+        assert_eq!(all.dropped_until, None);
+
+        let limited = missed("all", "2");
+        assert_eq!(
+            limited.runs,
+            [utc("2025-01-01T10:04:00Z"), utc("2025-01-01T10:05:00Z")]
+        );
+        assert_eq!(limited.dropped, 3);
+        assert_eq!(limited.dropped_until, Some(utc("2025-01-01T10:03:00Z")));
+    }
+
+    #[test]
+    fn long_downtimes_are_not_walked_through() {
+        let missed = |settings: &[(&str, &str)]| {
+            flow_schedule(&flow_with_settings(1, settings))
+                .unwrap()
+                .missed_runs(
+                    Some(utc("2025-01-01T00:00:00Z")),
+                    utc("2025-01-31T00:00:00Z"),
+                )
+        };
+        let last = utc("2025-01-31T00:00:00Z");
+
+        let skipped = missed(&[("cronExpression", "* * * * * *")]);
+        assert!(skipped.runs.is_empty());
+        assert_eq!(skipped.dropped, MAX_COUNTED_RUNS);
+        assert_eq!(skipped.dropped_until, Some(last));
+
+        let limited = missed(&[
+            ("cronExpression", "* * * * * *"),
+            ("cronMisfirePolicy", "all"),
+            ("cronMisfireMaxRuns", "2"),
+        ]);
+        assert_eq!(limited.runs, [last - TimeDelta::seconds(1), last]);
+        assert_eq!(limited.dropped_until, Some(last - TimeDelta::seconds(2)));
+
+        let interval = missed(&[("cronInterval", "1s"), ("cronMisfirePolicy", "once")]);
+        assert_eq!(interval.runs, [last]);
+        assert_eq!(interval.dropped, 30 * 24 * 60 * 60 - 1);
+        assert_eq!(interval.dropped_until, Some(last - TimeDelta::seconds(1)));
+    }
+
+    #[test]
+    fn overlap_policy_is_read_from_the_settings() {
+        let overlap = |policy: &str| {
+            let mut settings = MINUTELY.to_vec();
+            settings.push(("cronOverlapPolicy", policy));
+            flow_schedule(&flow_with_settings(1, &settings)).map(|schedule| schedule.overlap)
+        };
+
+        assert_eq!(overlap("skip").unwrap(), OverlapPolicy::Skip);
+        assert_eq!(overlap(" Queue ").unwrap(), OverlapPolicy::Queue);
+        assert!(matches!(
+            overlap("parallel"),
+            Err(ScheduleError::InvalidSetting {
+                setting: "cronOverlapPolicy",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_and_invalid_settings_are_reported() {
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(1, &MINUTELY[..4])),
+            Err(ScheduleError::MissingSetting("cronDayOfWeek"))
+        ));
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(
+                1,
+                &[("cronExpression", "61 * * * * *")]
+            )),
+            Err(ScheduleError::InvalidExpression { .. })
+        ));
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(
+                1,
+                &[
+                    ("cronExpression", "@daily"),
+                    ("cronTimezone", "Mars/Olympus")
+                ]
+            )),
+            Err(ScheduleError::InvalidTimezone(_))
+        ));
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(
+                1,
+                &[("cronExpression", "@daily"), ("cronMisfirePolicy", "later")]
+            )),
+            Err(ScheduleError::InvalidSetting {
+                setting: "cronMisfirePolicy",
+                ..
+            })
+        ));
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(
+                1,
+                &[
+                    ("cronExpression", "@daily"),
+                    ("cronMisfirePolicy", "all"),
+                    ("cronMisfireMaxRuns", "0")
+                ]
+            )),
+            Err(ScheduleError::InvalidSetting {
+                setting: "cronMisfireMaxRuns",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn intervals_count_from_their_start() {
+        let every = |settings: &[(&str, &str)]| flow_schedule(&flow_with_settings(1, settings));
+
+        let schedule = every(&[("cronInterval", "15m")]).unwrap();
+        assert_eq!(schedule.expression(), "@every 900s");
+        assert_eq!(
+            schedule.next_after(utc("2025-01-01T10:07:00Z")),
+            Some(utc("2025-01-01T10:15:00Z"))
+        );
+        assert_eq!(
+            schedule.next_after(utc("2025-01-01T10:15:00Z")),
+            Some(utc("2025-01-01T10:30:00Z"))
+        );
+
+        let schedule = every(&[
+            ("cronInterval", "90"),
+            ("cronIntervalStart", "2025-01-01T11:00:00"),
+            ("cronTimezone", "Europe/Berlin"),
+        ])
+        .unwrap();
+        assert_eq!(
+            schedule.next_after(utc("2025-01-01T09:00:00Z")),
+            Some(utc("2025-01-01T10:00:00Z"))
+        );
+        assert_eq!(
+            schedule.next_after(utc("2025-01-01T10:02:00Z")),
+            Some(utc("2025-01-01T10:03:00Z"))
+        );
+    }
+
+    #[test]
+    fn one_shot_runs_fire_once_and_are_caught_up_if_never_fired() {
+        let schedule = flow_schedule(&flow_with_settings(
+            1,
+            &[
+                ("cronRunAt", "2025-06-01T09:00:00+02:00"),
+                ("cronMisfirePolicy", "once"),
+            ],
+        ))
+        .unwrap();
+
+        assert_eq!(schedule.expression(), "@at 2025-06-01T07:00:00+00:00");
+        assert_eq!(
+            schedule.next_after(utc("2025-06-01T06:00:00Z")),
+            Some(utc("2025-06-01T07:00:00Z"))
+        );
+        assert_eq!(schedule.next_after(utc("2025-06-01T07:00:00Z")), None);
+
+        let now = utc("2025-06-02T00:00:00Z");
+        assert_eq!(
+            schedule.missed_runs(None, now).runs,
+            [utc("2025-06-01T07:00:00Z")]
+        );
+        assert_eq!(
+            schedule.missed_runs(Some(utc("2025-06-01T07:00:00Z")), now),
+            Default::default()
+        );
+    }
+
+    #[test]
+    fn only_one_kind_of_schedule_may_be_set() {
+        match flow_schedule(&flow_with_settings(
+            1,
+            &[("cronExpression", "@daily"), ("cronInterval", "1h")],
+        )) {
+            Err(ScheduleError::ConflictingSettings(settings)) => {
+                assert_eq!(settings, ["cronExpression", "cronInterval"]);
+            }
+            _ => panic!("expected conflicting settings"),
+        }
+        match flow_schedule(&flow_with_settings(
+            1,
+            &[("cronRunAt", "2025-06-01T09:00:00Z"), ("cronSecond", "30")],
+        )) {
+            Err(ScheduleError::ConflictingSettings(settings)) => {
+                assert_eq!(settings, ["cronRunAt", "cronSecond"]);
+            }
+            _ => panic!("expected conflicting settings"),
+        }
+        let mut settings = MINUTELY.to_vec();
+        settings.push(("cronInterval", "1h"));
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(1, &settings)),
+            Err(ScheduleError::ConflictingSettings(_))
+        ));
+
+        for interval in ["0s", "-5m", "1.5h", "5 minutes"] {
+            assert!(matches!(
+                flow_schedule(&flow_with_settings(1, &[("cronInterval", interval)])),
+                Err(ScheduleError::InvalidSetting {
+                    setting: "cronInterval",
+                    ..
+                })
+            ));
+        }
+        assert!(matches!(
+            flow_schedule(&flow_with_settings(1, &[("cronRunAt", "tomorrow")])),
+            Err(ScheduleError::InvalidSetting {
+                setting: "cronRunAt",
+                ..
+            })
+        ));
+    }
+}
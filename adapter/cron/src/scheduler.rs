@@ -0,0 +1,409 @@
+//! Priority queue of the next fire time of every cron flow.
+//!
+//! The queue is fed from the flow watch of the store. Changing or removing a
+//! flow doesn't touch the queue; its old entries are recognized as stale by
+//! their generation and dropped once they reach the head.
+//!
+//! Schedules are validated once when a flow is loaded. Flows with an invalid
+//! schedule are kept aside with their error until they are changed or removed.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use tucana::shared::ValidationFlow;
+
+use crate::schedule::{FlowSchedule, MissedRuns, OverlapPolicy, flow_schedule};
+
+#[derive(Default)]
+pub struct Scheduler {
+    flows: HashMap<String, ScheduledFlow>,
+    queue: BinaryHeap<Reverse<Due>>,
+    next_generation: u64,
+    /// Error of every flow with an invalid schedule, by key.
+    invalid: BTreeMap<String, String>,
+}
+
+struct ScheduledFlow {
+    flow: ValidationFlow,
+    schedule: FlowSchedule,
+    generation: u64,
+}
+
+/// A run of a flow at one of its fire times.
+pub struct DueRun {
+    pub flow: ValidationFlow,
+    pub at: DateTime<Utc>,
+    pub overlap: OverlapPolicy,
+    pub timezone: Tz,
+    pub expression: String,
+    /// Whether the run was missed, e.g. while the adapter was down.
+    pub catch_up: bool,
+}
+
+impl ScheduledFlow {
+    fn run_at(&self, at: DateTime<Utc>, catch_up: bool) -> DueRun {
+        DueRun {
+            flow: self.flow.clone(),
+            at,
+            overlap: self.schedule.overlap,
+            timezone: self.schedule.timezone(),
+            expression: self.schedule.expression(),
+            catch_up,
+        }
+    }
+}
+
+/// Runs taken from the queue by [`Scheduler::take_due`].
+#[derive(Default)]
+pub struct DueRuns {
+    pub runs: Vec<DueRun>,
+    /// Fire times that passed while the scheduler was late and that were
+    /// dropped by the misfire policy of their flow.
+    pub dropped: usize,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Due {
+    at: DateTime<Utc>,
+    generation: u64,
+    key: String,
+}
+
+impl Scheduler {
+    /// Schedules a new flow or replaces the schedule of a known one.
+    ///
+    /// Flows without a valid schedule are removed and reported as invalid.
+    /// An unchanged flow keeps its schedule, e.g. when it's watched again.
+    pub fn upsert(&mut self, key: String, flow: ValidationFlow, now: DateTime<Utc>) {
+        if self
+            .flows
+            .get(&key)
+            .is_some_and(|scheduled| scheduled.flow == flow)
+        {
+            return;
+        }
+        let schedule = match flow_schedule(&flow) {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                let error = format!("flow with id: {}: {}", flow.flow_id, err);
+                if self.invalid.get(&key) != Some(&error) {
+                    log::error!("Not scheduling {} (key {})", error, key);
+                }
+                self.remove(&key);
+                self.invalid.insert(key, error);
+                return;
+            }
+        };
+        self.invalid.remove(&key);
+
+        let generation = self.next_generation;
+        self.next_generation += 1;
+
+        match schedule.next_after(now) {
+            Some(at) => {
+                log::debug!("Flow with id: {} is due at {}", flow.flow_id, at);
+                self.queue.push(Reverse(Due {
+                    at,
+                    generation,
+                    key: key.clone(),
+                }));
+            }
+            None => log::warn!("Flow with id: {} has no upcoming schedule", flow.flow_id),
+        }
+
+        self.flows.insert(
+            key,
+            ScheduledFlow {
+                flow,
+                schedule,
+                generation,
+            },
+        );
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.flows.contains_key(key)
+    }
+
+    /// Runs of a flow missed since `last_fired`, if it ever fired, split by
+    /// the flow's misfire policy into the runs to catch up and dropped ones.
+    pub fn missed_runs(
+        &self,
+        key: &str,
+        last_fired: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> MissedRuns<DueRun> {
+        let Some(scheduled) = self.flows.get(key) else {
+            return MissedRuns::default();
+        };
+        scheduled
+            .schedule
+            .missed_runs(last_fired, now)
+            .map(|at| scheduled.run_at(at, true))
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.invalid.remove(key);
+        if let Some(scheduled) = self.flows.remove(key) {
+            log::debug!("Unscheduled flow with id: {}", scheduled.flow.flow_id);
+        }
+    }
+
+    /// Removes every flow, valid or not, whose key isn't kept.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        let removed: Vec<String> = self
+            .flows
+            .keys()
+            .chain(self.invalid.keys())
+            .filter(|key| !keep(key))
+            .cloned()
+            .collect();
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+
+    /// Number of scheduled flows.
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Error of every flow with an invalid schedule, by key.
+    pub fn invalid_flows(&self) -> &BTreeMap<String, String> {
+        &self.invalid
+    }
+
+    /// Earliest fire time of all flows.
+    pub fn next_due(&mut self) -> Option<DateTime<Utc>> {
+        while let Some(Reverse(due)) = self.queue.peek() {
+            if self.is_current(due) {
+                return Some(due.at);
+            }
+            self.queue.pop();
+        }
+        None
+    }
+
+    /// Removes the runs due at `now` and schedules the next fire time of
+    /// their flows.
+    ///
+    /// Fire times that passed while the scheduler was woken up late, e.g. on
+    /// a busy runtime, are handled by the misfire policy of their flow.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> DueRuns {
+        let mut taken = DueRuns::default();
+
+        while let Some(Reverse(due)) = self.queue.peek() {
+            if due.at > now {
+                break;
+            }
+            let Some(Reverse(due)) = self.queue.pop() else {
+                break;
+            };
+            if !self.is_current(&due) {
+                continue;
+            }
+
+            let scheduled = &self.flows[&due.key];
+            taken.runs.push(scheduled.run_at(due.at, false));
+
+            let late = scheduled.schedule.missed_runs(Some(due.at), now);
+            if late.dropped > 0 {
+                log::warn!(
+                    "Dropping {} run(s) of flow with id: {} that passed since {}",
+                    late.dropped,
+                    scheduled.flow.flow_id,
+                    due.at
+                );
+            }
+            taken.dropped += late.dropped;
+            taken
+                .runs
+                .extend(late.runs.into_iter().map(|at| scheduled.run_at(at, true)));
+
+            if let Some(at) = scheduled.schedule.next_after(now) {
+                self.queue.push(Reverse(Due { at, ..due }));
+            }
+        }
+
+        taken
+    }
+
+    fn is_current(&self, due: &Due) -> bool {
+        self.flows
+            .get(&due.key)
+            .is_some_and(|scheduled| scheduled.generation == due.generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+    use tucana::shared::ValidationFlow;
+
+    use super::{DueRun, Scheduler};
+    use crate::schedule::flow_with_settings;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 1, 1, hour, minute, second)
+            .unwrap()
+    }
+
+    fn cron_flow(flow_id: i64, minute: &str, hour: &str) -> ValidationFlow {
+        flow_with_settings(
+            flow_id,
+            &[
+                ("cronMinute", minute),
+                ("cronHour", hour),
+                ("cronDayOfMonth", "*"),
+                ("cronMonth", "*"),
+                ("cronDayOfWeek", "*"),
+            ],
+        )
+    }
+
+    fn flow_ids(runs: Vec<DueRun>) -> Vec<i64> {
+        runs.into_iter().map(|run| run.flow.flow_id).collect()
+    }
+
+    #[test]
+    fn fires_flows_in_order_and_reschedules_them() {
+        let mut scheduler = Scheduler::default();
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "*/5", "*"), at(10, 0, 0));
+        scheduler.upsert("CRON.2".into(), cron_flow(2, "2", "*"), at(10, 0, 0));
+
+        assert_eq!(scheduler.next_due(), Some(at(10, 2, 0)));
+        assert!(scheduler.take_due(at(10, 1, 59)).runs.is_empty());
+        assert_eq!(flow_ids(scheduler.take_due(at(10, 2, 0)).runs), vec![2]);
+
+        assert_eq!(scheduler.next_due(), Some(at(10, 5, 0)));
+        assert_eq!(flow_ids(scheduler.take_due(at(10, 5, 0)).runs), vec![1]);
+        assert_eq!(scheduler.next_due(), Some(at(10, 10, 0)));
+    }
+
+    #[test]
+    fn fires_at_the_requested_second() {
+        let mut scheduler = Scheduler::default();
+        let flow = flow_with_settings(1, &[("cronExpression", "10,40 * * * * *")]);
+        scheduler.upsert("CRON.1".into(), flow, at(10, 0, 0));
+
+        assert_eq!(scheduler.next_due(), Some(at(10, 0, 10)));
+        assert!(scheduler.take_due(at(10, 0, 9)).runs.is_empty());
+        assert_eq!(flow_ids(scheduler.take_due(at(10, 0, 10)).runs), vec![1]);
+        assert_eq!(scheduler.next_due(), Some(at(10, 0, 40)));
+    }
+
+    #[test]
+    fn runs_passed_while_late_follow_the_misfire_policy() {
+        let mut scheduler = Scheduler::default();
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "*", "*"), at(10, 0, 0));
+        let flow = flow_with_settings(
+            2,
+            &[
+                ("cronExpression", "* * * * *"),
+                ("cronMisfirePolicy", "once"),
+            ],
+        );
+        scheduler.upsert("CRON.2".into(), flow, at(10, 0, 0));
+
+        let due = scheduler.take_due(at(10, 3, 30));
+        let runs: Vec<_> = due
+            .runs
+            .iter()
+            .map(|run| (run.flow.flow_id, run.at, run.catch_up))
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                (1, at(10, 1, 0), false),
+                (2, at(10, 1, 0), false),
+                (2, at(10, 3, 0), true),
+            ]
+        );
+        assert_eq!(due.dropped, 3);
+        assert_eq!(scheduler.next_due(), Some(at(10, 4, 0)));
+    }
+
+    #[test]
+    fn rewatched_flows_keep_their_schedule_and_missing_ones_are_removed() {
+        let mut scheduler = Scheduler::default();
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "5", "*"), at(10, 0, 0));
+        scheduler.upsert("CRON.2".into(), cron_flow(2, "61", "*"), at(10, 0, 0));
+        scheduler.upsert("CRON.3".into(), cron_flow(3, "5", "*"), at(10, 0, 0));
+
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "5", "*"), at(10, 5, 30));
+        assert_eq!(flow_ids(scheduler.take_due(at(10, 5, 30)).runs), vec![1, 3]);
+
+        scheduler.retain(|key| key == "CRON.1");
+        assert_eq!(scheduler.len(), 1);
+        assert!(scheduler.invalid_flows().is_empty());
+    }
+
+    #[test]
+    fn runs_tell_whether_they_are_caught_up() {
+        let mut scheduler = Scheduler::default();
+        let flow = flow_with_settings(
+            1,
+            &[
+                ("cronExpression", "*/5 * * * *"),
+                ("cronTimezone", "Europe/Berlin"),
+                ("cronMisfirePolicy", "once"),
+            ],
+        );
+        scheduler.upsert("CRON.1".into(), flow, at(10, 0, 0));
+
+        let due = scheduler.take_due(at(10, 5, 0)).runs;
+        assert_eq!(due.len(), 1);
+        assert!(!due[0].catch_up);
+        assert_eq!(due[0].expression, "0 */5 * * * *");
+        assert_eq!(due[0].timezone, chrono_tz::Europe::Berlin);
+
+        let missed = scheduler
+            .missed_runs("CRON.1", Some(at(9, 0, 0)), at(10, 2, 0))
+            .runs;
+        assert_eq!(missed.len(), 1);
+        assert!(missed[0].catch_up);
+        assert_eq!(missed[0].at, at(10, 0, 0));
+    }
+
+    #[test]
+    fn changed_and_removed_flows_drop_their_old_schedule() {
+        let mut scheduler = Scheduler::default();
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "5", "*"), at(10, 0, 0));
+        scheduler.upsert("CRON.2".into(), cron_flow(2, "5", "*"), at(10, 0, 0));
+
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "30", "*"), at(10, 1, 0));
+        scheduler.remove("CRON.2");
+
+        assert_eq!(scheduler.next_due(), Some(at(10, 30, 0)));
+        assert!(scheduler.take_due(at(10, 5, 0)).runs.is_empty());
+        assert_eq!(scheduler.len(), 1);
+    }
+
+    #[test]
+    fn invalid_schedule_unschedules_the_flow() {
+        let mut scheduler = Scheduler::default();
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "5", "*"), at(10, 0, 0));
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "61", "*"), at(10, 1, 0));
+
+        assert_eq!(scheduler.len(), 0);
+        assert_eq!(scheduler.next_due(), None);
+    }
+
+    #[test]
+    fn invalid_flows_are_kept_until_fixed_or_removed() {
+        let mut scheduler = Scheduler::default();
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "61", "*"), at(10, 0, 0));
+        scheduler.upsert("CRON.2".into(), cron_flow(2, "*", "25"), at(10, 0, 0));
+
+        let invalid: Vec<_> = scheduler.invalid_flows().keys().cloned().collect();
+        assert_eq!(invalid, ["CRON.1", "CRON.2"]);
+        assert!(scheduler.invalid_flows()["CRON.1"].starts_with("flow with id: 1: "));
+
+        scheduler.upsert("CRON.1".into(), cron_flow(1, "5", "*"), at(10, 0, 0));
+        scheduler.remove("CRON.2");
+
+        assert!(scheduler.invalid_flows().is_empty());
+        assert_eq!(scheduler.len(), 1);
+    }
+}
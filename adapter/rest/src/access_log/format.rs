@@ -0,0 +1,294 @@
+use hyper::{HeaderMap, header::HeaderValue};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::AccessLogEntry;
+
+const REDACTED: &str = "REDACTED";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    Off,
+    /// NCSA common log format.
+    Common,
+    /// Common log format followed by referer and user agent.
+    Combined,
+    /// One JSON object per request including duration, request id and flow id.
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "none" | "" => Ok(Self::Off),
+            "common" | "clf" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown access log format '{}'", other)),
+        }
+    }
+}
+
+/// Query parameters and headers whose values never reach the access log.
+pub(super) struct Redaction {
+    query_params: Vec<String>,
+    headers: Vec<String>,
+}
+
+impl Redaction {
+    pub(super) fn new(query_params: &[String], headers: &[String]) -> Self {
+        Self {
+            query_params: query_params
+                .iter()
+                .map(|name| name.to_ascii_lowercase())
+                .collect(),
+            headers: headers
+                .iter()
+                .map(|name| name.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Replaces the values of redacted parameters and keeps everything else
+    /// byte for byte.
+    fn query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| {
+                let (key, value) = match pair.split_once('=') {
+                    Some((key, value)) => (key, Some(value)),
+                    None => (pair, None),
+                };
+                let name = percent_encoding::percent_decode_str(&key.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .to_ascii_lowercase();
+
+                match value {
+                    Some(_) if self.query_params.contains(&name) => {
+                        format!("{}={}", key, REDACTED)
+                    }
+                    _ => pair.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    fn header<'a>(&self, headers: &'a HeaderMap<HeaderValue>, name: &str) -> Option<&'a str> {
+        let value = headers.get(name)?;
+        if self.headers.iter().any(|redacted| redacted == name) {
+            return Some(REDACTED);
+        }
+        value.to_str().ok()
+    }
+}
+
+pub(super) fn render(
+    format: AccessLogFormat,
+    entry: &AccessLogEntry<'_>,
+    redaction: &Redaction,
+    include_headers: bool,
+) -> String {
+    let target = match entry.query {
+        Some(query) => format!("{}?{}", entry.path, redaction.query(query)),
+        None => entry.path.to_string(),
+    };
+
+    match format {
+        AccessLogFormat::Off => String::new(),
+        AccessLogFormat::Common => common(entry, &target),
+        AccessLogFormat::Combined => {
+            let mut line = common(entry, &target);
+            let _ = write!(
+                line,
+                " \"{}\" \"{}\"",
+                escape(redaction.header(entry.headers, "referer").unwrap_or("-")),
+                escape(redaction.header(entry.headers, "user-agent").unwrap_or("-"))
+            );
+            line
+        }
+        AccessLogFormat::Json => json(entry, &target, redaction, include_headers),
+    }
+}
+
+fn common(entry: &AccessLogEntry<'_>, target: &str) -> String {
+    format!(
+        "{} - - [{}] \"{} {} {:?}\" {} {}",
+        entry.client_ip,
+        entry.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+        entry.method,
+        escape(target),
+        entry.version,
+        entry.status.as_u16(),
+        entry
+            .bytes
+            .map_or_else(|| String::from("-"), |bytes| bytes.to_string())
+    )
+}
+
+fn json(
+    entry: &AccessLogEntry<'_>,
+    target: &str,
+    redaction: &Redaction,
+    include_headers: bool,
+) -> String {
+    let mut line = serde_json::json!({
+        "timestamp": entry.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "client_ip": entry.client_ip.to_string(),
+        "request_id": entry.request_id,
+        "method": entry.method.as_str(),
+        "path": target,
+        "protocol": format!("{:?}", entry.version),
+        "status": entry.status.as_u16(),
+        "bytes": entry.bytes,
+        "duration_ms": entry.duration.as_micros() as f64 / 1000.0,
+        "flow_id": entry.flow_id,
+        "referer": redaction.header(entry.headers, "referer"),
+        "user_agent": redaction.header(entry.headers, "user-agent"),
+    });
+
+    if include_headers {
+        let headers = entry
+            .headers
+            .keys()
+            .filter_map(|name| {
+                redaction
+                    .header(entry.headers, name.as_str())
+                    .map(|value| (name.to_string(), serde_json::Value::from(value)))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        line["headers"] = serde_json::Value::Object(headers);
+    }
+
+    line.to_string()
+}
+
+/// Escapes quotes, backslashes and control characters inside quoted fields.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use hyper::{HeaderMap, Method, StatusCode, Version, header::HeaderValue};
+    use std::time::Duration;
+
+    use super::{AccessLogFormat, Redaction, render};
+    use crate::access_log::AccessLogEntry;
+
+    fn redaction() -> Redaction {
+        Redaction::new(
+            &[String::from("token"), String::from("api_key")],
+            &[String::from("authorization"), String::from("user-agent")],
+        )
+    }
+
+    fn headers() -> HeaderMap<HeaderValue> {
+        let mut headers = HeaderMap::new();
+        headers.insert("referer", HeaderValue::from_static("https://example.com/"));
+        headers.insert("user-agent", HeaderValue::from_static("curl/8.0"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("accept", HeaderValue::from_static("application/json"));
+        headers
+    }
+
+    fn entry<'a>(method: &'a Method, headers: &'a HeaderMap<HeaderValue>) -> AccessLogEntry<'a> {
+        AccessLogEntry {
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 7, 13, 55, 36).unwrap(),
+            client_ip: "203.0.113.7".parse().unwrap(),
+            request_id: "req-1",
+            method,
+            path: "/project/users",
+            query: Some("page=2&Token=abc&api%5Fkey=xyz&flag"),
+            version: Version::HTTP_11,
+            headers,
+            status: StatusCode::CREATED,
+            bytes: Some(42),
+            duration: Duration::from_millis(15),
+            flow_id: Some(7),
+        }
+    }
+
+    #[test]
+    fn common_format_redacts_query_params() {
+        let headers = headers();
+        let line = render(
+            AccessLogFormat::Common,
+            &entry(&Method::POST, &headers),
+            &redaction(),
+            false,
+        );
+
+        assert_eq!(
+            line,
+            "203.0.113.7 - - [07/Mar/2025:13:55:36 +0000] \"POST /project/users?page=2&Token=REDACTED&api%5Fkey=REDACTED&flag HTTP/1.1\" 201 42"
+        );
+    }
+
+    #[test]
+    fn combined_format_appends_redacted_referer_and_user_agent() {
+        let headers = headers();
+        let line = render(
+            AccessLogFormat::Combined,
+            &entry(&Method::GET, &headers),
+            &redaction(),
+            false,
+        );
+
+        assert!(line.ends_with("201 42 \"https://example.com/\" \"REDACTED\""));
+    }
+
+    #[test]
+    fn json_format_contains_request_details_and_redacted_headers() {
+        let headers = headers();
+        let line = render(
+            AccessLogFormat::Json,
+            &entry(&Method::GET, &headers),
+            &redaction(),
+            true,
+        );
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(json["timestamp"], "2025-03-07T13:55:36.000Z");
+        assert_eq!(json["client_ip"], "203.0.113.7");
+        assert_eq!(json["request_id"], "req-1");
+        assert_eq!(json["status"], 201);
+        assert_eq!(json["bytes"], 42);
+        assert_eq!(json["duration_ms"], 15.0);
+        assert_eq!(json["flow_id"], 7);
+        assert_eq!(
+            json["path"],
+            "/project/users?page=2&Token=REDACTED&api%5Fkey=REDACTED&flag"
+        );
+        assert_eq!(json["headers"]["authorization"], "REDACTED");
+        assert_eq!(json["headers"]["accept"], "application/json");
+    }
+
+    #[test]
+    fn quoted_fields_are_escaped() {
+        let mut headers = HeaderMap::new();
+        headers.insert("referer", HeaderValue::from_static("a\"b\\c"));
+        let line = render(
+            AccessLogFormat::Combined,
+            &entry(&Method::GET, &headers),
+            &Redaction::new(&[], &[]),
+            false,
+        );
+
+        assert!(line.ends_with("\"a\\\"b\\\\c\" \"-\""));
+    }
+}
@@ -0,0 +1,137 @@
+//! One line per served request.
+//!
+//! The line is written after the response is built and contains method, path,
+//! status, body size, duration, client IP and the matched flow. Sensitive
+//! query parameters and headers are redacted before anything is formatted.
+
+mod format;
+mod sink;
+
+use chrono::{DateTime, Utc};
+use code0_flow::flow_config::env_with_default;
+use hyper::{HeaderMap, Method, StatusCode, Version, header::HeaderValue};
+use prometheus::IntCounter;
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub use format::AccessLogFormat;
+use format::Redaction;
+use sink::Sink;
+
+const DEFAULT_REDACTED_QUERY_PARAMS: &str =
+    "access_token,api_key,apikey,client_secret,code,password,secret,token";
+const DEFAULT_REDACTED_HEADERS: &str =
+    "authorization,cookie,proxy-authorization,set-cookie,x-api-key";
+
+#[derive(Clone, Debug)]
+pub struct AccessLogConfig {
+    /// Line format. `off` disables the access log.
+    pub format: AccessLogFormat,
+    /// File the log is written to. Lines go to stdout when unset.
+    pub path: Option<String>,
+    /// Size at which the log file is rotated.
+    pub max_bytes: u64,
+    /// Number of rotated files kept next to the active one.
+    pub max_files: usize,
+    /// Query parameters whose values are replaced, matched case-insensitively.
+    pub redact_query_params: Vec<String>,
+    /// Headers whose values are replaced.
+    pub redact_headers: Vec<String>,
+    /// Adds all request headers to JSON lines.
+    pub include_headers: bool,
+}
+
+impl AccessLogConfig {
+    pub fn from_env() -> Self {
+        Self {
+            format: env_with_default("HTTP_ACCESS_LOG_FORMAT", AccessLogFormat::Off),
+            path: Some(env_with_default("HTTP_ACCESS_LOG_PATH", String::new()))
+                .filter(|path| !path.is_empty()),
+            max_bytes: env_with_default("HTTP_ACCESS_LOG_MAX_BYTES", 100 * 1024 * 1024),
+            max_files: env_with_default("HTTP_ACCESS_LOG_MAX_FILES", 5),
+            redact_query_params: list(&env_with_default(
+                "HTTP_ACCESS_LOG_REDACT_QUERY_PARAMS",
+                String::from(DEFAULT_REDACTED_QUERY_PARAMS),
+            )),
+            redact_headers: list(&env_with_default(
+                "HTTP_ACCESS_LOG_REDACT_HEADERS",
+                String::from(DEFAULT_REDACTED_HEADERS),
+            )),
+            include_headers: env_with_default("HTTP_ACCESS_LOG_INCLUDE_HEADERS", false),
+        }
+    }
+}
+
+fn list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|entry| entry.trim().to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Everything known about a request once its response is ready.
+pub struct AccessLogEntry<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub client_ip: IpAddr,
+    pub request_id: &'a str,
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub version: Version,
+    pub headers: &'a HeaderMap<HeaderValue>,
+    pub status: StatusCode,
+    /// Response body size, if known.
+    pub bytes: Option<u64>,
+    pub duration: Duration,
+    pub flow_id: Option<i64>,
+}
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    redaction: Redaction,
+    include_headers: bool,
+    sink: Sink,
+}
+
+impl AccessLog {
+    /// Creates the access log, or `None` if it is turned off.
+    ///
+    /// Lines dropped because the writer can't keep up are counted in `dropped`.
+    pub fn from_config(
+        config: &AccessLogConfig,
+        dropped: IntCounter,
+    ) -> anyhow::Result<Option<Self>> {
+        if config.format == AccessLogFormat::Off {
+            return Ok(None);
+        }
+
+        let sink = match &config.path {
+            Some(path) => Sink::file(path, config.max_bytes, config.max_files, dropped)
+                .map_err(|e| anyhow::anyhow!("failed to open access log file '{}': {}", path, e))?,
+            None => Sink::stdout(dropped),
+        };
+
+        log::info!(
+            "Access log enabled: format={:?} sink={}",
+            config.format,
+            config.path.as_deref().unwrap_or("stdout")
+        );
+
+        Ok(Some(Self {
+            format: config.format,
+            redaction: Redaction::new(&config.redact_query_params, &config.redact_headers),
+            include_headers: config.include_headers,
+            sink,
+        }))
+    }
+
+    pub fn record(&self, entry: &AccessLogEntry<'_>) {
+        let line = format::render(self.format, entry, &self.redaction, self.include_headers);
+        self.sink.write(line);
+    }
+
+    /// Blocks until every recorded line is written, or `timeout` passed.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        self.sink.flush(timeout)
+    }
+}
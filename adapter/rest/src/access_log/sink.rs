@@ -0,0 +1,378 @@
+use prometheus::IntCounter;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+/// Lines waiting to be written before new ones are dropped.
+const QUEUE_CAPACITY: usize = 8192;
+/// Minimum time between two warnings about dropped lines.
+const DROP_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+/// How often a flush retries queueing while the queue is full.
+const FLUSH_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+enum Message {
+    Line(String),
+    /// Acknowledged once every line queued before it is written.
+    Flush(mpsc::Sender<()>),
+}
+
+/// Writes lines on a dedicated thread so request handling never blocks on I/O.
+pub(super) struct Sink {
+    sender: SyncSender<Message>,
+    drops: Drops,
+}
+
+impl Sink {
+    pub(super) fn stdout(dropped: IntCounter) -> Self {
+        Self::spawn(io::stdout(), dropped)
+    }
+
+    pub(super) fn file(
+        path: &str,
+        max_bytes: u64,
+        max_files: usize,
+        dropped: IntCounter,
+    ) -> io::Result<Self> {
+        Ok(Self::spawn(
+            RotatingFile::open(PathBuf::from(path), max_bytes, max_files)?,
+            dropped,
+        ))
+    }
+
+    fn spawn<W: Write + Send + 'static>(mut writer: W, dropped: IntCounter) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Message>(QUEUE_CAPACITY);
+
+        std::thread::Builder::new()
+            .name(String::from("access-log"))
+            .spawn(move || {
+                for message in receiver {
+                    match message {
+                        Message::Line(line) => {
+                            let result = writer
+                                .write_all(line.as_bytes())
+                                .and_then(|_| writer.flush());
+                            if let Err(err) = result {
+                                log::error!("Failed to write access log: {}", err);
+                            }
+                        }
+                        Message::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn access log thread");
+
+        Self {
+            sender,
+            drops: Drops::new(dropped),
+        }
+    }
+
+    pub(super) fn write(&self, mut line: String) {
+        // Written with a single call so rotation never splits a line.
+        line.push('\n');
+        match self.sender.try_send(Message::Line(line)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.drops.record("queue is full"),
+            Err(TrySendError::Disconnected(_)) => self.drops.record("writer stopped"),
+        }
+    }
+
+    /// Blocks until every queued line is written, or `timeout` passed.
+    ///
+    /// The flush itself is queued behind the lines, so a full queue counts
+    /// against the timeout as well.
+    pub(super) fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (done, flushed) = mpsc::channel();
+        let mut message = Message::Flush(done);
+        loop {
+            match self.sender.try_send(message) {
+                Ok(()) => break,
+                Err(TrySendError::Full(returned)) if Instant::now() < deadline => {
+                    message = returned;
+                    std::thread::sleep(FLUSH_RETRY_INTERVAL);
+                }
+                Err(_) => return false,
+            }
+        }
+
+        flushed
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .is_ok()
+    }
+}
+
+/// Counts dropped lines and warns about them at a limited rate, as lines are
+/// dropped exactly when the adapter is overloaded.
+struct Drops {
+    counter: IntCounter,
+    started: Instant,
+    /// Milliseconds after `started` before which no warning is logged.
+    next_warning: AtomicU64,
+    unreported: AtomicU64,
+}
+
+impl Drops {
+    fn new(counter: IntCounter) -> Self {
+        Self {
+            counter,
+            started: Instant::now(),
+            next_warning: AtomicU64::new(0),
+            unreported: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, reason: &str) {
+        self.counter.inc();
+        self.unreported.fetch_add(1, Ordering::Relaxed);
+
+        let now = self.started.elapsed().as_millis() as u64;
+        let next_warning = self.next_warning.load(Ordering::Relaxed);
+        if now < next_warning
+            || self
+                .next_warning
+                .compare_exchange(
+                    next_warning,
+                    now + DROP_WARNING_INTERVAL.as_millis() as u64,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+
+        let unreported = self.unreported.swap(0, Ordering::Relaxed);
+        log::warn!(
+            "Access log {}, dropped {} line(s) since the last warning",
+            reason,
+            unreported
+        );
+    }
+}
+
+/// A log file that is renamed to `<path>.1` once it grows past `max_bytes`.
+/// Older files shift up to `<path>.<max_files>`; anything beyond is deleted.
+///
+/// Rotation only happens between lines, so every line ends up whole in one file.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    /// Whether the last write ended a line.
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            size,
+            at_line_start: true,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, self.max_files));
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_bytes > 0
+            && self.at_line_start
+            && self.size > 0
+            && self.size + buf.len() as u64 > self.max_bytes
+        {
+            self.rotate()?;
+        }
+
+        // Written completely, so a partial write can't leave the rest of the
+        // line to a call that rotates first.
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        if let Some(&last) = buf.last() {
+            self.at_line_start = last == b'\n';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use prometheus::IntCounter;
+
+    use super::{Drops, QUEUE_CAPACITY, RotatingFile, Sink, rotated_path};
+
+    /// Writer blocking every write until its sender is dropped.
+    struct Blocked(mpsc::Receiver<()>);
+
+    impl Write for Blocked {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let _ = self.0.recv();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("draco-access-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("access.log")
+    }
+
+    #[test]
+    fn file_is_rotated_once_it_exceeds_max_bytes() {
+        let path = temp_log("rotate");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "second\n"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn lines_written_in_parts_are_not_split_by_rotation() {
+        let path = temp_log("split");
+        let mut file = RotatingFile::open(path.clone(), 10, 1).unwrap();
+
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second").unwrap();
+        file.write_all(b" half\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "second half\n"
+        );
+    }
+
+    #[test]
+    fn flush_gives_up_on_a_full_queue_within_the_timeout() {
+        let (release, blocked) = mpsc::channel();
+        let dropped = IntCounter::new("dropped", "dropped lines").unwrap();
+        let sink = Sink::spawn(Blocked(blocked), dropped);
+
+        // One line is taken by the blocked writer, the rest fill the queue.
+        for index in 0..=QUEUE_CAPACITY {
+            sink.write(index.to_string());
+        }
+
+        let started = Instant::now();
+        assert!(!sink.flush(Duration::from_millis(100)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(release);
+    }
+
+    #[test]
+    fn flush_waits_for_queued_lines() {
+        let path = temp_log("flush");
+        let dropped = IntCounter::new("dropped", "dropped lines").unwrap();
+        let sink = Sink::file(path.to_str().unwrap(), 0, 0, dropped.clone()).unwrap();
+
+        for line in ["first", "second"] {
+            sink.write(line.to_string());
+        }
+
+        assert!(sink.flush(Duration::from_secs(5)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+        assert_eq!(dropped.get(), 0);
+    }
+
+    #[test]
+    fn dropped_lines_are_counted_but_warned_about_once_per_interval() {
+        let counter = IntCounter::new("dropped", "dropped lines").unwrap();
+        let drops = Drops::new(counter.clone());
+
+        drops.record("queue is full");
+        drops.record("queue is full");
+        drops.record("queue is full");
+
+        assert_eq!(counter.get(), 3);
+        // The first drop was reported right away, the later ones wait for
+        // the next warning.
+        assert_eq!(
+            drops.unreported.load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[test]
+    fn reopened_file_continues_with_existing_size() {
+        let path = temp_log("reopen");
+        fs::write(&path, "existing\n").unwrap();
+
+        let mut file = RotatingFile::open(path.clone(), 12, 1).unwrap();
+        file.write_all(b"next\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "next\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "existing\n"
+        );
+    }
+}
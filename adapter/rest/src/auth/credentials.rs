@@ -1,7 +1,9 @@
+use base::secret::constant_time_eq;
 use base64::Engine;
-use tucana::shared::{Struct, Value, value::Kind};
+use tucana::shared::{ListValue, Struct, Value, value::Kind};
 
 use super::jwt::validate_hs256_jwt;
+use super::secret::{PasswordHashFormat, verify_password};
 use super::types::AuthenticationType;
 
 pub(super) fn matches_authorization(
@@ -21,24 +23,113 @@ pub(super) fn matches_authorization(
             let Some(expected_token) = value_as_string(auth_value) else {
                 return false;
             };
+            let Some(token) = authorization.trim().strip_prefix("Bearer ") else {
+                return false;
+            };
 
-            authorization.trim() == format!("Bearer {}", expected_token.trim())
+            constant_time_eq(token.trim().as_bytes(), expected_token.trim().as_bytes())
         }
         AuthenticationType::Basic => {
-            let Some(credentials) = basic_credentials(auth_value) else {
+            let credentials = basic_credentials(auth_value);
+            if credentials.is_empty() {
+                return false;
+            }
+
+            let Some((username, password)) = decode_basic_authorization(authorization) else {
                 return false;
             };
 
-            let expected_encoded =
-                base64::engine::general_purpose::STANDARD.encode(credentials.as_bytes());
-            authorization.trim() == format!("Basic {}", expected_encoded)
+            matches_basic_credentials(&credentials, &username, &password)
+        }
+        // Client certificates are not carried in the Authorization header and
+        // introspection needs a remote call; both are handled by the auth module.
+        AuthenticationType::MutualTls | AuthenticationType::Introspection => false,
+    }
+}
+
+struct BasicCredential {
+    username: String,
+    secret: String,
+}
+
+/// Checks the provided username/password against every configured user.
+///
+/// Usernames are compared in constant time against all entries so the position
+/// of a matching user is not observable. Only one secret is verified, because
+/// hashed secrets are deliberately expensive to check, see [`select_secret`].
+fn matches_basic_credentials(
+    credentials: &[BasicCredential],
+    username: &str,
+    password: &str,
+) -> bool {
+    let Some((secret, known_user)) = select_secret(credentials, username) else {
+        return false;
+    };
+
+    verify_password(password, secret) && known_user
+}
+
+/// The secret the password is verified against and whether it belongs to the
+/// user.
+///
+/// Without a matching user the configured secret that is most expensive to
+/// verify is checked instead and its result ignored. Unknown users thus take
+/// as long as the slowest known user, whatever formats the other secrets use,
+/// so the response time doesn't reveal whether the username exists.
+fn select_secret<'a>(
+    credentials: &'a [BasicCredential],
+    username: &str,
+) -> Option<(&'a str, bool)> {
+    let mut matched = None;
+    for credential in credentials {
+        if constant_time_eq(username.as_bytes(), credential.username.as_bytes())
+            && matched.is_none()
+        {
+            matched = Some(credential);
         }
     }
+
+    match matched {
+        Some(credential) => Some((credential.secret.as_str(), true)),
+        None => credentials
+            .iter()
+            .rev()
+            .max_by_key(|credential| PasswordHashFormat::detect(&credential.secret))
+            .map(|credential| (credential.secret.as_str(), false)),
+    }
+}
+
+fn decode_basic_authorization(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.trim().strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
 }
 
-fn basic_credentials(value: &Value) -> Option<String> {
+/// Reads the configured Basic users from `httpAuthValue`.
+///
+/// Accepts a single `user:secret` string, a `{ username, password }` object or
+/// a list of either. The password may be plaintext or a supported hash.
+fn basic_credentials(value: &Value) -> Vec<BasicCredential> {
+    match value.kind.as_ref() {
+        Some(Kind::ListValue(ListValue { values })) => {
+            values.iter().filter_map(basic_credential).collect()
+        }
+        _ => basic_credential(value).into_iter().collect(),
+    }
+}
+
+fn basic_credential(value: &Value) -> Option<BasicCredential> {
     if let Some(credentials) = value_as_string(value) {
-        return Some(credentials.trim().to_string());
+        let (username, secret) = credentials.trim().split_once(':')?;
+        return Some(BasicCredential {
+            username: username.to_string(),
+            secret: secret.to_string(),
+        });
     }
 
     let Some(Kind::StructValue(Struct { fields })) = value.kind.as_ref() else {
@@ -49,12 +140,17 @@ fn basic_credentials(value: &Value) -> Option<String> {
         .get("username")
         .or_else(|| fields.get("user"))
         .and_then(value_as_string)?;
-    let password = fields
+    let secret = fields
         .get("password")
         .or_else(|| fields.get("pass"))
+        .or_else(|| fields.get("passwordHash"))
+        .or_else(|| fields.get("hash"))
         .and_then(value_as_string)?;
 
-    Some(format!("{username}:{password}"))
+    Some(BasicCredential {
+        username: username.to_string(),
+        secret: secret.to_string(),
+    })
 }
 
 fn value_as_string(value: &Value) -> Option<&str> {
@@ -67,10 +163,11 @@ fn value_as_string(value: &Value) -> Option<&str> {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use tucana::shared::{Struct, Value, value::Kind};
+    use tucana::shared::{ListValue, Struct, Value, value::Kind};
 
-    use super::matches_authorization;
+    use super::{basic_credentials, matches_authorization, select_secret};
     use crate::auth::jwt::tests::create_hs256_jwt;
+    use crate::auth::secret::PasswordHashFormat;
     use crate::auth::types::AuthenticationType;
 
     #[test]
@@ -122,6 +219,139 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn bearer_static_rejects_other_schemes() {
+        let value = string_value("secret");
+
+        assert!(!matches_authorization(
+            AuthenticationType::BearerStatic,
+            &value,
+            "Basic secret"
+        ));
+        assert!(!matches_authorization(
+            AuthenticationType::BearerStatic,
+            &value,
+            "Bearer secret-and-more"
+        ));
+    }
+
+    #[test]
+    fn basic_matches_colon_separated_string() {
+        let value = string_value("user:pass");
+
+        assert!(matches_authorization(
+            AuthenticationType::Basic,
+            &value,
+            "Basic dXNlcjpwYXNz"
+        ));
+    }
+
+    #[test]
+    fn basic_matches_hashed_password() {
+        // SHA-256 crypt of "pass".
+        let value = basic_value(
+            "user",
+            "$5$draco.salt$sviI7euSIVpG14rQnZck.5xp9p3QW4PpSkGd5xfn/g2",
+        );
+
+        assert!(matches_authorization(
+            AuthenticationType::Basic,
+            &value,
+            "Basic dXNlcjpwYXNz"
+        ));
+        assert!(!matches_authorization(
+            AuthenticationType::Basic,
+            &value,
+            "Basic dXNlcjpvdGhlcg=="
+        ));
+    }
+
+    #[test]
+    fn basic_rejects_unknown_user() {
+        let value = basic_value("user", "pass");
+
+        // other:pass
+        assert!(!matches_authorization(
+            AuthenticationType::Basic,
+            &value,
+            "Basic b3RoZXI6cGFzcw=="
+        ));
+    }
+
+    #[test]
+    fn unknown_users_verify_the_same_kind_of_secret_as_known_ones() {
+        for secret in [
+            "pass",
+            "$5$draco.salt$sviI7euSIVpG14rQnZck.5xp9p3QW4PpSkGd5xfn/g2",
+        ] {
+            let credentials = basic_credentials(&basic_value("user", secret));
+
+            let (known, known_user) = select_secret(&credentials, "user").unwrap();
+            let (unknown, unknown_user) = select_secret(&credentials, "other").unwrap();
+
+            assert!(known_user);
+            assert!(!unknown_user);
+            assert_eq!(
+                PasswordHashFormat::detect(unknown),
+                PasswordHashFormat::detect(known)
+            );
+            assert_eq!(unknown, known);
+        }
+    }
+
+    #[test]
+    fn unknown_users_verify_the_most_expensive_secret_of_mixed_formats() {
+        let argon2 = "$argon2id$v=19$m=19456,t=2,p=1$ZHJhY28uc2FsdA$3+5Z1aKqRsnw1zHbKWGyf6MsKk/3pO5E35KcHtzbJJM";
+        let crypt = "$5$draco.salt$sviI7euSIVpG14rQnZck.5xp9p3QW4PpSkGd5xfn/g2";
+        let value = |secrets: &[(&str, &str)]| Value {
+            kind: Some(Kind::ListValue(ListValue {
+                values: secrets
+                    .iter()
+                    .map(|(username, secret)| basic_value(username, secret))
+                    .collect(),
+            })),
+        };
+
+        let credentials = basic_credentials(&value(&[
+            ("plain", "pass"),
+            ("crypt", crypt),
+            ("argon2", argon2),
+            ("other-plain", "other"),
+        ]));
+        assert_eq!(
+            select_secret(&credentials, "unknown"),
+            Some((argon2, false))
+        );
+        assert_eq!(select_secret(&credentials, "plain"), Some(("pass", true)));
+
+        let credentials = basic_credentials(&value(&[("plain", "pass"), ("crypt", crypt)]));
+        assert_eq!(select_secret(&credentials, "unknown"), Some((crypt, false)));
+    }
+
+    #[test]
+    fn basic_matches_any_user_from_list() {
+        let value = Value {
+            kind: Some(Kind::ListValue(ListValue {
+                values: vec![
+                    basic_value("admin", "admin-pass"),
+                    basic_value("user", "pass"),
+                ],
+            })),
+        };
+
+        assert!(matches_authorization(
+            AuthenticationType::Basic,
+            &value,
+            "Basic dXNlcjpwYXNz"
+        ));
+        // admin:pass must not match because the password belongs to another user.
+        assert!(!matches_authorization(
+            AuthenticationType::Basic,
+            &value,
+            "Basic YWRtaW46cGFzcw=="
+        ));
+    }
+
     fn string_value(value: &str) -> Value {
         Value {
             kind: Some(Kind::StringValue(value.to_string())),
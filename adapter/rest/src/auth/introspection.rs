@@ -0,0 +1,526 @@
+use base64::Engine;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Method, Request, StatusCode,
+    body::Bytes,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use ring::digest;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tucana::shared::{ListValue, Struct, Value, value::Kind};
+
+use crate::tls::crypto_provider;
+
+const INTROSPECTION_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_CACHED_TOKENS: usize = 10_000;
+
+/// Client for OAuth2 token introspection (RFC 7662).
+///
+/// Active responses are cached until the `exp` they report, so a token is only
+/// introspected once per lifetime. Inactive responses are never cached.
+///
+/// The request carries the token and the client secret, so endpoints have to
+/// use https unless plain http was explicitly allowed.
+pub struct IntrospectionClient {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    allow_http: bool,
+    cache: Mutex<HashMap<String, CachedIntrospection>>,
+    /// Endpoints already logged as misconfigured.
+    misconfigured: Mutex<HashSet<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum IntrospectionResult {
+    Active(serde_json::Value),
+    Inactive,
+    Unavailable,
+    /// The endpoint of the flow can't be used, e.g. plain http without opt-in.
+    Misconfigured,
+}
+
+struct CachedIntrospection {
+    response: serde_json::Value,
+    expires_at: u64,
+}
+
+/// Introspection settings of a flow, read from `httpAuthValue`:
+/// - `endpoint`: URL of the introspection endpoint
+/// - `clientId` / `clientSecret`: credentials of the resource server
+/// - `requiredScopes`: list or space separated string of scopes the token needs
+pub(super) struct IntrospectionConfig<'a> {
+    endpoint: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+    required_scopes: Vec<&'a str>,
+}
+
+impl IntrospectionClient {
+    pub fn new(allow_http: bool) -> anyhow::Result<Self> {
+        let builder =
+            HttpsConnectorBuilder::new().with_provider_and_native_roots(crypto_provider())?;
+        let builder = if allow_http {
+            builder.https_or_http()
+        } else {
+            builder.https_only()
+        };
+        let connector = builder.enable_http1().build();
+
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            allow_http,
+            cache: Mutex::new(HashMap::new()),
+            misconfigured: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub(super) async fn introspect(
+        &self,
+        config: &IntrospectionConfig<'_>,
+        token: &str,
+    ) -> IntrospectionResult {
+        let cache_key = cache_key(config, token);
+        let now = unix_now();
+
+        if let Some(response) = self.cached(&cache_key, now) {
+            log::debug!("introspection cache hit: endpoint={}", config.endpoint);
+            return IntrospectionResult::Active(response);
+        }
+
+        let result =
+            match tokio::time::timeout(INTROSPECTION_TIMEOUT, self.request(config, token)).await {
+                Ok(result) => result,
+                Err(_) => {
+                    log::warn!(
+                        "introspection timed out: endpoint={} timeout={}s",
+                        config.endpoint,
+                        INTROSPECTION_TIMEOUT.as_secs()
+                    );
+                    IntrospectionResult::Unavailable
+                }
+            };
+
+        if let IntrospectionResult::Active(response) = &result
+            && let Some(exp) = response.get("exp").and_then(|exp| exp.as_u64())
+        {
+            self.store(cache_key, response.clone(), exp, now);
+        }
+
+        result
+    }
+
+    async fn request(&self, config: &IntrospectionConfig<'_>, token: &str) -> IntrospectionResult {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", token)
+            .append_pair("token_type_hint", "access_token")
+            .finish();
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!(
+            "{}:{}",
+            form_urlencoded::byte_serialize(config.client_id.as_bytes()).collect::<String>(),
+            form_urlencoded::byte_serialize(config.client_secret.as_bytes()).collect::<String>()
+        ));
+
+        let request = match Request::builder()
+            .method(Method::POST)
+            .uri(config.endpoint)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(ACCEPT, "application/json")
+            .header(AUTHORIZATION, format!("Basic {credentials}"))
+            .body(Full::new(Bytes::from(body)))
+        {
+            Ok(request) => request,
+            Err(err) => {
+                self.report_misconfigured(
+                    config.endpoint,
+                    &format!("invalid_introspection_endpoint error={}", err),
+                );
+                return IntrospectionResult::Misconfigured;
+            }
+        };
+        if request.uri().scheme_str() != Some("https") && !self.allow_http {
+            self.report_misconfigured(config.endpoint, "insecure_introspection_endpoint");
+            return IntrospectionResult::Misconfigured;
+        }
+
+        let response = match self.http.request(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                log::warn!(
+                    "introspection request failed: endpoint={} error={:?}",
+                    config.endpoint,
+                    err
+                );
+                return IntrospectionResult::Unavailable;
+            }
+        };
+
+        let status = response.status();
+        let body = match response.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(err) => {
+                log::warn!(
+                    "introspection response unreadable: endpoint={} error={}",
+                    config.endpoint,
+                    err
+                );
+                return IntrospectionResult::Unavailable;
+            }
+        };
+
+        if status != StatusCode::OK {
+            log::warn!(
+                "introspection endpoint answered with status {}: endpoint={}",
+                status,
+                config.endpoint
+            );
+            return IntrospectionResult::Unavailable;
+        }
+
+        let response = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(response) => response,
+            Err(err) => {
+                log::warn!(
+                    "introspection response invalid: endpoint={} error={}",
+                    config.endpoint,
+                    err
+                );
+                return IntrospectionResult::Unavailable;
+            }
+        };
+
+        match response.get("active").and_then(|active| active.as_bool()) {
+            Some(true) => IntrospectionResult::Active(response),
+            _ => IntrospectionResult::Inactive,
+        }
+    }
+
+    /// Logs a misconfigured endpoint the first time it is used, instead of on
+    /// every request of the flow.
+    fn report_misconfigured(&self, endpoint: &str, reason: &str) {
+        let first = self
+            .misconfigured
+            .lock()
+            .map(|mut reported| reported.insert(endpoint.to_string()))
+            .unwrap_or(true);
+        if first {
+            log::error!(
+                "auth config invalid: reason={} endpoint={}",
+                reason,
+                endpoint
+            );
+        }
+    }
+
+    fn cached(&self, cache_key: &str, now: u64) -> Option<serde_json::Value> {
+        let cache = self.cache.lock().ok()?;
+        cache
+            .get(cache_key)
+            .filter(|cached| cached.expires_at > now)
+            .map(|cached| cached.response.clone())
+    }
+
+    fn store(&self, cache_key: String, response: serde_json::Value, expires_at: u64, now: u64) {
+        if expires_at <= now {
+            return;
+        }
+
+        let Ok(mut cache) = self.cache.lock() else {
+            return;
+        };
+
+        if cache.len() >= MAX_CACHED_TOKENS {
+            cache.retain(|_, cached| cached.expires_at > now);
+        }
+        if cache.len() >= MAX_CACHED_TOKENS {
+            log::warn!(
+                "introspection cache full ({} entries), not caching token",
+                MAX_CACHED_TOKENS
+            );
+            return;
+        }
+
+        cache.insert(
+            cache_key,
+            CachedIntrospection {
+                response,
+                expires_at,
+            },
+        );
+    }
+}
+
+impl<'a> IntrospectionConfig<'a> {
+    pub(super) fn from_value(value: &'a Value) -> Option<Self> {
+        let Some(Kind::StructValue(Struct { fields })) = value.kind.as_ref() else {
+            return None;
+        };
+
+        let required_scopes = match fields.get("requiredScopes").and_then(|v| v.kind.as_ref()) {
+            Some(Kind::StringValue(scopes)) => scopes.split_whitespace().collect(),
+            Some(Kind::ListValue(ListValue { values })) => {
+                values.iter().filter_map(value_as_string).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        Some(Self {
+            endpoint: fields.get("endpoint").and_then(value_as_string)?,
+            client_id: fields.get("clientId").and_then(value_as_string)?,
+            client_secret: fields.get("clientSecret").and_then(value_as_string)?,
+            required_scopes,
+        })
+    }
+
+    /// Checks the `scope` claim of an active response against the required scopes.
+    pub(super) fn has_required_scopes(&self, response: &serde_json::Value) -> bool {
+        let granted: Vec<&str> = response
+            .get("scope")
+            .and_then(|scope| scope.as_str())
+            .map(|scope| scope.split_whitespace().collect())
+            .unwrap_or_default();
+
+        self.required_scopes
+            .iter()
+            .all(|required| granted.contains(required))
+    }
+}
+
+/// Responses are only shared by flows using the same endpoint and credentials,
+/// so a flow with a wrong client secret can't reuse the response of another.
+fn cache_key(config: &IntrospectionConfig<'_>, token: &str) -> String {
+    let mut context = digest::Context::new(&digest::SHA256);
+    for part in [
+        config.endpoint,
+        config.client_id,
+        config.client_secret,
+        token,
+    ] {
+        context.update(part.as_bytes());
+        context.update(&[0]);
+    }
+
+    context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+fn value_as_string(value: &Value) -> Option<&str> {
+    match value.kind.as_ref() {
+        Some(Kind::StringValue(value)) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::{BodyExt, Full};
+    use hyper::{Request, Response, body::Bytes, server::conn::http1};
+    use hyper_util::rt::TokioIo;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+    use tucana::shared::{Struct, Value, value::Kind};
+
+    use super::{IntrospectionClient, IntrospectionConfig, IntrospectionResult, unix_now};
+
+    /// Minimal RFC 7662 endpoint: `active-token` is active with the `read` scope,
+    /// every other token is inactive. Returns the endpoint URL and a request counter.
+    async fn start_introspection_stub() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let counter = Arc::clone(&counter);
+
+                tokio::spawn(async move {
+                    let svc =
+                        hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                            let counter = Arc::clone(&counter);
+                            async move {
+                                counter.fetch_add(1, Ordering::SeqCst);
+                                let body = req.into_body().collect().await.unwrap().to_bytes();
+                                let active = form_urlencoded::parse(&body)
+                                    .any(|(key, value)| key == "token" && value == "active-token");
+
+                                let response = if active {
+                                    serde_json::json!({
+                                        "active": true,
+                                        "scope": "read",
+                                        "sub": "alice",
+                                        "exp": unix_now() + 3600,
+                                    })
+                                } else {
+                                    serde_json::json!({ "active": false })
+                                };
+
+                                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(
+                                    response.to_string(),
+                                ))))
+                            }
+                        });
+
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), svc)
+                        .await;
+                });
+            }
+        });
+
+        (format!("http://{addr}/introspect"), requests)
+    }
+
+    fn config_value(endpoint: &str, required_scopes: &str) -> Value {
+        config_value_with_secret(endpoint, required_scopes, "secret")
+    }
+
+    fn config_value_with_secret(endpoint: &str, required_scopes: &str, secret: &str) -> Value {
+        let mut fields = HashMap::new();
+        for (key, value) in [
+            ("endpoint", endpoint),
+            ("clientId", "draco"),
+            ("clientSecret", secret),
+            ("requiredScopes", required_scopes),
+        ] {
+            fields.insert(
+                key.to_string(),
+                Value {
+                    kind: Some(Kind::StringValue(value.to_string())),
+                },
+            );
+        }
+
+        Value {
+            kind: Some(Kind::StructValue(Struct { fields })),
+        }
+    }
+
+    #[tokio::test]
+    async fn active_token_is_cached_until_exp() {
+        let (endpoint, requests) = start_introspection_stub().await;
+        let value = config_value(&endpoint, "read");
+        let config = IntrospectionConfig::from_value(&value).unwrap();
+        let client = IntrospectionClient::new(true).unwrap();
+
+        let first = client.introspect(&config, "active-token").await;
+        let second = client.introspect(&config, "active-token").await;
+
+        assert!(matches!(first, IntrospectionResult::Active(_)));
+        assert_eq!(first, second);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cached_response_is_bound_to_the_client_secret() {
+        let (endpoint, requests) = start_introspection_stub().await;
+        let client = IntrospectionClient::new(true).unwrap();
+
+        for secret in ["secret", "other-secret"] {
+            let value = config_value_with_secret(&endpoint, "", secret);
+            let config = IntrospectionConfig::from_value(&value).unwrap();
+            assert!(matches!(
+                client.introspect(&config, "active-token").await,
+                IntrospectionResult::Active(_)
+            ));
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn inactive_token_is_not_cached() {
+        let (endpoint, requests) = start_introspection_stub().await;
+        let value = config_value(&endpoint, "");
+        let config = IntrospectionConfig::from_value(&value).unwrap();
+        let client = IntrospectionClient::new(true).unwrap();
+
+        assert_eq!(
+            client.introspect(&config, "revoked").await,
+            IntrospectionResult::Inactive
+        );
+        assert_eq!(
+            client.introspect(&config, "revoked").await,
+            IntrospectionResult::Inactive
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoint_is_unavailable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let value = config_value(&format!("http://{addr}/introspect"), "");
+        let config = IntrospectionConfig::from_value(&value).unwrap();
+        let client = IntrospectionClient::new(true).unwrap();
+
+        assert_eq!(
+            client.introspect(&config, "active-token").await,
+            IntrospectionResult::Unavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn http_endpoint_requires_opt_in() {
+        let (endpoint, requests) = start_introspection_stub().await;
+        let value = config_value(&endpoint, "");
+        let config = IntrospectionConfig::from_value(&value).unwrap();
+        let client = IntrospectionClient::new(false).unwrap();
+
+        assert_eq!(
+            client.introspect(&config, "active-token").await,
+            IntrospectionResult::Misconfigured
+        );
+        assert_eq!(
+            client.introspect(&config, "active-token").await,
+            IntrospectionResult::Misconfigured
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        assert_eq!(client.misconfigured.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unparsable_endpoint_is_misconfigured() {
+        let value = config_value("https://exa mple.com/introspect", "");
+        let config = IntrospectionConfig::from_value(&value).unwrap();
+        let client = IntrospectionClient::new(false).unwrap();
+
+        assert_eq!(
+            client.introspect(&config, "active-token").await,
+            IntrospectionResult::Misconfigured
+        );
+    }
+
+    #[test]
+    fn required_scopes_must_all_be_granted() {
+        let value = config_value("http://localhost/introspect", "read write");
+        let config = IntrospectionConfig::from_value(&value).unwrap();
+
+        assert!(config.has_required_scopes(&serde_json::json!({ "scope": "write read admin" })));
+        assert!(!config.has_required_scopes(&serde_json::json!({ "scope": "read" })));
+        assert!(!config.has_required_scopes(&serde_json::json!({})));
+    }
+}
@@ -1,5 +1,8 @@
 mod credentials;
+mod introspection;
 mod jwt;
+mod mtls;
+mod secret;
 mod settings;
 mod types;
 
@@ -7,18 +10,36 @@ use hyper::{
     HeaderMap,
     header::{AUTHORIZATION, HeaderValue, WWW_AUTHENTICATE},
 };
-use tucana::shared::ValidationFlow;
+use std::collections::HashMap;
+use tucana::shared::{ValidationFlow, Value};
 
 use self::credentials::matches_authorization;
-use self::settings::{FlowAuthConfig, flow_auth_config};
-pub use self::types::AuthenticationError;
+pub use self::introspection::IntrospectionClient;
+use self::introspection::{IntrospectionConfig, IntrospectionResult};
+pub use self::mtls::MtlsVerifiers;
+use self::settings::{AuthMethod, FlowAuthConfig, flow_auth_config};
+pub(crate) use self::types::AuthenticationType;
+pub use self::types::{Authentication, AuthenticationError};
+use crate::connection::ConnectionInfo;
 
-pub fn validate_flow_auth(
+/// Authenticates a request against the methods configured on the flow.
+///
+/// Methods are tried in the configured order and the first one that accepts the
+/// request wins. Returns the accepted method, or `None` for public flows.
+///
+/// If no method accepts the request and at least one could not be checked
+/// because its authorization server was unreachable, the request is answered
+/// with 503 instead of 401. A method that can't be checked because of its
+/// configuration answers with 500.
+pub async fn validate_flow_auth(
     flow: &ValidationFlow,
     headers: &HeaderMap<HeaderValue>,
-) -> Result<(), AuthenticationError> {
-    let auth_type = match flow_auth_config(flow) {
-        FlowAuthConfig::Unauthenticated => return Ok(()),
+    connection: &ConnectionInfo,
+    introspection: &IntrospectionClient,
+    mtls: &MtlsVerifiers,
+) -> Result<Option<Authentication>, AuthenticationError> {
+    let methods = match flow_auth_config(flow) {
+        FlowAuthConfig::Unauthenticated => return Ok(None),
         FlowAuthConfig::Invalid => {
             log::warn!(
                 "auth reject: flow_id={} reason=invalid_httpAuth",
@@ -26,40 +47,185 @@ pub fn validate_flow_auth(
             );
             return Err(AuthenticationError::InvalidAuthorization);
         }
-        FlowAuthConfig::Authenticated(auth_type) => auth_type,
+        FlowAuthConfig::Authenticated(methods) => methods,
     };
+    let auth_types = methods.iter().map(|method| method.auth_type).collect();
 
-    let Some(auth_value) = settings::flow_setting_value(flow, "httpAuthValue") else {
-        log::warn!(
-            "auth reject: flow_id={} reason=missing_or_invalid_httpAuthValue",
-            flow.flow_id
-        );
-        return Err(AuthenticationError::invalid_for(auth_type));
-    };
-
-    let Some(authorization) = headers
+    let authorization = headers
         .get(AUTHORIZATION)
-        .and_then(|value| value.to_str().ok())
-    else {
+        .and_then(|value| value.to_str().ok());
+
+    if !methods
+        .iter()
+        .any(|method| has_credentials(method.auth_type, authorization, connection))
+    {
         log::debug!(
             "auth reject: flow_id={} reason=missing_authorization",
             flow.flow_id
         );
-        return Err(AuthenticationError::missing_for(auth_type));
-    };
+        return Err(AuthenticationError::missing_for(auth_types));
+    }
 
-    if matches_authorization(auth_type, auth_value, authorization) {
-        log::debug!("auth accepted: flow_id={}", flow.flow_id);
-        Ok(())
-    } else {
-        log::debug!(
-            "auth reject: flow_id={} reason=authorization_mismatch",
+    let mut unavailable = false;
+    let mut misconfigured = false;
+    for method in &methods {
+        let Some(auth_value) = method.value else {
+            log::warn!(
+                "auth skip: flow_id={} method={} reason=missing_or_invalid_httpAuthValue",
+                flow.flow_id,
+                method.auth_type.identifier()
+            );
+            continue;
+        };
+
+        let request = MethodRequest {
+            authorization,
+            connection,
+            introspection,
+            mtls,
+        };
+
+        match authenticate_method(flow, method, auth_value, request).await {
+            MethodOutcome::Accepted(authentication) => {
+                log::debug!(
+                    "auth accepted: flow_id={} method={}",
+                    flow.flow_id,
+                    method.auth_type.identifier()
+                );
+                return Ok(Some(authentication));
+            }
+            MethodOutcome::Rejected => {}
+            MethodOutcome::Unavailable => unavailable = true,
+            MethodOutcome::Misconfigured => misconfigured = true,
+        }
+    }
+
+    if misconfigured {
+        log::warn!(
+            "auth reject: flow_id={} reason=authorization_misconfigured",
+            flow.flow_id
+        );
+        return Err(AuthenticationError::AuthorizationMisconfigured);
+    }
+
+    if unavailable {
+        log::warn!(
+            "auth reject: flow_id={} reason=authorization_server_unavailable",
             flow.flow_id
         );
-        Err(AuthenticationError::invalid_for(auth_type))
+        return Err(AuthenticationError::AuthorizationServerUnavailable);
     }
+
+    log::debug!(
+        "auth reject: flow_id={} reason=authorization_mismatch",
+        flow.flow_id
+    );
+    Err(AuthenticationError::invalid_for(auth_types))
 }
 
 pub fn authenticate_header_name() -> hyper::header::HeaderName {
     WWW_AUTHENTICATE
 }
+
+fn has_credentials(
+    auth_type: AuthenticationType,
+    authorization: Option<&str>,
+    connection: &ConnectionInfo,
+) -> bool {
+    match auth_type {
+        AuthenticationType::MutualTls => connection.client_certificate.is_some(),
+        _ => authorization.is_some(),
+    }
+}
+
+enum MethodOutcome {
+    Accepted(Authentication),
+    Rejected,
+    Unavailable,
+    Misconfigured,
+}
+
+#[derive(Clone, Copy)]
+struct MethodRequest<'a> {
+    authorization: Option<&'a str>,
+    connection: &'a ConnectionInfo,
+    introspection: &'a IntrospectionClient,
+    mtls: &'a MtlsVerifiers,
+}
+
+async fn authenticate_method(
+    flow: &ValidationFlow,
+    method: &AuthMethod<'_>,
+    auth_value: &Value,
+    request: MethodRequest<'_>,
+) -> MethodOutcome {
+    match method.auth_type {
+        AuthenticationType::MutualTls => {
+            let Some(chain) = request.connection.client_certificate.as_ref() else {
+                return MethodOutcome::Rejected;
+            };
+
+            match request
+                .mtls
+                .verify_client_certificate(flow.flow_id, auth_value, chain)
+            {
+                Some(details) => MethodOutcome::Accepted(
+                    Authentication::new(method.auth_type).with_details(details),
+                ),
+                None => MethodOutcome::Rejected,
+            }
+        }
+        AuthenticationType::Introspection => {
+            let Some(config) = IntrospectionConfig::from_value(auth_value) else {
+                log::warn!(
+                    "auth config invalid: flow_id={} reason=invalid_introspection_httpAuthValue",
+                    flow.flow_id
+                );
+                return MethodOutcome::Rejected;
+            };
+            let Some(token) = request
+                .authorization
+                .and_then(|authorization| authorization.trim().strip_prefix("Bearer "))
+            else {
+                return MethodOutcome::Rejected;
+            };
+
+            match request
+                .introspection
+                .introspect(&config, token.trim())
+                .await
+            {
+                IntrospectionResult::Active(response) if config.has_required_scopes(&response) => {
+                    let details = HashMap::from([(
+                        String::from("introspection"),
+                        tucana::shared::helper::value::from_json_value(response),
+                    )]);
+                    MethodOutcome::Accepted(
+                        Authentication::new(method.auth_type).with_details(details),
+                    )
+                }
+                IntrospectionResult::Active(_) => {
+                    log::debug!(
+                        "auth reject: flow_id={} reason=insufficient_scope",
+                        flow.flow_id
+                    );
+                    MethodOutcome::Rejected
+                }
+                IntrospectionResult::Inactive => MethodOutcome::Rejected,
+                IntrospectionResult::Unavailable => MethodOutcome::Unavailable,
+                IntrospectionResult::Misconfigured => MethodOutcome::Misconfigured,
+            }
+        }
+        auth_type => {
+            let Some(authorization) = request.authorization else {
+                return MethodOutcome::Rejected;
+            };
+
+            if matches_authorization(auth_type, auth_value, authorization) {
+                MethodOutcome::Accepted(Authentication::new(auth_type))
+            } else {
+                MethodOutcome::Rejected
+            }
+        }
+    }
+}
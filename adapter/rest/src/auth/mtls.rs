@@ -0,0 +1,474 @@
+use regex::Regex;
+use ring::digest;
+use rustls::{
+    RootCertStore,
+    pki_types::{CertificateDer, UnixTime, pem::PemObject},
+    server::{WebPkiClientVerifier, danger::ClientCertVerifier},
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tucana::shared::{ListValue, Struct, Value, value::Kind};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName};
+
+use crate::tls::{ClientCertificateChain, crypto_provider};
+
+const MAX_CACHED_CONFIGS: usize = 1_000;
+
+/// Verifiers built from the mTLS settings of the flows.
+///
+/// Parsing the CA bundle and compiling the patterns is done once per setting
+/// value, keyed by a hash of the value, so changed settings get a new entry.
+/// Invalid settings are cached as well.
+#[derive(Default)]
+pub struct MtlsVerifiers {
+    cache: Mutex<HashMap<String, Arc<CompiledMtls>>>,
+}
+
+type CompiledMtls = Result<MtlsVerifier, &'static str>;
+
+struct MtlsVerifier {
+    verifier: Arc<dyn ClientCertVerifier>,
+    subject: Option<Regex>,
+    san: Option<Regex>,
+}
+
+impl MtlsVerifiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verifies the client certificate of the connection against the flow's mTLS settings.
+    ///
+    /// `httpAuthValue` is either the PEM encoded CA bundle or an object with:
+    /// - `caBundle`: PEM encoded trusted CA certificates (required)
+    /// - `subject`: regex the certificate subject has to match
+    /// - `san`: regex at least one subject alternative name has to match
+    ///
+    /// Returns the verified certificate details for the flow input.
+    pub(super) fn verify_client_certificate(
+        &self,
+        flow_id: i64,
+        auth_value: &Value,
+        chain: &ClientCertificateChain,
+    ) -> Option<HashMap<String, Value>> {
+        let Some(config) = MtlsConfig::from_value(auth_value) else {
+            log::warn!(
+                "auth config invalid: flow_id={} reason=missing_mtls_caBundle",
+                flow_id
+            );
+            return None;
+        };
+
+        let compiled = self.compiled(flow_id, &config);
+        let verifier = match compiled.as_ref() {
+            Ok(verifier) => verifier,
+            Err(reason) => {
+                log::warn!("auth config invalid: flow_id={} reason={}", flow_id, reason);
+                return None;
+            }
+        };
+
+        if let Err(err) = verifier.verifier.verify_client_cert(
+            &chain.end_entity,
+            &chain.intermediates,
+            UnixTime::now(),
+        ) {
+            log::debug!(
+                "auth reject: flow_id={} reason=untrusted_client_certificate error={}",
+                flow_id,
+                err
+            );
+            return None;
+        }
+
+        let (_, certificate) = match x509_parser::parse_x509_certificate(&chain.end_entity) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                log::debug!(
+                    "auth reject: flow_id={} reason=unparsable_client_certificate error={}",
+                    flow_id,
+                    err
+                );
+                return None;
+            }
+        };
+
+        let subject = certificate.subject().to_string();
+        let sans = subject_alternative_names(&certificate);
+
+        if let Some(pattern) = &verifier.subject
+            && !pattern.is_match(&subject)
+        {
+            log::debug!(
+                "auth reject: flow_id={} reason=client_certificate_subject_mismatch subject={:?}",
+                flow_id,
+                subject
+            );
+            return None;
+        }
+
+        if let Some(pattern) = &verifier.san
+            && !sans.iter().any(|san| pattern.is_match(san))
+        {
+            log::debug!(
+                "auth reject: flow_id={} reason=client_certificate_san_mismatch sans={:?}",
+                flow_id,
+                sans
+            );
+            return None;
+        }
+
+        let mut details = HashMap::new();
+        details.insert(String::from("subject"), string_value(subject));
+        details.insert(
+            String::from("sans"),
+            Value {
+                kind: Some(Kind::ListValue(ListValue {
+                    values: sans.into_iter().map(string_value).collect(),
+                })),
+            },
+        );
+        details.insert(
+            String::from("fingerprint_sha256"),
+            string_value(fingerprint(&chain.end_entity)),
+        );
+
+        Some(details)
+    }
+
+    fn compiled(&self, flow_id: i64, config: &MtlsConfig<'_>) -> Arc<CompiledMtls> {
+        let cache_key = cache_key(config);
+        if let Some(compiled) = self
+            .cache
+            .lock()
+            .ok()
+            .and_then(|cache| cache.get(&cache_key).cloned())
+        {
+            return compiled;
+        }
+
+        let compiled = Arc::new(compile(flow_id, config));
+        if let Ok(mut cache) = self.cache.lock() {
+            // Settings are never removed from the cache otherwise, so start
+            // over instead of keeping the values of long changed flows.
+            if cache.len() >= MAX_CACHED_CONFIGS {
+                log::debug!(
+                    "mtls cache full ({} entries), clearing it",
+                    MAX_CACHED_CONFIGS
+                );
+                cache.clear();
+            }
+            cache.insert(cache_key, Arc::clone(&compiled));
+        }
+        compiled
+    }
+}
+
+struct MtlsConfig<'a> {
+    ca_bundle: &'a str,
+    subject: Option<&'a str>,
+    san: Option<&'a str>,
+}
+
+impl<'a> MtlsConfig<'a> {
+    fn from_value(value: &'a Value) -> Option<Self> {
+        match value.kind.as_ref() {
+            Some(Kind::StringValue(ca_bundle)) => Some(Self {
+                ca_bundle,
+                subject: None,
+                san: None,
+            }),
+            Some(Kind::StructValue(Struct { fields })) => Some(Self {
+                ca_bundle: fields.get("caBundle").and_then(value_as_string)?,
+                subject: fields.get("subject").and_then(value_as_string),
+                san: fields.get("san").and_then(value_as_string),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Builds the verifier of the settings, logging why they are invalid otherwise.
+fn compile(flow_id: i64, config: &MtlsConfig<'_>) -> CompiledMtls {
+    let certificates = CertificateDer::pem_slice_iter(config.ca_bundle.as_bytes())
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(certificates);
+    if added == 0 {
+        log::warn!(
+            "auth config invalid: flow_id={} reason=no_usable_mtls_ca ignored={}",
+            flow_id,
+            ignored
+        );
+        return Err("no_usable_mtls_ca");
+    }
+
+    let verifier =
+        match WebPkiClientVerifier::builder_with_provider(Arc::new(roots), crypto_provider())
+            .build()
+        {
+            Ok(verifier) => verifier,
+            Err(err) => {
+                log::warn!(
+                    "auth config invalid: flow_id={} reason=mtls_verifier error={}",
+                    flow_id,
+                    err
+                );
+                return Err("mtls_verifier");
+            }
+        };
+
+    Ok(MtlsVerifier {
+        verifier,
+        subject: config
+            .subject
+            .map(|pattern| compile_pattern(flow_id, pattern))
+            .transpose()?,
+        san: config
+            .san
+            .map(|pattern| compile_pattern(flow_id, pattern))
+            .transpose()?,
+    })
+}
+
+fn compile_pattern(flow_id: i64, pattern: &str) -> Result<Regex, &'static str> {
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(|err| {
+        log::warn!(
+            "auth config invalid: flow_id={} reason=invalid_mtls_pattern pattern={:?} error={}",
+            flow_id,
+            pattern,
+            err
+        );
+        "invalid_mtls_pattern"
+    })
+}
+
+fn cache_key(config: &MtlsConfig<'_>) -> String {
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(config.ca_bundle.as_bytes());
+    context.update(&[0]);
+    // Marks set patterns, so a missing pattern differs from an empty one.
+    for part in [config.subject, config.san] {
+        if let Some(pattern) = part {
+            context.update(&[1]);
+            context.update(pattern.as_bytes());
+        }
+        context.update(&[0]);
+    }
+
+    context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn subject_alternative_names(certificate: &X509Certificate<'_>) -> Vec<String> {
+    let Ok(Some(extension)) = certificate.subject_alternative_name() else {
+        return Vec::new();
+    };
+
+    extension
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(value)
+            | GeneralName::RFC822Name(value)
+            | GeneralName::URI(value) => Some(value.to_string()),
+            GeneralName::IPAddress(bytes) => ip_address(bytes).map(|ip| ip.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+fn fingerprint(certificate: &CertificateDer<'_>) -> String {
+    digest::digest(&digest::SHA256, certificate.as_ref())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn string_value(value: String) -> Value {
+    Value {
+        kind: Some(Kind::StringValue(value)),
+    }
+}
+
+fn value_as_string(value: &Value) -> Option<&str> {
+    match value.kind.as_ref() {
+        Some(Kind::StringValue(value)) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DistinguishedName, DnType,
+        ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use std::collections::HashMap;
+    use tucana::shared::{Struct, Value, value::Kind};
+
+    use super::MtlsVerifiers;
+    use crate::tls::ClientCertificateChain;
+
+    type TestCa = CertifiedIssuer<'static, KeyPair>;
+
+    #[test]
+    fn accepts_certificate_signed_by_flow_ca() {
+        let verifiers = MtlsVerifiers::new();
+        let ca = create_ca("Test CA");
+        let chain = create_client_certificate(&ca, "client", "client.example.com");
+
+        let details = verifiers
+            .verify_client_certificate(1, &string_value(&ca.pem()), &chain)
+            .expect("certificate should be trusted");
+
+        assert_eq!(
+            details.get("subject").and_then(|value| value.kind.as_ref()),
+            Some(&Kind::StringValue("CN=client".to_string()))
+        );
+        assert!(details.contains_key("sans"));
+        assert!(details.contains_key("fingerprint_sha256"));
+    }
+
+    #[test]
+    fn rejects_certificate_from_other_ca() {
+        let verifiers = MtlsVerifiers::new();
+        let trusted = create_ca("Trusted CA");
+        let other = create_ca("Other CA");
+        let chain = create_client_certificate(&other, "client", "client.example.com");
+
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &string_value(&trusted.pem()), &chain)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn subject_and_san_patterns_are_enforced() {
+        let verifiers = MtlsVerifiers::new();
+        let ca = create_ca("Test CA");
+        let chain = create_client_certificate(&ca, "partner-a", "a.partner.example.com");
+
+        let matching = config_value(&ca.pem(), "CN=partner-.*", r".*\.partner\.example\.com");
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &matching, &chain)
+                .is_some()
+        );
+
+        let wrong_subject = config_value(&ca.pem(), "CN=partner-b", r".*\.partner\.example\.com");
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &wrong_subject, &chain)
+                .is_none()
+        );
+
+        let wrong_san = config_value(&ca.pem(), "CN=partner-.*", r"b\.partner\.example\.com");
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &wrong_san, &chain)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn settings_are_compiled_once_per_value() {
+        let verifiers = MtlsVerifiers::new();
+        let ca = create_ca("Test CA");
+        let other = create_ca("Other CA");
+        let chain = create_client_certificate(&ca, "client", "client.example.com");
+
+        let value = string_value(&ca.pem());
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &value, &chain)
+                .is_some()
+        );
+        assert!(
+            verifiers
+                .verify_client_certificate(2, &value, &chain)
+                .is_some()
+        );
+        assert_eq!(verifiers.cache.lock().unwrap().len(), 1);
+
+        let changed = string_value(&other.pem());
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &changed, &chain)
+                .is_none()
+        );
+        assert_eq!(verifiers.cache.lock().unwrap().len(), 2);
+
+        let invalid = config_value(&ca.pem(), "(", ".*");
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &invalid, &chain)
+                .is_none()
+        );
+        assert!(
+            verifiers
+                .verify_client_certificate(1, &invalid, &chain)
+                .is_none()
+        );
+        assert_eq!(verifiers.cache.lock().unwrap().len(), 3);
+    }
+
+    fn create_ca(name: &str) -> TestCa {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+
+        CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap()
+    }
+
+    fn create_client_certificate(ca: &TestCa, name: &str, san: &str) -> ClientCertificateChain {
+        let mut params = CertificateParams::new(vec![san.to_string()]).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.signed_by(&key, &**ca).unwrap();
+
+        ClientCertificateChain {
+            end_entity: certificate.der().clone(),
+            intermediates: Vec::new(),
+        }
+    }
+
+    fn config_value(ca_bundle: &str, subject: &str, san: &str) -> Value {
+        let mut fields = HashMap::new();
+        fields.insert("caBundle".to_string(), string_value(ca_bundle));
+        fields.insert("subject".to_string(), string_value(subject));
+        fields.insert("san".to_string(), string_value(san));
+
+        Value {
+            kind: Some(Kind::StructValue(Struct { fields })),
+        }
+    }
+
+    fn string_value(value: &str) -> Value {
+        Value {
+            kind: Some(Kind::StringValue(value.to_string())),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base::secret::constant_time_eq;
+
+/// Verifies a password against a configured secret.
+///
+/// The secret may be a plaintext password or a password hash in one of the
+/// supported formats:
+/// - Argon2 PHC strings (`$argon2id$...`, `$argon2i$...`, `$argon2d$...`)
+/// - bcrypt (`$2a$`, `$2b$`, `$2y$`)
+/// - SHA-256/SHA-512 crypt (`$5$`, `$6$`)
+pub(super) fn verify_password(password: &str, secret: &str) -> bool {
+    match PasswordHashFormat::detect(secret) {
+        PasswordHashFormat::Argon2 => {
+            let Ok(hash) = PasswordHash::new(secret) else {
+                log::warn!("auth config invalid: malformed argon2 password hash");
+                return false;
+            };
+
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        }
+        PasswordHashFormat::Crypt => pwhash::unix::verify(password, secret),
+        PasswordHashFormat::Plaintext => constant_time_eq(password.as_bytes(), secret.as_bytes()),
+    }
+}
+
+/// Ordered by how expensive a secret of the format is to verify.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub(super) enum PasswordHashFormat {
+    Plaintext,
+    Crypt,
+    Argon2,
+}
+
+impl PasswordHashFormat {
+    pub(super) fn detect(secret: &str) -> Self {
+        if secret.starts_with("$argon2") {
+            return Self::Argon2;
+        }
+
+        if ["$2a$", "$2b$", "$2y$", "$5$", "$6$"]
+            .iter()
+            .any(|prefix| secret.starts_with(prefix))
+        {
+            return Self::Crypt;
+        }
+
+        Self::Plaintext
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{Argon2, PasswordHasher, password_hash::SaltString};
+
+    use super::{PasswordHashFormat, verify_password};
+
+    #[test]
+    fn hash_formats_are_detected() {
+        assert_eq!(
+            PasswordHashFormat::detect("$argon2id$v=19$m=19456,t=2,p=1$abc$def"),
+            PasswordHashFormat::Argon2
+        );
+        assert_eq!(
+            PasswordHashFormat::detect("$2b$10$abcdefghijklmnopqrstuv"),
+            PasswordHashFormat::Crypt
+        );
+        assert_eq!(
+            PasswordHashFormat::detect("$5$rounds=5000$salt$hash"),
+            PasswordHashFormat::Crypt
+        );
+        assert_eq!(
+            PasswordHashFormat::detect("plain-password"),
+            PasswordHashFormat::Plaintext
+        );
+    }
+
+    #[test]
+    fn verifies_plaintext_password() {
+        assert!(verify_password("pass", "pass"));
+        assert!(!verify_password("other", "pass"));
+    }
+
+    #[test]
+    fn verifies_argon2_password_hash() {
+        let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap();
+        let hash = Argon2::default()
+            .hash_password(b"pass", &salt)
+            .unwrap()
+            .to_string();
+
+        assert!(verify_password("pass", &hash));
+        assert!(!verify_password("other", &hash));
+    }
+
+    #[test]
+    fn verifies_bcrypt_password_hash() {
+        let hash = pwhash::bcrypt::hash("pass").unwrap();
+
+        assert!(verify_password("pass", &hash));
+        assert!(!verify_password("other", &hash));
+    }
+
+    #[test]
+    fn verifies_sha256_crypt_password_hash() {
+        // Generated with `openssl passwd -5 -salt draco.salt pass`.
+        let hash = "$5$draco.salt$sviI7euSIVpG14rQnZck.5xp9p3QW4PpSkGd5xfn/g2";
+
+        assert!(verify_password("pass", hash));
+        assert!(!verify_password("other", hash));
+    }
+
+    #[test]
+    fn malformed_argon2_hash_is_rejected() {
+        assert!(!verify_password("pass", "$argon2id$broken"));
+    }
+}
@@ -1,33 +1,143 @@
-use tucana::shared::{ValidationFlow, Value, value::Kind};
+use tucana::shared::{ListValue, Struct, ValidationFlow, Value, value::Kind};
 
 use super::types::{AuthenticationType, is_unauthenticated_value};
 
-pub(super) enum FlowAuthConfig {
+pub(super) enum FlowAuthConfig<'a> {
     Unauthenticated,
-    Authenticated(AuthenticationType),
+    Authenticated(Vec<AuthMethod<'a>>),
     Invalid,
 }
 
-pub(super) fn flow_auth_config(flow: &ValidationFlow) -> FlowAuthConfig {
-    let Some(raw_auth_type) = flow_setting_as_string(flow, "httpAuth") else {
+/// One accepted authentication method of a flow together with its configured value.
+pub(super) struct AuthMethod<'a> {
+    pub(super) auth_type: AuthenticationType,
+    pub(super) value: Option<&'a Value>,
+}
+
+/// Reads the authentication methods of a flow.
+///
+/// `httpAuth` is either a single type (with `httpAuthValue` as its value), a list
+/// of types (with `httpAuthValue` as a list of the same length), or a list of
+/// `{ type, value }` objects. Methods are tried in the configured order.
+pub(super) fn flow_auth_config(flow: &ValidationFlow) -> FlowAuthConfig<'_> {
+    let Some(raw_auth) = flow_setting_value(flow, "httpAuth") else {
         return FlowAuthConfig::Unauthenticated;
     };
+    let auth_value = flow_setting_value(flow, "httpAuthValue");
 
-    if is_unauthenticated_value(raw_auth_type) {
-        return FlowAuthConfig::Unauthenticated;
+    match raw_auth.kind.as_ref() {
+        Some(Kind::StringValue(raw_auth_type)) => {
+            if is_unauthenticated_value(raw_auth_type) {
+                return FlowAuthConfig::Unauthenticated;
+            }
+
+            match parse_auth_type(flow, raw_auth_type) {
+                Some(auth_type) => FlowAuthConfig::Authenticated(vec![AuthMethod {
+                    auth_type,
+                    value: auth_value,
+                }]),
+                None => FlowAuthConfig::Invalid,
+            }
+        }
+        Some(Kind::ListValue(ListValue { values })) => {
+            // A list that was meant to protect the flow must not make it public
+            // when it's empty; public flows set "none" instead.
+            match auth_method_list(flow, values, auth_value) {
+                Some(methods) if methods.is_empty() => {
+                    log::warn!(
+                        "auth config invalid: flow_id={} reason=empty_method_list",
+                        flow.flow_id
+                    );
+                    FlowAuthConfig::Invalid
+                }
+                Some(methods) => FlowAuthConfig::Authenticated(methods),
+                None => FlowAuthConfig::Invalid,
+            }
+        }
+        // Missing or null/non-string httpAuth means the flow remains public.
+        _ => FlowAuthConfig::Unauthenticated,
+    }
+}
+
+fn auth_method_list<'a>(
+    flow: &ValidationFlow,
+    entries: &'a [Value],
+    auth_value: Option<&'a Value>,
+) -> Option<Vec<AuthMethod<'a>>> {
+    let values = match auth_value.and_then(|value| value.kind.as_ref()) {
+        Some(Kind::ListValue(ListValue { values })) => Some(values.as_slice()),
+        _ => None,
+    };
+
+    if let Some(values) = values
+        && values.len() != entries.len()
+    {
+        log::warn!(
+            "auth config invalid: flow_id={} reason=httpAuth_httpAuthValue_length_mismatch httpAuth={} httpAuthValue={}",
+            flow.flow_id,
+            entries.len(),
+            values.len()
+        );
+        return None;
     }
 
-    match AuthenticationType::parse(raw_auth_type) {
-        Some(auth_type) => FlowAuthConfig::Authenticated(auth_type),
-        None => {
+    let mut methods = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        let positional_value = values.and_then(|values| values.get(index));
+
+        let (raw_auth_type, value) = match entry.kind.as_ref() {
+            Some(Kind::StringValue(raw_auth_type)) => (raw_auth_type.as_str(), positional_value),
+            Some(Kind::StructValue(Struct { fields })) => {
+                let Some(raw_auth_type) = fields.get("type").and_then(value_as_string) else {
+                    log::warn!(
+                        "auth config invalid: flow_id={} reason=missing_method_type index={}",
+                        flow.flow_id,
+                        index
+                    );
+                    return None;
+                };
+
+                (raw_auth_type, fields.get("value").or(positional_value))
+            }
+            _ => {
+                log::warn!(
+                    "auth config invalid: flow_id={} reason=invalid_method_entry index={}",
+                    flow.flow_id,
+                    index
+                );
+                return None;
+            }
+        };
+
+        if is_unauthenticated_value(raw_auth_type) {
             log::warn!(
-                "auth config invalid: flow_id={} httpAuth={:?}",
+                "auth config invalid: flow_id={} reason=unauthenticated_in_method_list index={}",
                 flow.flow_id,
-                raw_auth_type
+                index
             );
-            FlowAuthConfig::Invalid
+            return None;
         }
+
+        methods.push(AuthMethod {
+            auth_type: parse_auth_type(flow, raw_auth_type)?,
+            value,
+        });
+    }
+
+    Some(methods)
+}
+
+fn parse_auth_type(flow: &ValidationFlow, raw_auth_type: &str) -> Option<AuthenticationType> {
+    let auth_type = AuthenticationType::parse(raw_auth_type);
+    if auth_type.is_none() {
+        log::warn!(
+            "auth config invalid: flow_id={} httpAuth={:?}",
+            flow.flow_id,
+            raw_auth_type
+        );
     }
+
+    auth_type
 }
 
 pub(super) fn flow_setting_value<'a>(
@@ -40,12 +150,142 @@ pub(super) fn flow_setting_value<'a>(
         .and_then(|setting| setting.value.as_ref())
 }
 
-fn flow_setting_as_string<'a>(flow: &'a ValidationFlow, flow_setting_id: &str) -> Option<&'a str> {
-    flow_setting_value(flow, flow_setting_id)
-        .and_then(|value| value.kind.as_ref())
-        .and_then(|kind| match kind {
-            Kind::StringValue(value) => Some(value.as_str()),
-            // Missing or null/non-string httpAuth means the flow remains public.
-            _ => None,
-        })
+fn value_as_string(value: &Value) -> Option<&str> {
+    match value.kind.as_ref() {
+        Some(Kind::StringValue(value)) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use tucana::shared::{FlowSetting, ListValue, Struct, ValidationFlow, Value, value::Kind};
+
+    use super::{FlowAuthConfig, flow_auth_config};
+    use crate::auth::types::AuthenticationType;
+
+    #[test]
+    fn single_auth_type_uses_auth_value() {
+        let flow = flow_with_auth(string_value("Bearer static"), Some(string_value("token")));
+
+        let FlowAuthConfig::Authenticated(methods) = flow_auth_config(&flow) else {
+            panic!("expected authenticated config");
+        };
+
+        assert_eq!(methods.len(), 1);
+        assert_eq!(methods[0].auth_type, AuthenticationType::BearerStatic);
+        assert_eq!(methods[0].value, Some(&string_value("token")));
+    }
+
+    #[test]
+    fn auth_type_list_is_zipped_with_value_list() {
+        let flow = flow_with_auth(
+            list_value(vec![string_value("Bearer static"), string_value("jwt")]),
+            Some(list_value(vec![
+                string_value("token"),
+                string_value("jwt-secret"),
+            ])),
+        );
+
+        let FlowAuthConfig::Authenticated(methods) = flow_auth_config(&flow) else {
+            panic!("expected authenticated config");
+        };
+
+        assert_eq!(methods.len(), 2);
+        assert_eq!(methods[0].auth_type, AuthenticationType::BearerStatic);
+        assert_eq!(methods[0].value, Some(&string_value("token")));
+        assert_eq!(methods[1].auth_type, AuthenticationType::BearerJwt);
+        assert_eq!(methods[1].value, Some(&string_value("jwt-secret")));
+    }
+
+    #[test]
+    fn auth_method_objects_carry_their_own_value() {
+        let flow = flow_with_auth(
+            list_value(vec![
+                method_value("basic", string_value("user:pass")),
+                method_value("jwt", string_value("jwt-secret")),
+            ]),
+            None,
+        );
+
+        let FlowAuthConfig::Authenticated(methods) = flow_auth_config(&flow) else {
+            panic!("expected authenticated config");
+        };
+
+        assert_eq!(methods[0].auth_type, AuthenticationType::Basic);
+        assert_eq!(methods[0].value, Some(&string_value("user:pass")));
+        assert_eq!(methods[1].auth_type, AuthenticationType::BearerJwt);
+    }
+
+    #[test]
+    fn mismatched_list_lengths_are_invalid() {
+        let flow = flow_with_auth(
+            list_value(vec![string_value("basic"), string_value("jwt")]),
+            Some(list_value(vec![string_value("user:pass")])),
+        );
+
+        assert!(matches!(flow_auth_config(&flow), FlowAuthConfig::Invalid));
+    }
+
+    #[test]
+    fn unauthenticated_value_in_list_is_invalid() {
+        let flow = flow_with_auth(
+            list_value(vec![string_value("jwt"), string_value("none")]),
+            None,
+        );
+
+        assert!(matches!(flow_auth_config(&flow), FlowAuthConfig::Invalid));
+    }
+
+    #[test]
+    fn empty_method_list_is_invalid() {
+        let flow = flow_with_auth(list_value(Vec::new()), None);
+
+        assert!(matches!(flow_auth_config(&flow), FlowAuthConfig::Invalid));
+    }
+
+    fn flow_with_auth(auth: Value, auth_value: Option<Value>) -> ValidationFlow {
+        let mut settings = vec![setting("httpAuth", auth)];
+        if let Some(auth_value) = auth_value {
+            settings.push(setting("httpAuthValue", auth_value));
+        }
+
+        ValidationFlow {
+            flow_id: 1,
+            settings,
+            ..ValidationFlow::default()
+        }
+    }
+
+    fn setting(flow_setting_id: &str, value: Value) -> FlowSetting {
+        FlowSetting {
+            database_id: None,
+            flow_setting_id: flow_setting_id.to_string(),
+            value: Some(value),
+            cast: None,
+        }
+    }
+
+    fn method_value(auth_type: &str, value: Value) -> Value {
+        let mut fields = HashMap::new();
+        fields.insert("type".to_string(), string_value(auth_type));
+        fields.insert("value".to_string(), value);
+
+        Value {
+            kind: Some(Kind::StructValue(Struct { fields })),
+        }
+    }
+
+    fn list_value(values: Vec<Value>) -> Value {
+        Value {
+            kind: Some(Kind::ListValue(ListValue { values })),
+        }
+    }
+
+    fn string_value(value: &str) -> Value {
+        Value {
+            kind: Some(Kind::StringValue(value.to_string())),
+        }
+    }
 }
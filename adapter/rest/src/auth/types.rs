@@ -1,57 +1,96 @@
-use hyper::{StatusCode, header::HeaderValue};
+use hyper::header::HeaderValue;
+use std::collections::HashMap;
+use tucana::shared::{Struct, Value, value::Kind};
+
+use crate::response::ProblemType;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub(crate) enum AuthenticationType {
     BearerJwt,
     BearerStatic,
     Basic,
+    MutualTls,
+    Introspection,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// The outcome of a successful authentication, passed to the flow input as `auth`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authentication {
+    pub(crate) method: AuthenticationType,
+    /// Method specific details, e.g. the verified client certificate for mTLS.
+    pub(crate) details: HashMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum AuthenticationError {
-    MissingAuthorization(AuthenticationType),
-    InvalidAuthorizationFor(AuthenticationType),
+    MissingAuthorization(Vec<AuthenticationType>),
+    InvalidAuthorizationFor(Vec<AuthenticationType>),
     InvalidAuthorization,
+    /// A remote authorization server needed to decide the request could not be reached.
+    AuthorizationServerUnavailable,
+    /// A method needed to decide the request can't be used with its configuration.
+    AuthorizationMisconfigured,
 }
 
 impl AuthenticationError {
-    pub(super) fn missing_for(auth_type: AuthenticationType) -> Self {
-        Self::MissingAuthorization(auth_type)
+    pub(super) fn missing_for(auth_types: Vec<AuthenticationType>) -> Self {
+        Self::MissingAuthorization(auth_types)
     }
 
-    pub(super) fn invalid_for(auth_type: AuthenticationType) -> Self {
-        Self::InvalidAuthorizationFor(auth_type)
+    pub(super) fn invalid_for(auth_types: Vec<AuthenticationType>) -> Self {
+        Self::InvalidAuthorizationFor(auth_types)
     }
 
-    pub fn status_code(self) -> StatusCode {
-        StatusCode::UNAUTHORIZED
+    pub fn problem_type(&self) -> ProblemType {
+        match self {
+            Self::MissingAuthorization(_) => ProblemType::AuthenticationRequired,
+            Self::InvalidAuthorizationFor(_) | Self::InvalidAuthorization => {
+                ProblemType::InvalidCredentials
+            }
+            Self::AuthorizationServerUnavailable => ProblemType::AuthorizationServerUnavailable,
+            Self::AuthorizationMisconfigured => ProblemType::AuthorizationMisconfigured,
+        }
     }
 
-    pub fn message(self) -> &'static str {
+    pub fn message(&self) -> &'static str {
         match self {
             Self::MissingAuthorization(_) => "Missing authorization",
             Self::InvalidAuthorizationFor(_) | Self::InvalidAuthorization => {
                 "Invalid authorization"
             }
+            Self::AuthorizationServerUnavailable => "Authorization server unavailable",
+            Self::AuthorizationMisconfigured => "Authorization misconfigured",
         }
     }
 
-    pub fn challenge(self) -> HeaderValue {
-        match self.auth_type() {
-            Some(AuthenticationType::BearerJwt | AuthenticationType::BearerStatic) => {
-                HeaderValue::from_static("Bearer")
+    /// One `WWW-Authenticate` challenge per distinct scheme the flow accepts,
+    /// in the configured order. mTLS has no HTTP challenge and is left out.
+    pub fn challenges(&self) -> Vec<HeaderValue> {
+        let mut challenges: Vec<HeaderValue> = Vec::new();
+
+        for auth_type in self.auth_types() {
+            if let Some(challenge) = auth_type.challenge()
+                && !challenges.contains(&challenge)
+            {
+                challenges.push(challenge);
             }
-            Some(AuthenticationType::Basic) => HeaderValue::from_static("Basic"),
-            None => HeaderValue::from_static("Bearer"),
         }
+
+        if matches!(self, Self::InvalidAuthorization) {
+            challenges.push(HeaderValue::from_static("Bearer"));
+        }
+
+        challenges
     }
 
-    fn auth_type(self) -> Option<AuthenticationType> {
+    fn auth_types(&self) -> &[AuthenticationType] {
         match self {
-            Self::MissingAuthorization(auth_type) | Self::InvalidAuthorizationFor(auth_type) => {
-                Some(auth_type)
+            Self::MissingAuthorization(auth_types) | Self::InvalidAuthorizationFor(auth_types) => {
+                auth_types
             }
-            Self::InvalidAuthorization => None,
+            Self::InvalidAuthorization
+            | Self::AuthorizationServerUnavailable
+            | Self::AuthorizationMisconfigured => &[],
         }
     }
 }
@@ -64,9 +103,62 @@ impl AuthenticationType {
             "bearerjwt" | "jwt" => Some(Self::BearerJwt),
             "bearerstatic" | "bearer" | "staticbearer" => Some(Self::BearerStatic),
             "basicaccessauth" | "basic" | "basicauth" => Some(Self::Basic),
+            "mtls" | "mutualtls" | "clientcertificate" => Some(Self::MutualTls),
+            "introspection" | "oauth2introspection" | "tokenintrospection" => {
+                Some(Self::Introspection)
+            }
             _ => None,
         }
     }
+
+    /// Stable name of the method, passed to the flow input as `auth.method`.
+    pub(crate) fn identifier(self) -> &'static str {
+        match self {
+            Self::BearerJwt => "bearer_jwt",
+            Self::BearerStatic => "bearer_static",
+            Self::Basic => "basic",
+            Self::MutualTls => "mtls",
+            Self::Introspection => "introspection",
+        }
+    }
+
+    fn challenge(self) -> Option<HeaderValue> {
+        match self {
+            Self::BearerJwt | Self::BearerStatic | Self::Introspection => {
+                Some(HeaderValue::from_static("Bearer"))
+            }
+            Self::Basic => Some(HeaderValue::from_static("Basic")),
+            Self::MutualTls => None,
+        }
+    }
+}
+
+impl Authentication {
+    pub(crate) fn new(method: AuthenticationType) -> Self {
+        Self {
+            method,
+            details: HashMap::new(),
+        }
+    }
+
+    pub(super) fn with_details(mut self, details: HashMap<String, Value>) -> Self {
+        self.details = details;
+        self
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut fields = self.details.clone();
+        fields.insert(
+            String::from("method"),
+            Value {
+                kind: Some(Kind::StringValue(self.method.identifier().to_string())),
+            },
+        );
+
+        Value {
+            kind: Some(Kind::StructValue(Struct { fields })),
+        }
+    }
 }
 
 pub(super) fn is_unauthenticated_value(value: &str) -> bool {
@@ -85,7 +177,7 @@ fn normalize_auth_type(value: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::{AuthenticationType, is_unauthenticated_value};
+    use super::{AuthenticationError, AuthenticationType, is_unauthenticated_value};
 
     #[test]
     fn rest_auth_type_values_parse() {
@@ -101,6 +193,14 @@ mod tests {
             AuthenticationType::parse("Basic"),
             Some(AuthenticationType::Basic)
         );
+        assert_eq!(
+            AuthenticationType::parse("mTLS"),
+            Some(AuthenticationType::MutualTls)
+        );
+        assert_eq!(
+            AuthenticationType::parse("OAuth2 introspection"),
+            Some(AuthenticationType::Introspection)
+        );
     }
 
     #[test]
@@ -110,4 +210,22 @@ mod tests {
         assert!(is_unauthenticated_value("none"));
         assert!(is_unauthenticated_value(""));
     }
+
+    #[test]
+    fn challenges_list_every_distinct_scheme() {
+        let err = AuthenticationError::missing_for(vec![
+            AuthenticationType::BearerStatic,
+            AuthenticationType::Basic,
+            AuthenticationType::BearerJwt,
+        ]);
+
+        assert_eq!(err.challenges(), vec!["Bearer", "Basic"]);
+    }
+
+    #[test]
+    fn mtls_has_no_challenge() {
+        let err = AuthenticationError::missing_for(vec![AuthenticationType::MutualTls]);
+
+        assert!(err.challenges().is_empty());
+    }
 }
@@ -0,0 +1,196 @@
+use hyper::{HeaderMap, header::HeaderValue};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Parses a CIDR range (`10.0.0.0/8`) or a single address (`10.0.0.1`).
+pub fn parse_network(raw: &str) -> Option<IpNet> {
+    let raw = raw.trim();
+
+    raw.parse::<IpNet>()
+        .ok()
+        .or_else(|| raw.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Resolves the address of the client that sent the request.
+///
+/// Forwarding headers are only honoured if the direct peer is a trusted proxy.
+/// The chain is then walked from the nearest hop backwards and the first
+/// address that is not a trusted proxy is the client. `Forwarded` takes
+/// precedence over `X-Forwarded-For` when both are present.
+pub fn resolve_client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap<HeaderValue>,
+    trusted_proxies: &[IpNet],
+) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !is_trusted(peer, trusted_proxies) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        // An obfuscated or malformed hop can't be attributed, so the last
+        // trusted address is the best answer we can give.
+        let Some(hop) = hop else {
+            break;
+        };
+
+        client = hop.to_canonical();
+        if !is_trusted(client, trusted_proxies) {
+            break;
+        }
+    }
+
+    client
+}
+
+fn is_trusted(ip: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|network| network.contains(&ip))
+}
+
+fn forwarded_chain(headers: &HeaderMap<HeaderValue>) -> Vec<Option<IpAddr>> {
+    if headers.contains_key(FORWARDED) {
+        return header_elements(headers, FORWARDED)
+            .map(|element| forwarded_for(element).and_then(parse_node))
+            .collect();
+    }
+
+    header_elements(headers, X_FORWARDED_FOR)
+        .map(parse_node)
+        .collect()
+}
+
+/// Comma separated elements across all occurrences of a header, in order.
+fn header_elements<'a>(
+    headers: &'a HeaderMap<HeaderValue>,
+    name: &'static str,
+) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+}
+
+/// Extracts the `for` parameter of a `Forwarded` element (RFC 7239).
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        key.trim().eq_ignore_ascii_case("for").then(|| value.trim())
+    })
+}
+
+/// Parses a node such as `192.0.2.1`, `192.0.2.1:8080`, `"[2001:db8::1]:443"`
+/// or `2001:db8::1`. Returns `None` for `unknown` and obfuscated identifiers.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        let (address, _) = rest.split_once(']')?;
+        return address.parse().ok();
+    }
+
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{HeaderMap, header::HeaderValue};
+    use ipnet::IpNet;
+    use std::net::IpAddr;
+
+    use super::{parse_network, parse_node, resolve_client_ip};
+
+    #[test]
+    fn untrusted_peer_ignores_forwarding_headers() {
+        let headers = headers(&[("x-forwarded-for", "203.0.113.7")]);
+
+        assert_eq!(
+            resolve_client_ip(ip("198.51.100.1"), &headers, &proxies(&["10.0.0.0/8"])),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn trusted_peer_uses_first_untrusted_hop_from_the_right() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1, 203.0.113.7, 10.0.0.2")]);
+
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &proxies(&["10.0.0.0/8"])),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn forwarded_header_takes_precedence() {
+        let headers = headers(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            (
+                "forwarded",
+                r#"for="[2001:db8::7]:4711";proto=https, for=10.0.0.2"#,
+            ),
+        ]);
+
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &proxies(&["10.0.0.0/8"])),
+            ip("2001:db8::7")
+        );
+    }
+
+    #[test]
+    fn unknown_hop_stops_at_last_trusted_address() {
+        let headers = headers(&[("forwarded", "for=unknown, for=10.0.0.2")]);
+
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &proxies(&["10.0.0.0/8"])),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn ipv4_mapped_peer_is_canonicalized() {
+        assert_eq!(
+            resolve_client_ip(ip("::ffff:192.0.2.1"), &HeaderMap::new(), &[]),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn nodes_with_ports_are_parsed() {
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("\"[2001:db8::1]:443\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn networks_accept_single_addresses() {
+        assert_eq!(
+            parse_network("192.0.2.1").map(|network| network.to_string()),
+            Some("192.0.2.1/32".to_string())
+        );
+        assert!(parse_network("192.0.2.0/33").is_none());
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    fn proxies(raw: &[&str]) -> Vec<IpNet> {
+        raw.iter().map(|raw| raw.parse().unwrap()).collect()
+    }
+
+    fn headers(entries: &[(&'static str, &str)]) -> HeaderMap<HeaderValue> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+}
@@ -1,5 +1,8 @@
 use base::traits::LoadConfig;
 use code0_flow::flow_config::env_with_default;
+use ipnet::IpNet;
+
+use crate::access_log::AccessLogConfig;
 
 #[derive(Clone)]
 pub struct HttpServerConfig {
@@ -7,6 +10,21 @@ pub struct HttpServerConfig {
     pub external_port: u16,
     pub host: String,
     pub external_host: String,
+    /// PEM certificate chain for serving HTTPS. TLS is disabled when unset.
+    pub tls_cert_path: Option<String>,
+    /// PEM private key matching `tls_cert_path`.
+    pub tls_key_path: Option<String>,
+    /// Comma separated proxies whose `X-Forwarded-For`/`Forwarded` headers are
+    /// trusted when resolving the client IP, see [`trusted_proxies`]. Empty
+    /// means the peer address is always used.
+    pub trusted_proxies: String,
+    /// Allows plain http introspection endpoints, which receive tokens and
+    /// client secrets unencrypted.
+    pub introspection_allow_http: bool,
+    pub access_log: AccessLogConfig,
+    /// JSON file mapping project slugs to the error body template of the
+    /// project. Errors are sent as problem+json when unset.
+    pub error_templates_path: Option<String>,
 }
 
 impl LoadConfig for HttpServerConfig {
@@ -19,6 +37,49 @@ impl LoadConfig for HttpServerConfig {
             port,
             external_port: env_with_default("EXTERNAL_HTTP_SERVER_PORT", port),
             external_host: env_with_default("EXTERNAL_HTTP_SERVER_HOST", host),
+            tls_cert_path: optional_env("HTTP_SERVER_TLS_CERT_PATH"),
+            tls_key_path: optional_env("HTTP_SERVER_TLS_KEY_PATH"),
+            trusted_proxies: env_with_default("HTTP_TRUSTED_PROXIES", String::new()),
+            introspection_allow_http: env_with_default("HTTP_INTROSPECTION_ALLOW_HTTP", false),
+            access_log: AccessLogConfig::from_env(),
+            error_templates_path: optional_env("HTTP_ERROR_TEMPLATES_PATH"),
         }
     }
 }
+
+fn optional_env(name: &str) -> Option<String> {
+    Some(env_with_default(name, String::new())).filter(|value| !value.is_empty())
+}
+
+/// Parses a comma separated list of CIDR ranges or single addresses, or
+/// returns the first invalid entry.
+pub fn trusted_proxies(raw: &str) -> Result<Vec<IpNet>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| crate::client_ip::parse_network(entry).ok_or_else(|| entry.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trusted_proxies;
+
+    #[test]
+    fn trusted_proxies_accept_networks_and_addresses() {
+        let proxies = trusted_proxies(" 10.0.0.0/8, 192.168.1.10 ,,fd00::/8").unwrap();
+
+        assert_eq!(
+            proxies.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["10.0.0.0/8", "192.168.1.10/32", "fd00::/8"]
+        );
+    }
+
+    #[test]
+    fn invalid_trusted_proxy_is_named() {
+        assert_eq!(
+            trusted_proxies("10.0.0.0/8, 10.0.0.0/33"),
+            Err(String::from("10.0.0.0/33"))
+        );
+    }
+}
@@ -0,0 +1,13 @@
+use std::net::SocketAddr;
+
+use crate::tls::ClientCertificateChain;
+
+/// Per-connection information shared by every request served on that connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// Address of the directly connected peer, which may be a proxy.
+    pub peer_addr: SocketAddr,
+    /// Certificate chain presented by the client, if the connection uses TLS
+    /// and the client sent one.
+    pub client_certificate: Option<ClientCertificateChain>,
+}
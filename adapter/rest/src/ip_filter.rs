@@ -0,0 +1,162 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+use tucana::shared::{ListValue, ValidationFlow, value::Kind};
+
+use crate::client_ip::parse_network;
+
+/// Decides whether a client may call a flow based on its network settings.
+///
+/// `httpIpDeny` and `httpIpAllow` are lists (or comma separated strings) of
+/// CIDR ranges or single addresses. A denied address is always rejected; if an
+/// allowlist is configured, only addresses inside it are accepted. Invalid
+/// entries reject every request so a typo never opens a flow up.
+pub fn is_allowed(flow: &ValidationFlow, client_ip: IpAddr) -> bool {
+    let deny = match flow_networks(flow, "httpIpDeny") {
+        Ok(deny) => deny,
+        Err(entry) => return reject_invalid(flow, "httpIpDeny", &entry),
+    };
+    let allow = match flow_networks(flow, "httpIpAllow") {
+        Ok(allow) => allow,
+        Err(entry) => return reject_invalid(flow, "httpIpAllow", &entry),
+    };
+
+    if deny.iter().any(|network| network.contains(&client_ip)) {
+        log::debug!(
+            "ip reject: flow_id={} client_ip={} reason=denylisted",
+            flow.flow_id,
+            client_ip
+        );
+        return false;
+    }
+
+    if !allow.is_empty() && !allow.iter().any(|network| network.contains(&client_ip)) {
+        log::debug!(
+            "ip reject: flow_id={} client_ip={} reason=not_allowlisted",
+            flow.flow_id,
+            client_ip
+        );
+        return false;
+    }
+
+    true
+}
+
+fn reject_invalid(flow: &ValidationFlow, setting: &str, entry: &str) -> bool {
+    log::warn!(
+        "ip config invalid: flow_id={} setting={} entry={:?}",
+        flow.flow_id,
+        setting,
+        entry
+    );
+    false
+}
+
+/// Returns the configured networks, or the first entry that could not be parsed.
+fn flow_networks(flow: &ValidationFlow, flow_setting_id: &str) -> Result<Vec<IpNet>, String> {
+    let Some(value) = flow
+        .settings
+        .iter()
+        .find(|setting| setting.flow_setting_id == flow_setting_id)
+        .and_then(|setting| setting.value.as_ref())
+    else {
+        return Ok(Vec::new());
+    };
+
+    let entries: Vec<&str> = match value.kind.as_ref() {
+        Some(Kind::StringValue(raw)) => raw.split(',').collect(),
+        Some(Kind::ListValue(ListValue { values })) => values
+            .iter()
+            .map(|value| match value.kind.as_ref() {
+                Some(Kind::StringValue(raw)) => Ok(raw.as_str()),
+                _ => Err(format!("{:?}", value)),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(Kind::NullValue(_)) | None => Vec::new(),
+        Some(_) => return Err(format!("{:?}", value)),
+    };
+
+    entries
+        .into_iter()
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| parse_network(entry).ok_or_else(|| entry.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use tucana::shared::{FlowSetting, ListValue, ValidationFlow, Value, value::Kind};
+
+    use super::is_allowed;
+
+    #[test]
+    fn flow_without_settings_allows_everyone() {
+        assert!(is_allowed(&flow(vec![]), ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn allowlist_only_accepts_listed_networks() {
+        let flow = flow(vec![(
+            "httpIpAllow",
+            list_value(&["10.0.0.0/8", "2001:db8::/32"]),
+        )]);
+
+        assert!(is_allowed(&flow, ip("10.1.2.3")));
+        assert!(is_allowed(&flow, ip("2001:db8::1")));
+        assert!(!is_allowed(&flow, ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn denylist_wins_over_allowlist() {
+        let flow = flow(vec![
+            ("httpIpAllow", string_value("10.0.0.0/8")),
+            ("httpIpDeny", string_value("10.0.0.5, 10.9.0.0/16")),
+        ]);
+
+        assert!(is_allowed(&flow, ip("10.1.2.3")));
+        assert!(!is_allowed(&flow, ip("10.0.0.5")));
+        assert!(!is_allowed(&flow, ip("10.9.1.1")));
+    }
+
+    #[test]
+    fn invalid_entry_rejects_all_requests() {
+        let flow = flow(vec![("httpIpDeny", list_value(&["10.0.0.0/8", "nope"]))]);
+
+        assert!(!is_allowed(&flow, ip("203.0.113.7")));
+    }
+
+    fn flow(settings: Vec<(&str, Value)>) -> ValidationFlow {
+        ValidationFlow {
+            flow_id: 1,
+            settings: settings
+                .into_iter()
+                .map(|(flow_setting_id, value)| FlowSetting {
+                    database_id: None,
+                    flow_setting_id: flow_setting_id.to_string(),
+                    value: Some(value),
+                    cast: None,
+                })
+                .collect(),
+            ..ValidationFlow::default()
+        }
+    }
+
+    fn list_value(values: &[&str]) -> Value {
+        Value {
+            kind: Some(Kind::ListValue(ListValue {
+                values: values.iter().map(|value| string_value(value)).collect(),
+            })),
+        }
+    }
+
+    fn string_value(value: &str) -> Value {
+        Value {
+            kind: Some(Kind::StringValue(value.to_string())),
+        }
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+}
@@ -1,33 +1,52 @@
 use base::{
+    health::ComponentStatus,
     runner::{ServerContext, ServerRunner},
     traits::Server as ServerTrait,
 };
 use code0_flow::flow_service::ModuleDefinitionAppendix;
+use connection::ConnectionInfo;
 use hyper::server::conn::http1;
 use hyper_util::rt::TokioIo;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 use tonic::async_trait;
 use tucana::shared::{Endpoint, ModuleDefinition};
 
+mod access_log;
 mod auth;
+mod client_ip;
 mod config;
+mod connection;
 mod content_type;
+mod ip_filter;
+mod metrics;
 mod request;
 mod response;
 mod route;
+mod state;
+mod telemetry;
+mod tls;
+
+/// Readiness component of the HTTP listener.
+const LISTENER_COMPONENT: &str = "http_listener";
+/// How long the access log may take to write its queued lines on shutdown.
+const ACCESS_LOG_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     let server = HttpServer {
         shutdown_tx: None,
         addr: None,
+        tls_acceptor: None,
+        state: None,
     };
-    let runner = match ServerRunner::new(server).await {
-        Ok(runner) => runner,
-        Err(err) => panic!("Failed to create server runner: {:?}", err),
-    };
+    let runner = ServerRunner::new(server)
+        .await
+        .map_err(|e| e.context("failed to create server runner"))?;
     log::info!("Successfully created runner for http service");
 
     let external_addr = runner.get_server_config().external_port;
@@ -46,21 +65,25 @@ async fn main() {
             )),
         }],
     }];
-    match runner.serve(configs).await {
-        Ok(_) => (),
-        Err(err) => panic!("Failed to start server runner: {:?}", err),
-    };
+    runner
+        .serve(configs)
+        .await
+        .map_err(|e| e.context("failed to run server runner"))
 }
 
 struct HttpServer {
     shutdown_tx: Option<tokio::sync::broadcast::Sender<()>>,
     addr: Option<SocketAddr>,
+    tls_acceptor: Option<TlsAcceptor>,
+    state: Option<Arc<state::HttpState>>,
 }
 
 #[async_trait]
 impl ServerTrait<config::HttpServerConfig> for HttpServer {
     async fn init(&mut self, ctx: &ServerContext<config::HttpServerConfig>) -> anyhow::Result<()> {
         log::info!("Initializing http server");
+        ctx.readiness
+            .set(LISTENER_COMPONENT, ComponentStatus::Starting);
 
         let (shutdown_tx, _) = tokio::sync::broadcast::channel(1);
         self.shutdown_tx = Some(shutdown_tx);
@@ -72,6 +95,43 @@ impl ServerTrait<config::HttpServerConfig> for HttpServer {
                 .map_err(|e| anyhow::anyhow!("Invalid bind address '{}': {}", bind, e))?,
         );
 
+        self.tls_acceptor = match (
+            &ctx.server_config.tls_cert_path,
+            &ctx.server_config.tls_key_path,
+        ) {
+            (Some(cert_path), Some(key_path)) => {
+                log::info!("TLS enabled, client certificates will be requested");
+                Some(tls::create_acceptor(cert_path, key_path)?)
+            }
+            (None, None) => None,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "HTTP_SERVER_TLS_CERT_PATH and HTTP_SERVER_TLS_KEY_PATH must be set together"
+                ));
+            }
+        };
+
+        let metrics = metrics::RestMetrics::register(ctx.metrics.registry())?;
+        let access_log = access_log::AccessLog::from_config(
+            &ctx.server_config.access_log,
+            metrics.access_log_dropped_lines.clone(),
+        )?;
+        self.state = Some(Arc::new(state::HttpState {
+            store: Arc::clone(&ctx.adapter_store),
+            introspection: auth::IntrospectionClient::new(
+                ctx.server_config.introspection_allow_http,
+            )?,
+            mtls: auth::MtlsVerifiers::new(),
+            trusted_proxies: config::trusted_proxies(&ctx.server_config.trusted_proxies).map_err(
+                |entry| anyhow::anyhow!("Invalid HTTP_TRUSTED_PROXIES entry '{}'", entry),
+            )?,
+            metrics,
+            access_log,
+            error_templates: response::ErrorTemplates::load(
+                ctx.server_config.error_templates_path.as_deref(),
+            )?,
+        }));
+
         log::debug!("Initialized with Address: {:?}", self.addr);
         Ok(())
     }
@@ -80,10 +140,23 @@ impl ServerTrait<config::HttpServerConfig> for HttpServer {
         let addr = self
             .addr
             .expect("cannot start tcp listener with empty address");
+        let state = self
+            .state
+            .clone()
+            .expect("state not initialized; init() must run first");
 
-        let listener = TcpListener::bind(addr)
-            .await
-            .map_err(|e| anyhow::anyhow!("failed to bind {addr}: {e}"))?;
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                ctx.readiness.set(
+                    LISTENER_COMPONENT,
+                    ComponentStatus::Failed(format!("failed to bind {addr}: {err}")),
+                );
+                return Err(anyhow::anyhow!("failed to bind {addr}: {err}"));
+            }
+        };
+        ctx.readiness
+            .set(LISTENER_COMPONENT, ComponentStatus::Ready);
 
         // Create a receiver for this run loop
         let shutdown_tx = self
@@ -94,41 +167,60 @@ impl ServerTrait<config::HttpServerConfig> for HttpServer {
         let mut shutdown_rx = shutdown_tx.subscribe();
 
         loop {
-            let (stream, _) = tokio::select! {
+            let (stream, peer_addr) = tokio::select! {
                 _ = shutdown_rx.recv() => {
                     log::info!("HTTP server: shutdown received, stopping accept loop");
                     break;
                 }
+                _ = ctx.shutdown.requested() => {
+                    log::info!("HTTP server: shutdown requested, stopping accept loop");
+                    // Lets open connections finish their request and close.
+                    let _ = shutdown_tx.send(());
+                    break;
+                }
                 res = listener.accept() => {
                     res.map_err(|e| anyhow::anyhow!("accept failed: {e}"))?
                 }
             };
 
-            let io = TokioIo::new(stream);
-            let store = Arc::clone(&ctx.adapter_store);
-
-            let mut conn_shutdown_rx = shutdown_tx.subscribe();
+            let state = Arc::clone(&state);
+            let tls_acceptor = self.tls_acceptor.clone();
+            let conn_shutdown_rx = shutdown_tx.subscribe();
+            let in_flight = ctx
+                .in_flight
+                .track(format!("http connection from {}", peer_addr));
 
             tokio::spawn(async move {
-                let svc = hyper::service::service_fn(move |req| {
-                    let store = Arc::clone(&store);
-                    async move { request::handle(req, store).await }
-                });
+                // Dropped once the connection is closed.
+                let _in_flight = in_flight;
 
-                let conn = http1::Builder::new().serve_connection(io, svc);
+                let Some(acceptor) = tls_acceptor else {
+                    let connection = ConnectionInfo {
+                        peer_addr,
+                        client_certificate: None,
+                    };
+                    serve_connection(stream, connection, state, conn_shutdown_rx).await;
+                    return;
+                };
 
-                tokio::pin!(conn);
-
-                tokio::select! {
-                    res = conn.as_mut() => {
-                        if let Err(err) = res {
-                            log::error!("Error serving connection: {:?}", err);
-                        }
-                    }
-                    _ = conn_shutdown_rx.recv() => {
-                        conn.as_mut().graceful_shutdown();
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        log::warn!("TLS handshake failed: peer={} error={:?}", peer_addr, err);
+                        return;
                     }
-                }
+                };
+
+                let connection = ConnectionInfo {
+                    peer_addr,
+                    client_certificate: stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(tls::ClientCertificateChain::from_peer_certificates),
+                };
+
+                serve_connection(stream, connection, state, conn_shutdown_rx).await;
             });
         }
 
@@ -145,4 +237,68 @@ impl ServerTrait<config::HttpServerConfig> for HttpServer {
 
         Ok(())
     }
+
+    async fn drained(
+        &mut self,
+        _ctx: &ServerContext<config::HttpServerConfig>,
+    ) -> anyhow::Result<()> {
+        let Some(state) = self.state.clone() else {
+            return Ok(());
+        };
+        if state.access_log.is_none() {
+            return Ok(());
+        }
+
+        let flushed = tokio::task::spawn_blocking(move || {
+            state
+                .access_log
+                .as_ref()
+                .is_none_or(|access_log| access_log.flush(ACCESS_LOG_FLUSH_TIMEOUT))
+        })
+        .await?;
+        if !flushed {
+            return Err(anyhow::anyhow!(
+                "access log not flushed within {}s",
+                ACCESS_LOG_FLUSH_TIMEOUT.as_secs()
+            ));
+        }
+        Ok(())
+    }
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    connection: ConnectionInfo,
+    state: Arc<state::HttpState>,
+    mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(stream);
+    let connection = Arc::new(connection);
+
+    let svc = hyper::service::service_fn(move |req| {
+        let state = Arc::clone(&state);
+        let connection = Arc::clone(&connection);
+        async move { request::handle(req, state, connection).await }
+    });
+
+    let conn = http1::Builder::new().serve_connection(io, svc);
+
+    tokio::pin!(conn);
+
+    tokio::select! {
+        res = conn.as_mut() => {
+            if let Err(err) = res {
+                log::error!("Error serving connection: {:?}", err);
+            }
+        }
+        _ = shutdown_rx.recv() => {
+            // Finishes the request in flight before closing the connection.
+            conn.as_mut().graceful_shutdown();
+            if let Err(err) = conn.await {
+                log::error!("Error serving connection: {:?}", err);
+            }
+        }
+    }
 }
@@ -0,0 +1,136 @@
+use hyper::{Method, StatusCode};
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry};
+use std::time::Duration;
+
+/// Route label of requests that did not match a flow.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// HTTP metrics of the REST adapter, registered on the shared adapter registry.
+pub struct RestMetrics {
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    /// Access log lines dropped because the writer couldn't keep up.
+    pub access_log_dropped_lines: IntCounter,
+}
+
+impl RestMetrics {
+    pub fn register(registry: &Registry) -> anyhow::Result<Self> {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by method, route and status code",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time from receiving a request until its response is ready",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+            ]),
+            &["method", "route"],
+        )?;
+
+        let access_log_dropped_lines = IntCounter::new(
+            "http_access_log_dropped_lines_total",
+            "Access log lines dropped because the writer couldn't keep up",
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(access_log_dropped_lines.clone()))?;
+
+        Ok(Self {
+            requests,
+            request_duration,
+            access_log_dropped_lines,
+        })
+    }
+
+    pub fn observe(&self, method: &Method, route: &str, status: StatusCode, elapsed: Duration) {
+        let method = method_label(method);
+        self.requests
+            .with_label_values(&[method, route, status.as_str()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+// Extension methods are collapsed so clients can't create arbitrary label values.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "OTHER",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+    use prometheus::Registry;
+    use std::time::Duration;
+
+    use super::{RestMetrics, UNMATCHED_ROUTE};
+
+    #[test]
+    fn requests_are_counted_per_route_and_status() {
+        let registry = Registry::new();
+        let metrics = RestMetrics::register(&registry).unwrap();
+
+        metrics.observe(
+            &Method::GET,
+            "/project/users/:id",
+            StatusCode::OK,
+            Duration::from_millis(12),
+        );
+        metrics.observe(
+            &Method::GET,
+            "/project/users/:id",
+            StatusCode::OK,
+            Duration::from_millis(8),
+        );
+        metrics.observe(
+            &Method::from_bytes(b"PURGE").unwrap(),
+            UNMATCHED_ROUTE,
+            StatusCode::NOT_FOUND,
+            Duration::from_millis(1),
+        );
+
+        let families = registry.gather();
+        let requests = families
+            .iter()
+            .find(|family| family.name() == "http_requests_total")
+            .unwrap();
+        let counts = requests
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let labels = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| format!("{}={}", label.name(), label.value()))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                (labels, metric.get_counter().get_value())
+            })
+            .collect::<Vec<_>>();
+
+        assert!(counts.contains(&(
+            String::from("method=GET,route=/project/users/:id,status=200"),
+            2.0
+        )));
+        assert!(counts.contains(&(String::from("method=OTHER,route=unmatched,status=404"), 1.0)));
+    }
+}
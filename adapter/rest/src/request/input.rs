@@ -1,15 +1,27 @@
 use hyper::{HeaderMap, header::HeaderValue};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use tucana::shared::{Struct, ValidationFlow, Value, helper::value::ToValue, value::Kind};
 
+use crate::auth::Authentication;
 use crate::route;
 
+/// Request details that are passed to the flow next to the payload.
+pub(super) struct InputRequest<'a> {
+    pub(super) path: &'a str,
+    pub(super) query: Option<&'a str>,
+    pub(super) headers: &'a HeaderMap<HeaderValue>,
+    /// Client address after resolving trusted proxies.
+    pub(super) client_ip: IpAddr,
+    /// Id returned as `X-Request-Id` and attached to every log line of the request.
+    pub(super) request_id: &'a str,
+}
+
 pub(super) fn build_flow_input(
     flow: &ValidationFlow,
-    path: &str,
-    query: Option<&str>,
-    headers: &HeaderMap<HeaderValue>,
+    request: &InputRequest<'_>,
     payload: Option<Value>,
+    auth: Option<&Authentication>,
 ) -> Value {
     let mut fields = HashMap::new();
 
@@ -19,16 +31,28 @@ pub(super) fn build_flow_input(
 
     fields.insert(
         String::from("headers"),
-        string_map_to_value(header_map(headers)),
+        string_map_to_value(header_map(request.headers)),
     );
     fields.insert(
         String::from("query_params"),
-        string_map_to_value(query_params(query)),
+        string_map_to_value(query_params(request.query)),
     );
     fields.insert(
         String::from("path_params"),
-        string_map_to_value(route::extract_path_params(flow, path)),
+        string_map_to_value(route::extract_path_params(flow, request.path)),
+    );
+    fields.insert(
+        String::from("client_ip"),
+        request.client_ip.to_string().to_value(),
     );
+    fields.insert(
+        String::from("request_id"),
+        request.request_id.to_string().to_value(),
+    );
+
+    if let Some(auth) = auth {
+        fields.insert(String::from("auth"), auth.to_value());
+    }
 
     Value {
         kind: Some(Kind::StructValue(Struct { fields })),
@@ -74,8 +98,10 @@ fn string_map_to_value(map: HashMap<String, String>) -> Value {
 
 #[cfg(test)]
 mod tests {
-    use super::{build_flow_input, query_params, string_map_to_value};
+    use super::{InputRequest, build_flow_input, query_params, string_map_to_value};
+    use crate::auth::{Authentication, AuthenticationType};
     use hyper::HeaderMap;
+    use std::net::{IpAddr, Ipv4Addr};
     use tucana::shared::{FlowSetting, Struct, ValidationFlow, Value, value::Kind};
 
     #[test]
@@ -124,13 +150,15 @@ mod tests {
             ..ValidationFlow::default()
         };
 
-        let input = build_flow_input(
-            &flow,
-            "/project/users/42",
-            Some("search=hello+world"),
-            &HeaderMap::new(),
-            None,
-        );
+        let headers = HeaderMap::new();
+        let request = InputRequest {
+            path: "/project/users/42",
+            query: Some("search=hello+world"),
+            headers: &headers,
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            request_id: "req-1",
+        };
+        let input = build_flow_input(&flow, &request, None, None);
 
         assert_eq!(
             nested_string_field(&input, "query_params", "search"),
@@ -142,6 +170,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn flow_input_contains_accepted_auth_method() {
+        let headers = HeaderMap::new();
+        let request = InputRequest {
+            path: "/project/users",
+            query: None,
+            headers: &headers,
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            request_id: "req-1",
+        };
+        let input = build_flow_input(
+            &ValidationFlow::default(),
+            &request,
+            None,
+            Some(&Authentication::new(AuthenticationType::Basic)),
+        );
+
+        assert_eq!(nested_string_field(&input, "auth", "method"), Some("basic"));
+    }
+
+    #[test]
+    fn flow_input_contains_client_ip_and_request_id() {
+        let headers = HeaderMap::new();
+        let request = InputRequest {
+            path: "/project/users",
+            query: None,
+            headers: &headers,
+            client_ip: "203.0.113.7".parse().unwrap(),
+            request_id: "req-1",
+        };
+        let input = build_flow_input(&ValidationFlow::default(), &request, None, None);
+
+        let Some(Kind::StructValue(Struct { fields })) = input.kind.as_ref() else {
+            panic!("expected struct value");
+        };
+        assert_eq!(
+            fields
+                .get("client_ip")
+                .and_then(|value| value.kind.as_ref()),
+            Some(&Kind::StringValue("203.0.113.7".to_string()))
+        );
+        assert_eq!(
+            fields
+                .get("request_id")
+                .and_then(|value| value.kind.as_ref()),
+            Some(&Kind::StringValue("req-1".to_string()))
+        );
+    }
+
     fn nested_string_field<'a>(value: &'a Value, field: &str, nested: &str) -> Option<&'a str> {
         let Some(Kind::StructValue(Struct { fields })) = value.kind.as_ref() else {
             return None;
@@ -1,44 +1,145 @@
 mod input;
+mod request_id;
 
 use base::store::FlowIdentifyResult;
 use http_body_util::{BodyExt, Full};
 use hyper::{
-    Request, Response, StatusCode,
-    body::{Bytes, Incoming},
+    HeaderMap, Request, Response,
+    body::{Body, Bytes, Incoming},
+    header::HeaderValue,
+};
+use opentelemetry::{
+    KeyValue,
+    trace::{FutureExt, TraceContextExt},
 };
 use std::convert::Infallible;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Instant;
+use tucana::shared::ValidationFlow;
 
+use crate::access_log::AccessLogEntry;
 use crate::auth::{authenticate_header_name, validate_flow_auth};
+use crate::client_ip::resolve_client_ip;
+use crate::connection::ConnectionInfo;
 use crate::content_type;
-use crate::response::{error_to_http_response, flow_execution_to_http_response};
-use crate::route::{self, RequestRoute};
+use crate::ip_filter;
+use crate::metrics::UNMATCHED_ROUTE;
+use crate::response::{
+    ErrorContext, ErrorTemplates, Problem, ProblemType, flow_execution_to_http_response,
+    store_unavailable,
+};
+use crate::route::{self, MatchedFlow, RequestRoute};
+use crate::state::HttpState;
+use crate::telemetry;
 
 pub async fn handle(
     req: Request<Incoming>,
-    store: Arc<base::store::AdapterStore>,
+    state: Arc<HttpState>,
+    connection: Arc<ConnectionInfo>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let started = Instant::now();
+    let received_at = chrono::Utc::now();
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let version = req.version();
+    // Only the access log needs the headers once the request is consumed.
+    let headers = state.access_log.as_ref().map(|_| req.headers().clone());
+    let client_ip = resolve_client_ip(
+        connection.peer_addr.ip(),
+        req.headers(),
+        &state.trusted_proxies,
+    );
+    let request_id = request_id::resolve(req.headers());
+    let cx = telemetry::start_request_span(req.method(), req.uri().path(), req.headers());
+    cx.span()
+        .set_attribute(KeyValue::new("draco.request_id", request_id.clone()));
+
+    let Ok(mut response) = base::context::with_request_id(
+        request_id.clone(),
+        handle_request(
+            req,
+            Arc::clone(&state),
+            connection,
+            client_ip,
+            request_id.clone(),
+        )
+        .with_context(cx.clone()),
+    )
+    .await;
+    telemetry::record_response(&cx, response.status());
+
+    let elapsed = started.elapsed();
+    let matched_flow = response.extensions().get::<MatchedFlow>();
+    let route = matched_flow
+        .and_then(|flow| flow.route.as_deref())
+        .unwrap_or(UNMATCHED_ROUTE);
+    state
+        .metrics
+        .observe(&method, route, response.status(), elapsed);
+
+    if let (Some(access_log), Some(headers)) = (&state.access_log, &headers) {
+        access_log.record(&AccessLogEntry {
+            timestamp: received_at,
+            client_ip,
+            request_id: &request_id,
+            method: &method,
+            path: uri.path(),
+            query: uri.query(),
+            version,
+            headers,
+            status: response.status(),
+            bytes: response.body().size_hint().exact(),
+            duration: elapsed,
+            flow_id: matched_flow.map(|flow| flow.flow_id),
+        });
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(request_id::REQUEST_ID_HEADER, value);
+    }
+
+    Ok(response)
+}
+
+async fn handle_request(
+    req: Request<Incoming>,
+    state: Arc<HttpState>,
+    connection: Arc<ConnectionInfo>,
+    client_ip: IpAddr,
+    request_id: String,
 ) -> Result<Response<Full<Bytes>>, Infallible> {
     let method = req.method().clone();
     let path = req.uri().path().to_string();
     let query = req.uri().query().map(str::to_owned);
     let headers = req.headers().clone();
+    log::debug!(
+        "Received request: method={} path={} client_ip={}",
+        method,
+        path,
+        client_ip
+    );
+    let errors = error_context(&state.error_templates, &path, &request_id);
 
     let body_bytes = match BodyExt::collect(req.into_body()).await {
         Ok(collected) => collected.to_bytes().to_vec(),
         Err(err) => {
             log::error!("Failed to read request body: {}", err);
-            return Ok(error_to_http_response(
-                StatusCode::BAD_REQUEST,
-                "Failed to read request body",
-            ));
+            return Ok(
+                Problem::new(ProblemType::RequestBodyUnreadable, err.to_string())
+                    .into_response(&errors),
+            );
         }
     };
 
     let Some(slug) = route::extract_slug_from_path(&path) else {
-        return Ok(error_to_http_response(
-            StatusCode::BAD_REQUEST,
-            "Missing slug in path",
-        ));
+        return Ok(Problem::new(
+            ProblemType::MissingProjectSlug,
+            "The path does not start with a project slug",
+        )
+        .into_response(&errors));
     };
 
     let pattern = format!("REST.{}.*", slug);
@@ -47,50 +148,169 @@ pub async fn handle(
         method,
     };
 
-    let response = match store.get_possible_flow_match(pattern, route).await {
-        FlowIdentifyResult::Single(flow) => {
-            if let Err(err) = validate_flow_auth(&flow, &headers) {
-                let mut response = error_to_http_response(err.status_code(), err.message());
-                response
-                    .headers_mut()
-                    .insert(authenticate_header_name(), err.challenge());
-                return Ok(response);
-            }
+    // A failed key scan looks like an unknown route, answer 503 instead of 404.
+    if !state.store.is_connected() {
+        return Ok(store_unavailable(&errors));
+    }
 
-            let request_body_value = match parse_request_body(&headers, &body_bytes) {
-                Ok(value) => value,
-                Err(response) => return Ok(response),
+    let response = match state.store.get_possible_flow_match(pattern, route).await {
+        FlowIdentifyResult::Single(flow) => {
+            let matched_flow = MatchedFlow {
+                flow_id: flow.flow_id,
+                route: route::flow_route(&flow),
+            };
+            let request = input::InputRequest {
+                path: &path,
+                query: query.as_deref(),
+                headers: &headers,
+                client_ip,
+                request_id: &request_id,
             };
 
-            let input = input::build_flow_input(
-                &flow,
-                &path,
-                query.as_deref(),
-                &headers,
-                request_body_value,
-            );
-
-            flow_execution_to_http_response(flow, input, store).await
+            let mut response =
+                handle_flow(flow, &request, &body_bytes, &state, &connection, errors).await;
+            response.extensions_mut().insert(matched_flow);
+            response
+        }
+        _ => {
+            Problem::new(ProblemType::FlowNotFound, "No flow found for path").into_response(&errors)
         }
-        _ => error_to_http_response(StatusCode::NOT_FOUND, "No flow found for path"),
     };
 
     Ok(response)
 }
 
+/// Error context of a request, with the template of the project in its path,
+/// so errors raised before a flow matched use it as well.
+fn error_context(templates: &ErrorTemplates, path: &str, request_id: &str) -> ErrorContext {
+    let errors = ErrorContext {
+        instance: Some(path.to_string()),
+        request_id: Some(request_id.to_string()),
+        template: None,
+    };
+
+    match route::extract_slug_from_path(path) {
+        Some(slug) => errors.with_project(templates, slug),
+        None => errors,
+    }
+}
+
+async fn handle_flow(
+    flow: ValidationFlow,
+    request: &input::InputRequest<'_>,
+    body_bytes: &[u8],
+    state: &HttpState,
+    connection: &ConnectionInfo,
+    errors: ErrorContext,
+) -> Response<Full<Bytes>> {
+    if !ip_filter::is_allowed(&flow, request.client_ip) {
+        return Problem::new(
+            ProblemType::ClientAddressForbidden,
+            format!(
+                "Requests from {} are not allowed for this flow",
+                request.client_ip
+            ),
+        )
+        .into_response(&errors);
+    }
+
+    let auth = match validate_flow_auth(
+        &flow,
+        request.headers,
+        connection,
+        &state.introspection,
+        &state.mtls,
+    )
+    .await
+    {
+        Ok(auth) => auth,
+        Err(err) => {
+            let mut response =
+                Problem::new(err.problem_type(), err.message()).into_response(&errors);
+            for challenge in err.challenges() {
+                response
+                    .headers_mut()
+                    .append(authenticate_header_name(), challenge);
+            }
+            return response;
+        }
+    };
+
+    let request_body_value = match parse_request_body(request.headers, body_bytes) {
+        Ok(value) => value,
+        Err(problem) => return problem.into_response(&errors),
+    };
+
+    let input = input::build_flow_input(&flow, request, request_body_value, auth.as_ref());
+
+    flow_execution_to_http_response(flow, input, Arc::clone(&state.store), &errors).await
+}
+
 fn parse_request_body(
-    headers: &hyper::HeaderMap<hyper::header::HeaderValue>,
+    headers: &HeaderMap<HeaderValue>,
     body_bytes: &[u8],
-) -> Result<Option<tucana::shared::Value>, Response<Full<Bytes>>> {
+) -> Result<Option<tucana::shared::Value>, Problem> {
     content_type::parse_body_from_headers(headers, body_bytes).map_err(|err| {
         log::warn!("Failed to parse request body: {}", err);
-        let status_code = match err {
+        let problem_type = match err {
             content_type::BodyParseError::UnsupportedContentType { .. } => {
-                StatusCode::UNSUPPORTED_MEDIA_TYPE
+                ProblemType::UnsupportedMediaType
             }
-            _ => StatusCode::BAD_REQUEST,
+            _ => ProblemType::MalformedRequestBody,
         };
 
-        error_to_http_response(status_code, &err.to_string())
+        Problem::new(problem_type, err.to_string())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use hyper::{StatusCode, header::CONTENT_TYPE};
+    use serde_json::json;
+
+    use super::error_context;
+    use crate::response::{ErrorTemplates, Problem, ProblemType, store_unavailable};
+
+    fn templates() -> ErrorTemplates {
+        ErrorTemplates::from_json(
+            r#"{"shop": {"code": "{{status}}", "message": "{{detail}}", "path": "{{instance}}"}}"#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unknown_route_of_templated_project_uses_the_template() {
+        let errors = error_context(&templates(), "/shop/unknown", "req-1");
+
+        let response = Problem::new(ProblemType::FlowNotFound, "No flow found for path")
+            .into_response(&errors);
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!({"code": 404, "message": "No flow found for path", "path": "/shop/unknown"})
+        );
+    }
+
+    #[test]
+    fn errors_before_a_flow_matched_use_the_project_template() {
+        let response = store_unavailable(&error_context(&templates(), "/shop/orders", "req-1"));
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let response = store_unavailable(&error_context(&templates(), "/other/orders", "req-1"));
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        assert!(error_context(&templates(), "/", "req-1").template.is_none());
+    }
+}
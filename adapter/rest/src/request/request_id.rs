@@ -0,0 +1,72 @@
+use hyper::{
+    HeaderMap,
+    header::{HeaderName, HeaderValue},
+};
+
+pub(super) const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Returns the caller supplied `X-Request-Id`, or a newly generated id if the
+/// header is missing or not a short, printable ASCII token.
+pub(super) fn resolve(headers: &HeaderMap<HeaderValue>) -> String {
+    let incoming = headers
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim);
+
+    match incoming {
+        Some(request_id) if is_valid(request_id) => request_id.to_string(),
+        Some(request_id) => {
+            let generated = base::context::generate_request_id();
+            log::debug!(
+                "Replacing invalid incoming request id: incoming={:?} request_id={}",
+                request_id,
+                generated
+            );
+            generated
+        }
+        None => base::context::generate_request_id(),
+    }
+}
+
+fn is_valid(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{HeaderMap, header::HeaderValue};
+
+    use super::{REQUEST_ID_HEADER, resolve};
+
+    #[test]
+    fn incoming_request_id_is_kept() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123"));
+
+        assert_eq!(resolve(&headers), "abc-123");
+    }
+
+    #[test]
+    fn missing_request_id_is_generated() {
+        let first = resolve(&HeaderMap::new());
+        let second = resolve(&HeaderMap::new());
+
+        assert!(!first.is_empty());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn invalid_request_id_is_replaced() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("has spaces"));
+        assert_ne!(resolve(&headers), "has spaces");
+
+        let too_long = "a".repeat(129);
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&too_long).unwrap());
+        assert_ne!(resolve(&headers), too_long);
+    }
+}
@@ -11,71 +11,85 @@ use tucana::shared::{
     value::Kind::{self, StructValue},
 };
 
+mod problem;
+
+pub use problem::{ErrorContext, ErrorTemplates, Problem, ProblemType};
+
 use crate::content_type;
-use base::store::FlowExecutionResult;
+use base::store::{AdapterStore, FlowExecutionResult};
+
+/// `Retry-After` seconds sent while the adapter is disconnected from NATS.
+pub const STORE_UNAVAILABLE_RETRY_AFTER_SECS: u64 = 5;
+
+/// Response for requests that can't be served while NATS is unreachable.
+///
+/// The client reconnects on its own, so the outage is reported as temporary.
+pub fn store_unavailable(errors: &ErrorContext) -> Response<Full<Bytes>> {
+    Problem::new(
+        ProblemType::FlowStoreUnavailable,
+        "The flow store is temporarily unreachable",
+    )
+    .with_retry_after(STORE_UNAVAILABLE_RETRY_AFTER_SECS)
+    .into_response(errors)
+}
 
 pub async fn flow_execution_to_http_response(
     flow: ValidationFlow,
     input: Value,
-    store: Arc<base::store::AdapterStore>,
+    store: Arc<AdapterStore>,
+    errors: &ErrorContext,
 ) -> Response<Full<Bytes>> {
     match store.execute_flow_with_emitter(flow, Some(input)).await {
         FlowExecutionResult::Ongoing(result) => {
             log::debug!("Received first ongoing response from emitter");
-            value_to_http_response(result)
+            value_to_http_response(result, errors)
         }
         FlowExecutionResult::Failed => {
             log::error!("Flow execution failed event received from emitter");
-            error_to_http_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            Problem::new(
+                ProblemType::FlowExecutionFailed,
+                "The flow reported a failed execution",
+            )
+            .into_response(errors)
         }
         FlowExecutionResult::FinishedWithoutOngoing => Response::builder()
             .status(StatusCode::NO_CONTENT)
             .body(Full::new(Bytes::new()))
             .unwrap(),
+        FlowExecutionResult::TransportError if !store.is_connected() => {
+            log::warn!("Flow execution failed while disconnected from NATS");
+            store_unavailable(errors)
+        }
         FlowExecutionResult::TransportError => {
             log::error!("Flow execution transport error");
-            error_to_http_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            Problem::new(
+                ProblemType::FlowTransportFailed,
+                "The flow execution could not be delivered to the runtime",
+            )
+            .into_response(errors)
         }
     }
 }
 
-pub fn error_to_http_response(status: StatusCode, msg: &str) -> Response<Full<Bytes>> {
-    let body = format!(r#"{{"error": "{}"}}"#, msg);
-    Response::builder()
-        .status(status)
-        .header("content-type", "application/json")
-        .body(Full::new(Bytes::from(body)))
-        .unwrap()
-}
+pub fn value_to_http_response(value: Value, errors: &ErrorContext) -> Response<Full<Bytes>> {
+    let invalid_result =
+        |detail: &str| Problem::new(ProblemType::InvalidFlowResult, detail).into_response(errors);
 
-pub fn value_to_http_response(value: Value) -> Response<Full<Bytes>> {
     let Value {
         kind: Some(StructValue(Struct { fields })),
     } = value
     else {
-        return error_to_http_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Flow result was not a struct",
-        );
+        return invalid_result("Flow result was not a struct");
     };
 
     let Some(headers_val) = fields.get("headers") else {
-        return error_to_http_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Flow result missing the field: headers",
-        );
+        return invalid_result("Flow result missing the field: headers");
     };
     let Some(status_code_val) = fields.get("http_status_code") else {
-        return error_to_http_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Flow result missing the field: http_status_code",
-        );
+        return invalid_result("Flow result missing the field: http_status_code");
     };
     let Some(payload_val) = fields.get("payload") else {
-        return error_to_http_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Flow result missing the field: payload",
-        );
+        return invalid_result("Flow result missing the field: payload");
     };
 
     let Value {
@@ -84,10 +98,7 @@ pub fn value_to_http_response(value: Value) -> Response<Full<Bytes>> {
         })),
     } = headers_val
     else {
-        return error_to_http_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "headers was not a list of header entries",
-        );
+        return invalid_result("headers was not a list of header entries");
     };
 
     let mut http_headers: HashMap<String, String> = header_fields
@@ -109,10 +120,7 @@ pub fn value_to_http_response(value: Value) -> Response<Full<Bytes>> {
     }
 
     let Some(Kind::NumberValue(code)) = status_code_val.kind else {
-        return error_to_http_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "status_code was not a number",
-        );
+        return invalid_result("status_code was not a number");
     };
 
     let content_type_header = find_header_value_case_insensitive(&http_headers, "content-type");
@@ -120,10 +128,11 @@ pub fn value_to_http_response(value: Value) -> Response<Full<Bytes>> {
         Ok(body) => body,
         Err(err) => {
             log::error!("Failed to encode response payload: {}", err);
-            return error_to_http_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to encode response payload",
-            );
+            return Problem::new(
+                ProblemType::ResponseEncodingFailed,
+                format!("Failed to encode response payload: {}", err),
+            )
+            .into_response(errors);
         }
     };
 
@@ -133,10 +142,7 @@ pub fn value_to_http_response(value: Value) -> Response<Full<Bytes>> {
             tucana::shared::number_value::Number::Float(float) => float as u16,
         },
         None => {
-            return error_to_http_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Flow execution failed",
-            );
+            return invalid_result("http_status_code was empty");
         }
     };
 
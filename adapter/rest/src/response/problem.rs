@@ -0,0 +1,413 @@
+use http_body_util::Full;
+use hyper::{
+    Response, StatusCode,
+    body::Bytes,
+    header::{CONTENT_TYPE, RETRY_AFTER},
+};
+use serde_json::{Map, Value as JsonValue, json};
+use std::collections::HashMap;
+
+/// Prefix of the `type` URI of every problem the adapter reports itself.
+pub const PROBLEM_TYPE_PREFIX: &str = "urn:draco:problem:";
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+const TEMPLATE_CONTENT_TYPE: &str = "application/json";
+
+/// Failures raised by the adapter itself. Every variant has a stable `type` URI
+/// clients can match on, independent of the human readable `detail`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ProblemType {
+    RequestBodyUnreadable,
+    MissingProjectSlug,
+    FlowNotFound,
+    ClientAddressForbidden,
+    AuthenticationRequired,
+    InvalidCredentials,
+    AuthorizationServerUnavailable,
+    AuthorizationMisconfigured,
+    UnsupportedMediaType,
+    MalformedRequestBody,
+    FlowExecutionFailed,
+    FlowTransportFailed,
+    FlowStoreUnavailable,
+    InvalidFlowResult,
+    ResponseEncodingFailed,
+}
+
+impl ProblemType {
+    pub fn slug(self) -> &'static str {
+        match self {
+            Self::RequestBodyUnreadable => "request-body-unreadable",
+            Self::MissingProjectSlug => "missing-project-slug",
+            Self::FlowNotFound => "flow-not-found",
+            Self::ClientAddressForbidden => "client-address-forbidden",
+            Self::AuthenticationRequired => "authentication-required",
+            Self::InvalidCredentials => "invalid-credentials",
+            Self::AuthorizationServerUnavailable => "authorization-server-unavailable",
+            Self::AuthorizationMisconfigured => "authorization-misconfigured",
+            Self::UnsupportedMediaType => "unsupported-media-type",
+            Self::MalformedRequestBody => "malformed-request-body",
+            Self::FlowExecutionFailed => "flow-execution-failed",
+            Self::FlowTransportFailed => "flow-transport-failed",
+            Self::FlowStoreUnavailable => "flow-store-unavailable",
+            Self::InvalidFlowResult => "invalid-flow-result",
+            Self::ResponseEncodingFailed => "response-encoding-failed",
+        }
+    }
+
+    pub fn type_uri(self) -> String {
+        format!("{}{}", PROBLEM_TYPE_PREFIX, self.slug())
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::RequestBodyUnreadable => "Request body could not be read",
+            Self::MissingProjectSlug => "Missing project slug",
+            Self::FlowNotFound => "No flow found",
+            Self::ClientAddressForbidden => "Client address not allowed",
+            Self::AuthenticationRequired => "Authentication required",
+            Self::InvalidCredentials => "Invalid credentials",
+            Self::AuthorizationServerUnavailable => "Authorization server unavailable",
+            Self::AuthorizationMisconfigured => "Authorization misconfigured",
+            Self::UnsupportedMediaType => "Unsupported media type",
+            Self::MalformedRequestBody => "Malformed request body",
+            Self::FlowExecutionFailed => "Flow execution failed",
+            Self::FlowTransportFailed => "Flow could not be executed",
+            Self::FlowStoreUnavailable => "Flow store unavailable",
+            Self::InvalidFlowResult => "Invalid flow result",
+            Self::ResponseEncodingFailed => "Response could not be encoded",
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::RequestBodyUnreadable | Self::MissingProjectSlug | Self::MalformedRequestBody => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::AuthenticationRequired | Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::ClientAddressForbidden => StatusCode::FORBIDDEN,
+            Self::FlowNotFound => StatusCode::NOT_FOUND,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::AuthorizationServerUnavailable | Self::FlowStoreUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::AuthorizationMisconfigured
+            | Self::FlowExecutionFailed
+            | Self::FlowTransportFailed
+            | Self::InvalidFlowResult
+            | Self::ResponseEncodingFailed => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Request scoped information every error response carries.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorContext {
+    /// Path of the failed request, reported as the problem `instance`.
+    pub instance: Option<String>,
+    pub request_id: Option<String>,
+    /// Error body template of the request's project, see [`ErrorTemplates`].
+    pub template: Option<JsonValue>,
+}
+
+impl ErrorContext {
+    /// Picks up the error template of the project the request is addressed to.
+    pub fn with_project(mut self, templates: &ErrorTemplates, slug: &str) -> Self {
+        self.template = templates.get(slug).cloned();
+        self
+    }
+}
+
+/// Error body templates of the projects, keyed by project slug.
+///
+/// Read from a JSON object mapping each slug to its template, see
+/// [`render_template`] for the placeholders.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorTemplates {
+    templates: HashMap<String, JsonValue>,
+}
+
+impl ErrorTemplates {
+    pub fn from_json(raw: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            templates: serde_json::from_str(raw)?,
+        })
+    }
+
+    /// Reads the templates from the file at `path`, if one is configured.
+    pub fn load(path: Option<&str>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        let raw = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read error templates {}: {}", path, err))?;
+        Self::from_json(&raw)
+            .map_err(|err| anyhow::anyhow!("invalid error templates {}: {}", path, err))
+    }
+
+    pub fn get(&self, slug: &str) -> Option<&JsonValue> {
+        self.templates.get(slug)
+    }
+}
+
+/// An RFC 9457 problem detail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub problem_type: ProblemType,
+    pub detail: String,
+    /// Seconds after which the client may retry, sent as `Retry-After`.
+    pub retry_after: Option<u64>,
+}
+
+impl Problem {
+    pub fn new(problem_type: ProblemType, detail: impl Into<String>) -> Self {
+        Self {
+            problem_type,
+            detail: detail.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.problem_type.status()
+    }
+
+    fn to_json(&self, ctx: &ErrorContext) -> JsonValue {
+        let mut body = Map::new();
+        body.insert("type".into(), json!(self.problem_type.type_uri()));
+        body.insert("title".into(), json!(self.problem_type.title()));
+        body.insert("status".into(), json!(self.status().as_u16()));
+        body.insert("detail".into(), json!(self.detail));
+        if let Some(instance) = &ctx.instance {
+            body.insert("instance".into(), json!(instance));
+        }
+        if let Some(request_id) = &ctx.request_id {
+            body.insert("request_id".into(), json!(request_id));
+        }
+
+        JsonValue::Object(body)
+    }
+
+    /// Renders the problem, using the project's error template if one is configured.
+    pub fn into_response(self, ctx: &ErrorContext) -> Response<Full<Bytes>> {
+        let problem = self.to_json(ctx);
+        let (content_type, body) = match &ctx.template {
+            Some(template) => (TEMPLATE_CONTENT_TYPE, render_template(template, &problem)),
+            None => (PROBLEM_CONTENT_TYPE, problem),
+        };
+
+        let body = serde_json::to_vec(&body).unwrap_or_else(|err| {
+            log::error!("Failed to encode error response: {}", err);
+            Vec::new()
+        });
+
+        let mut response = Response::builder()
+            .status(self.status())
+            .header(CONTENT_TYPE, content_type);
+        if let Some(seconds) = self.retry_after {
+            response = response.header(RETRY_AFTER, seconds);
+        }
+        response.body(Full::new(Bytes::from(body))).unwrap()
+    }
+}
+
+/// Substitutes `{{type}}`, `{{title}}`, `{{status}}`, `{{detail}}`, `{{instance}}`
+/// and `{{request_id}}` in every string of the template.
+///
+/// A string consisting of a single placeholder is replaced by the raw value, so
+/// `"{{status}}"` becomes a number. Since substitution happens on the parsed
+/// template, the result is always valid JSON regardless of the values.
+fn render_template(template: &JsonValue, problem: &JsonValue) -> JsonValue {
+    match template {
+        JsonValue::String(text) => {
+            if let Some(field) = text
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|field| is_placeholder(field))
+            {
+                return problem.get(field).cloned().unwrap_or(JsonValue::Null);
+            }
+
+            let mut rendered = text.clone();
+            for field in PLACEHOLDERS {
+                let placeholder = format!("{{{{{}}}}}", field);
+                if rendered.contains(&placeholder) {
+                    rendered = rendered.replace(&placeholder, &placeholder_text(problem, field));
+                }
+            }
+            JsonValue::String(rendered)
+        }
+        JsonValue::Array(values) => JsonValue::Array(
+            values
+                .iter()
+                .map(|value| render_template(value, problem))
+                .collect(),
+        ),
+        JsonValue::Object(fields) => JsonValue::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_template(value, problem)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+const PLACEHOLDERS: [&str; 6] = [
+    "type",
+    "title",
+    "status",
+    "detail",
+    "instance",
+    "request_id",
+];
+
+fn is_placeholder(field: &str) -> bool {
+    PLACEHOLDERS.contains(&field)
+}
+
+fn placeholder_text(problem: &JsonValue, field: &str) -> String {
+    match problem.get(field) {
+        Some(JsonValue::String(text)) => text.clone(),
+        Some(JsonValue::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use hyper::{
+        StatusCode,
+        header::{CONTENT_TYPE, RETRY_AFTER},
+    };
+    use serde_json::{Value as JsonValue, json};
+
+    use super::{ErrorContext, ErrorTemplates, Problem, ProblemType};
+
+    #[tokio::test]
+    async fn problem_is_rendered_as_problem_json() {
+        let ctx = ErrorContext {
+            instance: Some("/project/users".to_string()),
+            request_id: Some("req-1".to_string()),
+            template: None,
+        };
+
+        let response =
+            Problem::new(ProblemType::FlowNotFound, "No flow found for path").into_response(&ctx);
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        assert_eq!(
+            body_json(response).await,
+            json!({
+                "type": "urn:draco:problem:flow-not-found",
+                "title": "No flow found",
+                "status": 404,
+                "detail": "No flow found for path",
+                "instance": "/project/users",
+                "request_id": "req-1",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn detail_with_quotes_stays_valid_json() {
+        let detail = r#"invalid JSON body: expected `"` at line 1 column 2 \ "#;
+        let response = Problem::new(ProblemType::MalformedRequestBody, detail)
+            .into_response(&ErrorContext::default());
+
+        let body = body_json(response).await;
+        assert_eq!(body["detail"], json!(detail));
+        assert!(body.get("instance").is_none());
+    }
+
+    #[tokio::test]
+    async fn project_error_template_overrides_body() {
+        let templates = ErrorTemplates::from_json(
+            r#"{"shop": {"error": {"code": "{{status}}", "message": "{{title}}: {{detail}}"}, "trace": "{{request_id}}"}}"#,
+        )
+        .unwrap();
+        let ctx = ErrorContext {
+            request_id: Some("req-1".to_string()),
+            ..ErrorContext::default()
+        }
+        .with_project(&templates, "shop");
+
+        let response =
+            Problem::new(ProblemType::ClientAddressForbidden, r#"ip "x""#).into_response(&ctx);
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            body_json(response).await,
+            json!({
+                "error": {"code": 403, "message": r#"Client address not allowed: ip "x""#},
+                "trace": "req-1",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_sent_as_header() {
+        let response = Problem::new(ProblemType::FlowStoreUnavailable, "NATS is unreachable")
+            .with_retry_after(5)
+            .into_response(&ErrorContext::default());
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "5");
+        assert_eq!(
+            body_json(response).await["type"],
+            json!("urn:draco:problem:flow-store-unavailable")
+        );
+    }
+
+    #[test]
+    fn templates_only_apply_to_their_project() {
+        let templates = ErrorTemplates::from_json(r#"{"shop": {"error": "{{detail}}"}}"#).unwrap();
+
+        assert!(
+            ErrorContext::default()
+                .with_project(&templates, "other")
+                .template
+                .is_none()
+        );
+        assert!(ErrorTemplates::from_json("{not json").is_err());
+        assert!(ErrorTemplates::load(None).unwrap().get("shop").is_none());
+    }
+
+    #[test]
+    fn problem_types_have_stable_uris() {
+        assert_eq!(
+            ProblemType::AuthenticationRequired.type_uri(),
+            "urn:draco:problem:authentication-required"
+        );
+        assert_eq!(
+            ProblemType::AuthorizationServerUnavailable.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            ProblemType::AuthorizationMisconfigured.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    async fn body_json(
+        response: hyper::Response<http_body_util::Full<hyper::body::Bytes>>,
+    ) -> JsonValue {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+}
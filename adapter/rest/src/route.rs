@@ -7,6 +7,14 @@ pub struct RequestRoute {
     pub method: hyper::Method,
 }
 
+/// Response extension describing the flow that handled a request.
+#[derive(Clone, Debug)]
+pub struct MatchedFlow {
+    pub flow_id: i64,
+    /// Route pattern of the flow, e.g. `/project/users/:id`.
+    pub route: Option<String>,
+}
+
 // Checks if the Method and Url matches any of the
 // Flows that matched the original slug pattern for project
 // Only if both matched, it will return true
@@ -85,6 +93,12 @@ pub fn extract_path_params(flow: &ValidationFlow, path: &str) -> HashMap<String,
     extract_named_route_captures(&flow_route_pattern(flow, flow_http_url), path)
 }
 
+/// The route pattern of a flow, e.g. `/project/users/:id`.
+pub fn flow_route(flow: &ValidationFlow) -> Option<String> {
+    extract_flow_setting_as_string(flow, "httpURL")
+        .map(|flow_http_url| flow_route_pattern(flow, flow_http_url))
+}
+
 fn flow_route_pattern(flow: &ValidationFlow, flow_http_url: &str) -> String {
     format!("/{}{}", flow.project_slug, flow_http_url)
 }
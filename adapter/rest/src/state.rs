@@ -0,0 +1,20 @@
+use base::store::AdapterStore;
+use ipnet::IpNet;
+use std::sync::Arc;
+
+use crate::access_log::AccessLog;
+use crate::auth::{IntrospectionClient, MtlsVerifiers};
+use crate::metrics::RestMetrics;
+use crate::response::ErrorTemplates;
+
+/// State shared by every request the HTTP adapter serves.
+pub struct HttpState {
+    pub store: Arc<AdapterStore>,
+    pub introspection: IntrospectionClient,
+    pub mtls: MtlsVerifiers,
+    pub trusted_proxies: Vec<IpNet>,
+    pub metrics: RestMetrics,
+    /// `None` if the access log is turned off.
+    pub access_log: Option<AccessLog>,
+    pub error_templates: ErrorTemplates,
+}
@@ -0,0 +1,82 @@
+use hyper::{HeaderMap, Method, StatusCode, header::HeaderValue};
+use opentelemetry::{
+    Context, KeyValue,
+    propagation::Extractor,
+    trace::{SpanKind, Status, TraceContextExt},
+};
+
+/// Starts the server span of a request, continuing the caller's trace if the
+/// request carries a `traceparent` header.
+pub fn start_request_span(
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap<HeaderValue>,
+) -> Context {
+    let parent = base::telemetry::extract_context(&HeaderExtractor(headers));
+    let cx = base::telemetry::start_span(method.to_string(), SpanKind::Server, &parent);
+
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("http.request.method", method.to_string()));
+    span.set_attribute(KeyValue::new("url.path", path.to_string()));
+
+    cx
+}
+
+pub fn record_response(cx: &Context, status: StatusCode) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new(
+        "http.response.status_code",
+        i64::from(status.as_u16()),
+    ));
+
+    if status.is_server_error() {
+        span.set_status(Status::error(status.to_string()));
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap<HeaderValue>);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{HeaderMap, Method, StatusCode, header::HeaderValue};
+    use opentelemetry::trace::{SpanKind, Status, TraceId};
+
+    use super::{record_response, start_request_span};
+
+    #[test]
+    fn request_span_continues_incoming_trace() {
+        let exporter = base::telemetry::testing::in_memory_exporter();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+
+        let cx = start_request_span(&Method::POST, "/project/users", &headers);
+        record_response(&cx, StatusCode::BAD_GATEWAY);
+        drop(cx);
+
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        let span = exporter
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .find(|span| span.span_context.trace_id() == trace_id)
+            .expect("request span should be exported");
+
+        assert_eq!(span.name, "POST");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(span.parent_span_id.to_string(), "00f067aa0ba902b7");
+        assert!(matches!(span.status, Status::Error { .. }));
+    }
+}
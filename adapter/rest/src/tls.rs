@@ -0,0 +1,105 @@
+use rustls::{
+    DigitallySignedStruct, DistinguishedName, Error, ServerConfig, SignatureScheme,
+    client::danger::HandshakeSignatureValid,
+    crypto::{CryptoProvider, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, PrivateKeyDer, UnixTime, pem::PemObject},
+    server::danger::{ClientCertVerified, ClientCertVerifier},
+};
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+
+/// Certificate chain presented by the client during the TLS handshake.
+///
+/// The chain is only checked for proof of key possession at the TLS layer.
+/// Trust is decided per flow, because every flow configures its own CA bundle.
+#[derive(Debug, Clone)]
+pub struct ClientCertificateChain {
+    pub end_entity: CertificateDer<'static>,
+    pub intermediates: Vec<CertificateDer<'static>>,
+}
+
+impl ClientCertificateChain {
+    pub fn from_peer_certificates(certificates: &[CertificateDer<'static>]) -> Option<Self> {
+        let (end_entity, intermediates) = certificates.split_first()?;
+
+        Some(Self {
+            end_entity: end_entity.clone(),
+            intermediates: intermediates.to_vec(),
+        })
+    }
+}
+
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Builds the TLS acceptor for the HTTP listener.
+///
+/// Client certificates are requested but not required, so flows without mTLS
+/// stay reachable over the same listener.
+pub fn create_acceptor(cert_path: &str, key_path: &str) -> anyhow::Result<TlsAcceptor> {
+    let certificates = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| anyhow::anyhow!("failed to read TLS certificate '{}': {}", cert_path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("invalid TLS certificate '{}': {}", cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| anyhow::anyhow!("failed to read TLS key '{}': {}", key_path, e))?;
+
+    let provider = crypto_provider();
+    let verifier = OptionalClientCertVerifier {
+        algorithms: provider.signature_verification_algorithms,
+    };
+
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_single_cert(certificates, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[derive(Debug)]
+struct OptionalClientCertVerifier {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ClientCertVerifier for OptionalClientCertVerifier {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
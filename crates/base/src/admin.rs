@@ -0,0 +1,329 @@
+//! Plain HTTP server for operational endpoints.
+//!
+//! Serves:
+//! - `GET /metrics`: Prometheus metrics of the adapter
+//! - `GET /healthz`: liveness, `503` once a component has failed
+//! - `GET /readyz`: readiness, `503` until every component is ready
+//! - `GET /diagnostics`: problems the adapter found in its flows
+//! - `GET /log-level`: the active log filter
+//! - `PUT /log-level`: replaces the log filter with the directives in the body,
+//!   e.g. `info,base::store=debug`
+//!
+//! Mutating endpoints require `Authorization: Bearer <ADMIN_TOKEN>` and are
+//! disabled without a configured token.
+//!
+//! The server is started by the [`ServerRunner`](crate::runner::ServerRunner)
+//! if [`AdapterConfig::admin_port`](crate::config::AdapterConfig::admin_port) is set.
+
+use crate::{
+    diagnostics::Diagnostics, health::Readiness, logging, metrics::Metrics,
+    secret::constant_time_eq,
+};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    HeaderMap, Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use std::{convert::Infallible, sync::Arc};
+use tokio::net::TcpListener;
+
+/// Upper bound for request bodies, which only ever carry a log filter.
+const MAX_BODY_BYTES: usize = 4096;
+
+/// Shared state of the admin endpoints.
+pub struct AdminState {
+    pub metrics: Arc<Metrics>,
+    pub readiness: Readiness,
+    pub diagnostics: Diagnostics,
+    /// Token of the mutating endpoints, see [`AdapterConfig::admin_token`](crate::config::AdapterConfig::admin_token).
+    pub token: Option<String>,
+}
+
+pub async fn bind(host: &str, port: u16) -> anyhow::Result<TcpListener> {
+    TcpListener::bind((host, port))
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to bind admin server to {}:{}: {}", host, port, e))
+}
+
+pub async fn serve(listener: TcpListener, state: Arc<AdminState>) {
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                log::warn!("Admin server accept failed: {}", err);
+                continue;
+            }
+        };
+
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let svc = service_fn(move |req| {
+                let state = Arc::clone(&state);
+                async move { Ok::<_, Infallible>(handle(req, &state).await) }
+            });
+
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), svc)
+                .await
+            {
+                log::debug!("Admin connection error: {:?}", err);
+            }
+        });
+    }
+}
+
+async fn handle(req: Request<Incoming>, state: &AdminState) -> Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => match state.metrics.encode() {
+            Ok(body) => response(StatusCode::OK, prometheus::TEXT_FORMAT, body),
+            Err(err) => {
+                log::error!("Failed to encode metrics: {}", err);
+                response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "text/plain",
+                    String::from("failed to encode metrics"),
+                )
+            }
+        },
+        (&Method::GET, "/healthz") => health(&state.readiness, state.readiness.is_live()),
+        (&Method::GET, "/readyz") => health(&state.readiness, state.readiness.is_ready()),
+        (&Method::GET, "/diagnostics") => response(
+            StatusCode::OK,
+            "application/json",
+            state.diagnostics.report().to_string(),
+        ),
+        (&Method::GET, "/log-level") => match logging::current_filter() {
+            Some(filter) => response(StatusCode::OK, "text/plain", filter),
+            None => response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "text/plain",
+                String::from("logger is not initialized"),
+            ),
+        },
+        (&Method::PUT, "/log-level") => {
+            match reject_unauthorized(req.headers(), state.token.as_deref()) {
+                Some(rejection) => rejection,
+                None => set_log_level(req).await,
+            }
+        }
+        _ => response(
+            StatusCode::NOT_FOUND,
+            "text/plain",
+            String::from("not found"),
+        ),
+    }
+}
+
+async fn set_log_level(req: Request<Incoming>) -> Response<Full<Bytes>> {
+    let body = match Limited::new(req.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            return response(
+                StatusCode::BAD_REQUEST,
+                "text/plain",
+                format!("failed to read body: {}", err),
+            );
+        }
+    };
+
+    let Ok(directives) = std::str::from_utf8(&body) else {
+        return response(
+            StatusCode::BAD_REQUEST,
+            "text/plain",
+            String::from("log filter must be valid UTF-8"),
+        );
+    };
+
+    match logging::set_filter(directives) {
+        Ok(()) => response(StatusCode::OK, "text/plain", directives.trim().to_string()),
+        Err(err) => response(StatusCode::BAD_REQUEST, "text/plain", err.to_string()),
+    }
+}
+
+/// Checks the bearer token of a request to a mutating endpoint and returns
+/// the response rejecting it, if any.
+fn reject_unauthorized(headers: &HeaderMap, token: Option<&str>) -> Option<Response<Full<Bytes>>> {
+    let Some(token) = token else {
+        return Some(response(
+            StatusCode::FORBIDDEN,
+            "text/plain",
+            String::from("set ADMIN_TOKEN to enable this endpoint"),
+        ));
+    };
+
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if constant_time_eq(provided.trim().as_bytes(), token.as_bytes()) => None,
+        _ => Some(response(
+            StatusCode::UNAUTHORIZED,
+            "text/plain",
+            String::from("invalid admin token"),
+        )),
+    }
+}
+
+fn health(readiness: &Readiness, healthy: bool) -> Response<Full<Bytes>> {
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    response(
+        status,
+        "application/json",
+        readiness.report(healthy).to_string(),
+    )
+}
+
+fn response(status: StatusCode, content_type: &str, body: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdminState, bind, health, reject_unauthorized, serve};
+    use crate::diagnostics::Diagnostics;
+    use crate::health::{ComponentStatus, Readiness};
+    use crate::metrics::Metrics;
+    use hyper::{HeaderMap, StatusCode, header::AUTHORIZATION};
+    use std::{net::SocketAddr, sync::Arc};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn mutating_endpoints_require_the_admin_token() {
+        assert!(reject_unauthorized(&headers("Bearer secret"), Some("secret")).is_none());
+        assert_eq!(
+            reject_unauthorized(&headers("Bearer other"), Some("secret"))
+                .unwrap()
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            reject_unauthorized(&HeaderMap::new(), Some("secret"))
+                .unwrap()
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    async fn start(token: Option<&str>) -> SocketAddr {
+        let listener = bind("127.0.0.1", 0).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            Arc::new(AdminState {
+                metrics: Arc::new(Metrics::new("test").unwrap()),
+                readiness: Readiness::new(),
+                diagnostics: Diagnostics::new(),
+                token: token.map(String::from),
+            }),
+        ));
+        addr
+    }
+
+    /// Sends `PUT /log-level` and returns the status and body of the response.
+    async fn put_log_level(addr: SocketAddr, token: &str, filter: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "PUT /log-level HTTP/1.1\r\nHost: admin\r\nAuthorization: Bearer {token}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{filter}",
+            filter.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (response[9..12].parse().unwrap(), body)
+    }
+
+    #[tokio::test]
+    async fn put_log_level_rejects_a_wrong_token() {
+        let addr = start(Some("secret")).await;
+
+        let (status, _) = put_log_level(addr, "guess", "debug").await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn put_log_level_rejects_an_invalid_filter() {
+        let addr = start(Some("secret")).await;
+
+        let (status, body) = put_log_level(addr, "secret", "info,base::store=loud").await;
+        assert_eq!(status, 400);
+        assert!(body.starts_with("invalid log filter"), "{}", body);
+    }
+
+    #[test]
+    fn mutating_endpoints_are_disabled_without_a_token() {
+        assert_eq!(
+            reject_unauthorized(&headers("Bearer secret"), None)
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    /// Status codes of `/readyz` and `/healthz`.
+    fn probes(readiness: &Readiness) -> (StatusCode, StatusCode) {
+        (
+            health(readiness, readiness.is_ready()).status(),
+            health(readiness, readiness.is_live()).status(),
+        )
+    }
+
+    #[test]
+    fn not_ready_components_fail_readyz_but_not_healthz() {
+        let readiness = Readiness::new();
+        readiness.set("init", ComponentStatus::Ready);
+        assert_eq!(probes(&readiness), (StatusCode::OK, StatusCode::OK));
+
+        readiness.set("listener", ComponentStatus::Starting);
+        assert_eq!(
+            probes(&readiness),
+            (StatusCode::SERVICE_UNAVAILABLE, StatusCode::OK)
+        );
+
+        readiness.set("listener", ComponentStatus::Degraded(String::from("slow")));
+        assert_eq!(
+            probes(&readiness),
+            (StatusCode::SERVICE_UNAVAILABLE, StatusCode::OK)
+        );
+
+        readiness.set("listener", ComponentStatus::Ready);
+        assert_eq!(probes(&readiness), (StatusCode::OK, StatusCode::OK));
+
+        readiness.set("listener", ComponentStatus::Failed(String::from("gone")));
+        assert_eq!(
+            probes(&readiness),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::SERVICE_UNAVAILABLE
+            )
+        );
+    }
+}
@@ -1,7 +1,6 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use code0_flow::flow_service::auth::get_authorization_metadata;
-use tokio::time::sleep;
 use tonic::{
     Extensions, Request,
     transport::{Channel, Endpoint},
@@ -13,62 +12,96 @@ use tucana::{
     shared::ModuleStatus,
 };
 
+use crate::retry::RetryPolicy;
+
 pub struct DracoRuntimeStatusService {
     channel: Channel,
     identifier: String,
     aquila_token: String,
 }
 
-const MAX_BACKOFF: u64 = 2000 * 60;
-const MAX_RETRIES: i8 = 10;
+/// Failure to set up a gRPC channel.
+#[derive(Debug)]
+pub enum ChannelError {
+    InvalidUrl {
+        service: String,
+        url: String,
+        source: tonic::transport::Error,
+    },
+    Unreachable {
+        service: String,
+        url: String,
+        attempts: u32,
+        source: tonic::transport::Error,
+    },
+}
+
+impl std::fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl {
+                service,
+                url,
+                source,
+            } => {
+                write!(f, "invalid url `{}` for {}: {}", url, service, source)
+            }
+            Self::Unreachable {
+                service,
+                url,
+                attempts,
+                source,
+            } => write!(
+                f,
+                "could not connect to {} at `{}` after {} attempt(s): {}",
+                service, url, attempts, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidUrl { source, .. } | Self::Unreachable { source, .. } => Some(source),
+        }
+    }
+}
 
 pub async fn create_channel_with_retry(
     channel_name: &str,
     url: String,
     connect_timeout: std::time::Duration,
     request_timeout: std::time::Duration,
-) -> Channel {
-    let mut backoff = 100;
-    let mut retries = 0;
-
-    loop {
-        let channel = match Endpoint::from_shared(url.clone()) {
-            Ok(c) => {
-                log::debug!("Creating a new endpoint for the: {} Service", channel_name);
-                c.connect_timeout(connect_timeout).timeout(request_timeout)
-            }
-            Err(err) => {
-                panic!(
-                    "Cannot create Endpoint for Service: `{}`. Reason: {:?}",
-                    channel_name, err
-                );
-            }
-        };
-
-        match channel.connect().await {
-            Ok(ch) => {
-                return ch;
-            }
-            Err(err) => {
-                log::warn!(
-                    "Retry connect to `{}` using url: `{}` failed: {:?}, retrying in {}ms",
-                    channel_name,
-                    url,
-                    err,
-                    backoff
-                );
-                sleep(std::time::Duration::from_millis(backoff)).await;
-
-                backoff = (backoff * 2).min(MAX_BACKOFF);
-                retries += 1;
-
-                if retries >= MAX_RETRIES {
-                    panic!("Reached max retries to url {}", url)
-                }
-            }
+    retry: &RetryPolicy,
+) -> Result<Channel, ChannelError> {
+    let endpoint = match Endpoint::from_shared(url.clone()) {
+        Ok(endpoint) => {
+            log::debug!("Creating a new endpoint for the: {} Service", channel_name);
+            endpoint
+                .connect_timeout(connect_timeout)
+                .timeout(request_timeout)
         }
-    }
+        Err(source) => {
+            return Err(ChannelError::InvalidUrl {
+                service: channel_name.to_string(),
+                url,
+                source,
+            });
+        }
+    };
+
+    retry
+        .run(channel_name, || endpoint.connect())
+        .await
+        .map_err(|(source, attempts)| ChannelError::Unreachable {
+            service: channel_name.to_string(),
+            url,
+            attempts,
+            source,
+        })
 }
+
 impl DracoRuntimeStatusService {
     pub async fn from_url(
         aquila_url: String,
@@ -76,10 +109,17 @@ impl DracoRuntimeStatusService {
         identifier: String,
         connect_timeout: std::time::Duration,
         request_timeout: std::time::Duration,
-    ) -> Self {
-        let channel =
-            create_channel_with_retry("Aquila", aquila_url, connect_timeout, request_timeout).await;
-        Self::new(channel, identifier, aquila_token)
+        retry: &RetryPolicy,
+    ) -> Result<Self, ChannelError> {
+        let channel = create_channel_with_retry(
+            "Aquila",
+            aquila_url,
+            connect_timeout,
+            request_timeout,
+            retry,
+        )
+        .await?;
+        Ok(Self::new(channel, identifier, aquila_token))
     }
 
     pub fn new(channel: Channel, identifier: String, aquila_token: String) -> Self {
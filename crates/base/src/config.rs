@@ -1,5 +1,15 @@
 use code0_flow::flow_config::environment::Environment;
 use code0_flow::flow_config::mode::Mode;
+use std::time::Duration;
+
+use crate::logging::LogFormat;
+use crate::nats::NatsOptions;
+use crate::retry::RetryPolicy;
+use crate::telemetry::TraceExporter;
+
+/// Port of the admin server if only `ADMIN_TOKEN` is set.
+const DEFAULT_ADMIN_PORT: u16 = 9090;
+const DEFAULT_CONNECT_BACKOFF_JITTER: f64 = 0.2;
 
 /// Service Configuration
 /// This configuration holds the setup for every Adapter.
@@ -27,6 +37,12 @@ pub struct AdapterConfig {
     /// Name of the NATS bucket to use.
     pub nats_bucket: String,
 
+    /// NATS Options
+    ///
+    /// Authentication, TLS, connection name and reconnect behaviour of the NATS
+    /// connection, read from the `NATS_*` variables.
+    pub nats_options: NatsOptions,
+
     /// GRPC Port
     ///
     /// Port on which the adapter's Health Service server will listen.
@@ -73,6 +89,77 @@ pub struct AdapterConfig {
 
     /// Timeout in seconds for Aquila gRPC requests.
     pub aquila_grpc_request_timeout_secs: u64,
+
+    /// Trace Exporter
+    ///
+    /// Where spans are exported to: `otlp`, `stdout` or `none`.
+    pub trace_exporter: TraceExporter,
+
+    /// OTLP Endpoint
+    ///
+    /// gRPC endpoint of the OpenTelemetry collector, used by the `otlp` exporter.
+    pub otlp_endpoint: String,
+
+    /// Admin Host
+    ///
+    /// Host on which the admin HTTP server (`/metrics`) will listen. Defaults
+    /// to loopback, as the endpoints expose internals of the adapter.
+    pub admin_host: String,
+
+    /// Admin Port
+    ///
+    /// Port on which the admin HTTP server will listen. The server is
+    /// disabled unless `ADMIN_PORT` or `ADMIN_TOKEN` is set, and with
+    /// `ADMIN_PORT=0`. Defaults to 9090 if only the token is set.
+    pub admin_port: Option<u16>,
+
+    /// Admin Token
+    ///
+    /// Bearer token required by the mutating admin endpoints (`PUT /log-level`).
+    /// They are disabled when unset.
+    pub admin_token: Option<String>,
+
+    /// Log Level
+    ///
+    /// Log filter in `RUST_LOG` syntax, e.g. `info,base::store=debug`.
+    /// Defaults to `RUST_LOG` or `info`.
+    pub log_level: String,
+
+    /// Log Format
+    ///
+    /// Format of log lines: `text` or `json`.
+    pub log_format: LogFormat,
+
+    /// Drain Timeout Seconds
+    ///
+    /// How long in-flight requests and executions may take to finish on shutdown.
+    pub drain_timeout_secs: u64,
+
+    /// Shutdown Delay Seconds
+    ///
+    /// How long the adapter keeps accepting work after reporting not ready on
+    /// shutdown, so load balancers stop routing to it first.
+    pub shutdown_delay_secs: u64,
+
+    /// Connect Max Attempts
+    ///
+    /// Attempts for the initial NATS and Aquila connections before giving up.
+    pub connect_max_attempts: u32,
+
+    /// Connect Initial Backoff Milliseconds
+    ///
+    /// Delay after the first failed connection attempt. Doubles with every attempt.
+    pub connect_initial_backoff_ms: u64,
+
+    /// Connect Max Backoff Milliseconds
+    ///
+    /// Upper bound for the delay between connection attempts.
+    pub connect_max_backoff_ms: u64,
+
+    /// Connect Backoff Jitter
+    ///
+    /// Fraction of the delay that is randomized, between 0.0 and 1.0.
+    pub connect_backoff_jitter: f64,
 }
 
 impl AdapterConfig {
@@ -113,11 +200,53 @@ impl AdapterConfig {
             code0_flow::flow_config::env_with_default("AQUILA_GRPC_CONNECT_TIMEOUT_SECS", 2_u64);
         let aquila_grpc_request_timeout_secs =
             code0_flow::flow_config::env_with_default("AQUILA_GRPC_REQUEST_TIMEOUT_SECS", 10_u64);
+        let trace_exporter =
+            code0_flow::flow_config::env_with_default("OTEL_TRACES_EXPORTER", TraceExporter::None);
+        let otlp_endpoint = code0_flow::flow_config::env_with_default(
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            String::from("http://localhost:4317"),
+        );
+        let admin_host =
+            code0_flow::flow_config::env_with_default("ADMIN_HOST", String::from("127.0.0.1"));
+        let admin_token = Some(code0_flow::flow_config::env_with_default(
+            "ADMIN_TOKEN",
+            String::new(),
+        ))
+        .filter(|token| !token.is_empty());
+        let admin_port = admin_port(
+            std::env::var("ADMIN_PORT")
+                .is_ok_and(|port| !port.is_empty())
+                .then(|| {
+                    code0_flow::flow_config::env_with_default("ADMIN_PORT", DEFAULT_ADMIN_PORT)
+                }),
+            admin_token.is_some(),
+        );
+        let log_level = code0_flow::flow_config::env_with_default(
+            "LOG_LEVEL",
+            std::env::var("RUST_LOG").unwrap_or_else(|_| String::from("info")),
+        );
+        let log_format = code0_flow::flow_config::env_with_default("LOG_FORMAT", LogFormat::Text);
+        let drain_timeout_secs =
+            code0_flow::flow_config::env_with_default("DRAIN_TIMEOUT_SECS", 30_u64);
+        let shutdown_delay_secs =
+            code0_flow::flow_config::env_with_default("SHUTDOWN_DELAY_SECS", 5_u64);
+        let connect_max_attempts =
+            code0_flow::flow_config::env_with_default("CONNECT_MAX_ATTEMPTS", 10_u32);
+        let connect_initial_backoff_ms =
+            code0_flow::flow_config::env_with_default("CONNECT_INITIAL_BACKOFF_MS", 100_u64);
+        let connect_max_backoff_ms =
+            code0_flow::flow_config::env_with_default("CONNECT_MAX_BACKOFF_MS", 120_000_u64);
+        let connect_backoff_jitter = backoff_jitter(code0_flow::flow_config::env_with_default(
+            "CONNECT_BACKOFF_JITTER",
+            DEFAULT_CONNECT_BACKOFF_JITTER,
+        ));
+        let nats_options = NatsOptions::from_env(&draco_variant);
         Self {
             environment,
             nats_bucket,
             mode,
             nats_url,
+            nats_options,
             grpc_port,
             grpc_host,
             aquila_url,
@@ -128,6 +257,29 @@ impl AdapterConfig {
             adapter_status_update_interval_seconds,
             aquila_grpc_connect_timeout_secs,
             aquila_grpc_request_timeout_secs,
+            trace_exporter,
+            otlp_endpoint,
+            admin_host,
+            admin_port,
+            admin_token,
+            log_level,
+            log_format,
+            drain_timeout_secs,
+            shutdown_delay_secs,
+            connect_max_attempts,
+            connect_initial_backoff_ms,
+            connect_max_backoff_ms,
+            connect_backoff_jitter,
+        }
+    }
+
+    /// Retry policy for the initial NATS and Aquila connections.
+    pub fn connect_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.connect_max_attempts,
+            initial_backoff: Duration::from_millis(self.connect_initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.connect_max_backoff_ms),
+            jitter: self.connect_backoff_jitter,
         }
     }
 
@@ -135,3 +287,51 @@ impl AdapterConfig {
         self.mode == Mode::STATIC
     }
 }
+
+/// The admin endpoints expose internals of the adapter, so the server only
+/// starts when it was asked for.
+fn admin_port(configured: Option<u16>, has_token: bool) -> Option<u16> {
+    match configured {
+        Some(0) => None,
+        Some(port) => Some(port),
+        None => has_token.then_some(DEFAULT_ADMIN_PORT),
+    }
+}
+
+/// `f64` parses `NaN` and `inf`, which would make every backoff delay invalid.
+fn backoff_jitter(jitter: f64) -> f64 {
+    if jitter.is_finite() {
+        return jitter;
+    }
+
+    log::warn!(
+        "CONNECT_BACKOFF_JITTER must be a finite number, got {}; using {}",
+        jitter,
+        DEFAULT_CONNECT_BACKOFF_JITTER
+    );
+    DEFAULT_CONNECT_BACKOFF_JITTER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_ADMIN_PORT, DEFAULT_CONNECT_BACKOFF_JITTER, admin_port, backoff_jitter};
+
+    #[test]
+    fn admin_server_is_disabled_unless_configured() {
+        assert_eq!(admin_port(None, false), None);
+        assert_eq!(admin_port(None, true), Some(DEFAULT_ADMIN_PORT));
+        assert_eq!(admin_port(Some(9100), false), Some(9100));
+        assert_eq!(admin_port(Some(0), true), None);
+    }
+
+    #[test]
+    fn non_finite_jitter_falls_back_to_the_default() {
+        assert_eq!(backoff_jitter(0.5), 0.5);
+        for jitter in ["NaN", "inf", "-inf"] {
+            assert_eq!(
+                backoff_jitter(jitter.parse().unwrap()),
+                DEFAULT_CONNECT_BACKOFF_JITTER
+            );
+        }
+    }
+}
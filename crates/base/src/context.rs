@@ -0,0 +1,51 @@
+//! Request scoped context that follows a unit of work across `.await` points.
+//!
+//! Adapters wrap the handling of an incoming request (or a scheduled run) in
+//! [`with_request_id`]. Everything awaited inside, including the
+//! [`AdapterStore`](crate::store::AdapterStore), can then read the id through
+//! [`current_request_id`], and the logger installed by the
+//! [`ServerRunner`](crate::runner::ServerRunner) appends it to every line.
+//! The store does the same for the flow execution it is running through
+//! [`with_execution`].
+
+use std::future::Future;
+
+/// NATS header carrying the request id on published executions.
+pub const REQUEST_ID_HEADER: &str = "Draco-Request-Id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+    static EXECUTION: ExecutionContext;
+}
+
+/// The flow execution a unit of work belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionContext {
+    pub flow_id: i64,
+    pub execution_id: String,
+}
+
+/// Runs `future` with `request_id` as the current request id.
+pub async fn with_request_id<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// The request id of the surrounding [`with_request_id`] scope, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Runs `future` as part of the flow execution `execution`.
+pub async fn with_execution<F: Future>(execution: ExecutionContext, future: F) -> F::Output {
+    EXECUTION.scope(execution, future).await
+}
+
+/// The flow execution of the surrounding [`with_execution`] scope, if any.
+pub fn current_execution() -> Option<ExecutionContext> {
+    EXECUTION.try_with(Clone::clone).ok()
+}
+
+/// Generates a new request id.
+pub fn generate_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
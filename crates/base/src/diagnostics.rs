@@ -0,0 +1,47 @@
+//! Problems an adapter found in the flows it serves.
+//!
+//! Unlike the [`Readiness`](crate::health::Readiness), diagnostics don't affect
+//! the health of the adapter: a flow with an invalid setting is an error of
+//! the flow, not of the adapter. Each component reports its problems keyed by
+//! subject (e.g. a flow key), replacing what it reported before. They are
+//! served by the [admin server](crate::admin) as `/diagnostics`.
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// Shared registry of diagnostics. Cloning is cheap.
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    components: Arc<RwLock<BTreeMap<String, BTreeMap<String, String>>>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the problems of `component`, keyed by subject. An empty map
+    /// clears them.
+    pub fn set(&self, component: &str, problems: BTreeMap<String, String>) {
+        let mut components = self.components.write().unwrap_or_else(|e| e.into_inner());
+        if problems.is_empty() {
+            components.remove(component);
+        } else {
+            components.insert(component.to_string(), problems);
+        }
+    }
+
+    /// Current problems of every component, sorted by component and subject.
+    pub fn components(&self) -> BTreeMap<String, BTreeMap<String, String>> {
+        self.components
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// JSON body of the `/diagnostics` endpoint.
+    pub fn report(&self) -> serde_json::Value {
+        json!({ "components": self.components() })
+    }
+}
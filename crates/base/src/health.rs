@@ -0,0 +1,306 @@
+//! Adapter reported health.
+//!
+//! The runner and the adapter report the state of their sub-components (e.g.
+//! `init`, `definitions`, an HTTP listener) through the [`Readiness`] in the
+//! [`ServerContext`](crate::runner::ServerContext). Components whose state is
+//! better looked up on demand, like the NATS connection, are added as probes.
+//!
+//! The adapter is *ready* once every component is [`ComponentStatus::Ready`]
+//! and *live* as long as no component has [`ComponentStatus::Failed`]. Both are
+//! served by the gRPC health service (overall status as service `""`, each
+//! component under its own name) and by the [admin server](crate::admin) as
+//! `/readyz` and `/healthz`.
+
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tonic_health::{
+    ServingStatus,
+    pb::health_server::HealthServer,
+    server::{HealthReporter, HealthService},
+};
+
+/// How often the gRPC health statuses are refreshed from the probes.
+const HEALTH_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentStatus {
+    /// Not ready yet, e.g. still initializing.
+    Starting,
+    Ready,
+    /// Running with reduced functionality. Not ready, but still live.
+    Degraded(String),
+    /// Broken beyond recovery. The adapter is no longer live.
+    Failed(String),
+}
+
+impl ComponentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComponentStatus::Starting => "starting",
+            ComponentStatus::Ready => "ready",
+            ComponentStatus::Degraded(_) => "degraded",
+            ComponentStatus::Failed(_) => "failed",
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            ComponentStatus::Degraded(reason) | ComponentStatus::Failed(reason) => Some(reason),
+            ComponentStatus::Starting | ComponentStatus::Ready => None,
+        }
+    }
+
+    fn serving_status(&self) -> ServingStatus {
+        match self {
+            ComponentStatus::Ready => ServingStatus::Serving,
+            _ => ServingStatus::NotServing,
+        }
+    }
+}
+
+type Probe = Box<dyn Fn() -> ComponentStatus + Send + Sync>;
+
+#[derive(Default)]
+struct Components {
+    reported: BTreeMap<String, ComponentStatus>,
+    probes: Vec<(String, Probe)>,
+}
+
+/// Shared registry of component health. Cloning is cheap.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    components: Arc<RwLock<Components>>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports the status of `component`, registering it if unknown.
+    pub fn set(&self, component: &str, status: ComponentStatus) {
+        let mut components = self.components.write().unwrap_or_else(|e| e.into_inner());
+        if components.reported.get(component) != Some(&status) {
+            log::info!(
+                "Component '{}' is {}{}",
+                component,
+                status.as_str(),
+                status
+                    .reason()
+                    .map(|reason| format!(": {}", reason))
+                    .unwrap_or_default()
+            );
+        }
+        components.reported.insert(component.to_string(), status);
+    }
+
+    /// Registers a component whose status is evaluated on every health check.
+    pub fn add_probe<F>(&self, component: &str, probe: F)
+    where
+        F: Fn() -> ComponentStatus + Send + Sync + 'static,
+    {
+        self.components
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .probes
+            .push((component.to_string(), Box::new(probe)));
+    }
+
+    /// Current status of every component, sorted by name.
+    pub fn components(&self) -> BTreeMap<String, ComponentStatus> {
+        let components = self.components.read().unwrap_or_else(|e| e.into_inner());
+        let mut statuses = components.reported.clone();
+        for (name, probe) in &components.probes {
+            statuses.insert(name.clone(), probe());
+        }
+        statuses
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.components()
+            .values()
+            .all(|status| *status == ComponentStatus::Ready)
+    }
+
+    pub fn is_live(&self) -> bool {
+        !self
+            .components()
+            .values()
+            .any(|status| matches!(status, ComponentStatus::Failed(_)))
+    }
+
+    /// JSON body of the `/healthz` and `/readyz` endpoints.
+    pub fn report(&self, healthy: bool) -> serde_json::Value {
+        let components = self
+            .components()
+            .into_iter()
+            .map(|(name, status)| {
+                let mut component = json!({ "status": status.as_str() });
+                if let Some(reason) = status.reason() {
+                    component["reason"] = json!(reason);
+                }
+                (name, component)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        json!({
+            "status": if healthy { "ok" } else { "unavailable" },
+            "components": components,
+        })
+    }
+
+    /// Creates a gRPC health service that follows this registry.
+    ///
+    /// The returned future keeps the statuses up to date and has to be spawned.
+    pub fn grpc_service(
+        &self,
+    ) -> (
+        HealthServer<HealthService>,
+        impl Future<Output = ()> + Send + 'static,
+    ) {
+        let reporter = HealthReporter::new();
+        let service = HealthServer::new(HealthService::from_health_reporter(reporter.clone()));
+
+        (service, sync_reporter(self.clone(), reporter))
+    }
+}
+
+async fn sync_reporter(readiness: Readiness, reporter: HealthReporter) {
+    let mut last = HashMap::new();
+    let mut interval = tokio::time::interval(HEALTH_SYNC_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        let components = readiness.components();
+        let overall = if components.values().all(|s| *s == ComponentStatus::Ready) {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+
+        let statuses = components
+            .iter()
+            .map(|(name, status)| (name.clone(), status.serving_status()))
+            .chain(std::iter::once((String::new(), overall)));
+
+        for (name, status) in statuses {
+            if last.get(&name) != Some(&status) {
+                reporter.set_service_status(&name, status).await;
+                last.insert(name, status);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComponentStatus, Readiness, sync_reporter};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+    use std::time::Duration;
+    use tonic::{Code, Request};
+    use tonic_health::{
+        pb::{HealthCheckRequest, health_check_response::ServingStatus, health_server::Health},
+        server::{HealthReporter, HealthService},
+    };
+
+    #[test]
+    fn degraded_components_are_live_but_not_ready() {
+        let readiness = Readiness::new();
+        readiness.set("init", ComponentStatus::Ready);
+        readiness.set("listener", ComponentStatus::Starting);
+        assert!(!readiness.is_ready());
+        assert!(readiness.is_live());
+
+        readiness.set("listener", ComponentStatus::Degraded(String::from("slow")));
+        assert!(!readiness.is_ready());
+        assert!(readiness.is_live());
+        assert_eq!(
+            readiness.report(false)["components"]["listener"],
+            serde_json::json!({ "status": "degraded", "reason": "slow" })
+        );
+
+        readiness.set("listener", ComponentStatus::Failed(String::from("gone")));
+        assert!(!readiness.is_live());
+    }
+
+    #[test]
+    fn recovered_components_make_the_adapter_ready_again() {
+        let readiness = Readiness::new();
+        let connected = Arc::new(AtomicBool::new(false));
+        let probe = Arc::clone(&connected);
+        readiness.add_probe("nats", move || {
+            if probe.load(Ordering::SeqCst) {
+                ComponentStatus::Ready
+            } else {
+                ComponentStatus::Degraded(String::from("not connected"))
+            }
+        });
+        readiness.set("listener", ComponentStatus::Starting);
+        assert!(!readiness.is_ready());
+
+        readiness.set("listener", ComponentStatus::Ready);
+        assert!(!readiness.is_ready());
+
+        connected.store(true, Ordering::SeqCst);
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn unknown_components_do_not_block_readiness() {
+        let readiness = Readiness::new();
+        assert!(readiness.is_ready());
+
+        readiness.set("listener", ComponentStatus::Ready);
+        assert!(readiness.is_ready());
+        assert!(!readiness.components().contains_key("scheduler"));
+    }
+
+    #[tokio::test]
+    async fn grpc_statuses_follow_the_registry() {
+        let readiness = Readiness::new();
+        readiness.set("listener", ComponentStatus::Degraded(String::from("slow")));
+        let reporter = HealthReporter::new();
+        let service = HealthService::from_health_reporter(reporter.clone());
+        let sync = tokio::spawn(sync_reporter(readiness.clone(), reporter));
+
+        let status = |name: &'static str| {
+            let service = &service;
+            async move {
+                service
+                    .check(Request::new(HealthCheckRequest {
+                        service: name.to_string(),
+                    }))
+                    .await
+                    .map(|response| response.into_inner().status)
+            }
+        };
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(status("").await.unwrap(), ServingStatus::NotServing as i32);
+        assert_eq!(
+            status("listener").await.unwrap(),
+            ServingStatus::NotServing as i32
+        );
+        assert_eq!(
+            status("scheduler").await.unwrap_err().code(),
+            Code::NotFound
+        );
+
+        readiness.set("listener", ComponentStatus::Ready);
+        tokio::time::sleep(super::HEALTH_SYNC_INTERVAL + Duration::from_millis(200)).await;
+        assert_eq!(status("").await.unwrap(), ServingStatus::Serving as i32);
+        assert_eq!(
+            status("listener").await.unwrap(),
+            ServingStatus::Serving as i32
+        );
+
+        sync.abort();
+    }
+}
@@ -0,0 +1,148 @@
+//! Tracking of in-flight work for a graceful drain on shutdown.
+//!
+//! Work that should be finished before the process exits (connections, flow
+//! executions, ...) holds an [`InFlightGuard`] for as long as it runs. On
+//! shutdown the [`ServerRunner`](crate::runner::ServerRunner) waits until every
+//! guard is dropped or the drain timeout passed, and logs whatever was
+//! abandoned.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+#[derive(Default)]
+struct Inner {
+    next_id: AtomicU64,
+    active: Mutex<BTreeMap<u64, (String, Instant)>>,
+    idle: Notify,
+}
+
+/// Shared registry of running work. Cloning is cheap.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<Inner>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a unit of work until the returned guard is dropped.
+    ///
+    /// Tracking is still possible during the drain, so work that was accepted
+    /// before the drain started can spawn follow-up work (e.g. an execution of
+    /// an already accepted request).
+    pub fn track(&self, description: impl Into<String>) -> InFlightGuard {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.active()
+            .insert(id, (description.into(), Instant::now()));
+
+        InFlightGuard {
+            inner: Arc::clone(&self.inner),
+            id,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.active().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits until no work is in flight.
+    ///
+    /// Returns the descriptions of the work still running once `timeout` passed.
+    pub async fn wait_idle(&self, timeout: Duration) -> Result<(), Vec<String>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let idle = self.inner.idle.notified();
+            tokio::pin!(idle);
+            // Register before checking, otherwise a guard dropped in between is missed.
+            idle.as_mut().enable();
+
+            if self.is_empty() {
+                return Ok(());
+            }
+
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                return Err(self
+                    .active()
+                    .values()
+                    .map(|(description, started)| {
+                        format!(
+                            "{} (running for {}ms)",
+                            description,
+                            started.elapsed().as_millis()
+                        )
+                    })
+                    .collect());
+            }
+        }
+    }
+
+    fn active(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, (String, Instant)>> {
+        self.inner.active.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Keeps its unit of work registered as in flight until dropped.
+pub struct InFlightGuard {
+    inner: Arc<Inner>,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut active = self.inner.active.lock().unwrap_or_else(|e| e.into_inner());
+        active.remove(&self.id);
+        if active.is_empty() {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InFlight;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn wait_idle_returns_once_all_work_finished() {
+        let in_flight = InFlight::new();
+        let first = in_flight.track("first");
+        let second = in_flight.track("second");
+
+        let waiting = tokio::spawn({
+            let in_flight = in_flight.clone();
+            async move { in_flight.wait_idle(Duration::from_secs(5)).await }
+        });
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        drop(second);
+        assert_eq!(waiting.await.unwrap(), Ok(()));
+        assert!(in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn wait_idle_reports_the_work_left_after_the_timeout() {
+        let in_flight = InFlight::new();
+        drop(in_flight.track("finished"));
+        let _running = in_flight.track("connection from 127.0.0.1");
+
+        let abandoned = in_flight
+            .wait_idle(Duration::from_millis(20))
+            .await
+            .unwrap_err();
+
+        assert_eq!(abandoned.len(), 1);
+        assert!(abandoned[0].starts_with("connection from 127.0.0.1 (running for "));
+    }
+}
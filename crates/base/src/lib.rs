@@ -1,5 +1,16 @@
+pub mod admin;
 pub mod client;
 pub mod config;
+pub mod context;
+pub mod diagnostics;
+pub mod health;
+pub mod in_flight;
+pub mod logging;
+pub mod metrics;
+pub mod nats;
+pub mod retry;
 pub mod runner;
+pub mod secret;
 pub mod store;
+pub mod telemetry;
 pub mod traits;
@@ -0,0 +1,288 @@
+//! Logger installed by the [`ServerRunner`](crate::runner::ServerRunner).
+//!
+//! The filter uses the `RUST_LOG` directive syntax (e.g. `info,base::store=debug`)
+//! and is taken from [`AdapterConfig::log_level`]. It can be replaced at runtime
+//! through [`set_filter`], which the [admin server](crate::admin) exposes as
+//! `PUT /log-level`. Every line carries the adapter variant and, when present,
+//! the request id and flow execution of the [current context](crate::context).
+
+use crate::config::AdapterConfig;
+use crate::context::{current_execution, current_request_id};
+use env_filter::Filter;
+use log::{Log, Metadata, Record};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+/// Output format of log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines with trailing `key=value` fields.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" | "" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format '{}'", other)),
+        }
+    }
+}
+
+/// Installs the global logger.
+pub fn init(config: &AdapterConfig) -> anyhow::Result<()> {
+    let filter = parse_filter(&config.log_level)?;
+    let builder = formatter(config.log_format, config.draco_variant.to_lowercase());
+
+    let logger = LOGGER.get_or_init(|| ReloadableLogger::new(builder, &config.log_level, filter));
+    log::set_logger(logger).map_err(|e| anyhow::anyhow!("failed to install logger: {}", e))?;
+    log::set_max_level(logger.max_level());
+    Ok(())
+}
+
+/// Replaces the filter of the installed logger.
+pub fn set_filter(directives: &str) -> anyhow::Result<()> {
+    let filter = parse_filter(directives)?;
+    let Some(logger) = LOGGER.get() else {
+        return Err(anyhow::anyhow!("logger is not initialized"));
+    };
+
+    logger.replace_filter(directives, filter);
+    log::set_max_level(logger.max_level());
+    log::info!("Log filter changed to '{}'", directives.trim());
+    Ok(())
+}
+
+/// Directives of the active filter.
+pub fn current_filter() -> Option<String> {
+    LOGGER.get().map(|logger| {
+        logger
+            .filter
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .0
+            .clone()
+    })
+}
+
+/// Inner logger writing every record it gets in `format`.
+fn formatter(format: LogFormat, variant: String) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::new();
+    // Filtering happens in `ReloadableLogger`, the inner logger only formats.
+    builder.filter_level(log::LevelFilter::Trace);
+    match format {
+        LogFormat::Text => builder.format(move |buf, record| {
+            let style = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}] {} variant={}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                record.args(),
+                variant
+            )?;
+            if let Some(request_id) = current_request_id() {
+                write!(buf, " request_id={}", request_id)?;
+            }
+            if let Some(execution) = current_execution() {
+                write!(
+                    buf,
+                    " flow_id={} execution_id={}",
+                    execution.flow_id, execution.execution_id
+                )?;
+            }
+            writeln!(buf)
+        }),
+        LogFormat::Json => {
+            builder
+                .write_style(env_logger::WriteStyle::Never)
+                .format(move |buf, record| {
+                    let mut line = serde_json::Map::new();
+                    line.insert("timestamp".into(), buf.timestamp().to_string().into());
+                    line.insert("level".into(), record.level().as_str().into());
+                    line.insert("target".into(), record.target().into());
+                    line.insert("message".into(), record.args().to_string().into());
+                    line.insert("variant".into(), variant.clone().into());
+                    if let Some(request_id) = current_request_id() {
+                        line.insert("request_id".into(), request_id.into());
+                    }
+                    if let Some(execution) = current_execution() {
+                        line.insert("flow_id".into(), execution.flow_id.into());
+                        line.insert("execution_id".into(), execution.execution_id.into());
+                    }
+
+                    writeln!(buf, "{}", serde_json::Value::Object(line))
+                })
+        }
+    };
+    builder
+}
+
+fn parse_filter(directives: &str) -> anyhow::Result<Filter> {
+    env_filter::Builder::new()
+        .try_parse(directives.trim())
+        .map(|builder| builder.build())
+        .map_err(|e| anyhow::anyhow!("invalid log filter '{}': {}", directives, e))
+}
+
+struct ReloadableLogger {
+    inner: env_logger::Logger,
+    filter: RwLock<(String, Filter)>,
+}
+
+impl ReloadableLogger {
+    fn new(mut inner: env_logger::Builder, directives: &str, filter: Filter) -> Self {
+        Self {
+            inner: inner.build(),
+            filter: RwLock::new((directives.trim().to_string(), filter)),
+        }
+    }
+
+    fn replace_filter(&self, directives: &str, filter: Filter) {
+        *self.filter.write().unwrap_or_else(|e| e.into_inner()) =
+            (directives.trim().to_string(), filter);
+    }
+
+    fn max_level(&self) -> log::LevelFilter {
+        self.filter
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .1
+            .filter()
+    }
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .1
+            .enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let matches = self
+            .filter
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .1
+            .matches(record);
+        if matches {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogFormat, ReloadableLogger, formatter, parse_filter, set_filter};
+    use log::{Level, Log, Metadata, Record};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects what the logger writes.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logger(format: LogFormat, directives: &str) -> (ReloadableLogger, Output) {
+        let output = Output::default();
+        let mut builder = formatter(format, String::from("rest"));
+        builder.target(env_logger::Target::Pipe(Box::new(output.clone())));
+        let logger = ReloadableLogger::new(builder, directives, parse_filter(directives).unwrap());
+        (logger, output)
+    }
+
+    fn log_line(logger: &ReloadableLogger, output: &Output, level: Level) -> String {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target("base::store")
+                .args(format_args!("flow stored"))
+                .build(),
+        );
+        logger.flush();
+        String::from_utf8(std::mem::take(&mut *output.0.lock().unwrap())).unwrap()
+    }
+
+    #[test]
+    fn text_lines_carry_level_target_and_variant() {
+        let (logger, output) = logger(LogFormat::Text, "info");
+
+        let line = log_line(&logger, &output, Level::Warn);
+        assert!(line.contains("WARN"), "{}", line);
+        assert!(
+            line.trim_end()
+                .ends_with("base::store] flow stored variant=rest"),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn json_lines_are_one_object_each() {
+        let (logger, output) = logger(LogFormat::Json, "info");
+
+        let line = log_line(&logger, &output, Level::Info);
+        assert_eq!(line.lines().count(), 1);
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "base::store");
+        assert_eq!(line["message"], "flow stored");
+        assert_eq!(line["variant"], "rest");
+        assert!(line["timestamp"].is_string());
+    }
+
+    #[test]
+    fn replacing_the_filter_changes_what_is_enabled() {
+        let (logger, output) = logger(LogFormat::Json, "info");
+        let debug = Metadata::builder()
+            .level(Level::Debug)
+            .target("base::store")
+            .build();
+        assert!(!logger.enabled(&debug));
+        assert!(log_line(&logger, &output, Level::Debug).is_empty());
+
+        let directives = "info,base::store=debug";
+        logger.replace_filter(directives, parse_filter(directives).unwrap());
+        assert!(logger.enabled(&debug));
+        assert_eq!(logger.max_level(), log::LevelFilter::Debug);
+        assert!(!log_line(&logger, &output, Level::Debug).is_empty());
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert!(parse_filter("info,base::store=loud").is_err());
+        // Rejected before the logger is looked up, so also without one.
+        assert!(
+            set_filter("info,base::store=loud")
+                .unwrap_err()
+                .to_string()
+                .starts_with("invalid log filter")
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Prometheus metrics shared by all adapters.
+//!
+//! Every metric is prefixed with `draco_` and labelled with the adapter
+//! `variant`. Adapters register their own collectors on [`Metrics::registry`]
+//! during `init`; everything is served as `/metrics` by the
+//! [admin server](crate::admin).
+
+use prometheus::{
+    Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub struct Metrics {
+    registry: Registry,
+    flow_executions: IntCounterVec,
+    emitter_wait: HistogramVec,
+    kv_scan_duration: Histogram,
+    flows_in_store: IntGauge,
+}
+
+impl Metrics {
+    pub fn new(variant: &str) -> anyhow::Result<Self> {
+        let labels = HashMap::from([(String::from("variant"), variant.to_lowercase())]);
+        let registry = Registry::new_custom(Some(String::from("draco")), Some(labels))?;
+
+        let flow_executions = IntCounterVec::new(
+            Opts::new(
+                "flow_executions_total",
+                "Flow executions by flow id and outcome",
+            ),
+            &["flow_id", "outcome"],
+        )?;
+        let emitter_wait = HistogramVec::new(
+            HistogramOpts::new(
+                "emitter_wait_seconds",
+                "Time between publishing an execution and its first emitter result",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+            ]),
+            &["outcome"],
+        )?;
+        let kv_scan_duration = Histogram::with_opts(HistogramOpts::new(
+            "kv_scan_duration_seconds",
+            "Duration of scanning the flow store for matching flows",
+        ))?;
+        let flows_in_store = IntGauge::new(
+            "flows_in_store",
+            "Number of flows seen during the last flow store scan",
+        )?;
+
+        registry.register(Box::new(flow_executions.clone()))?;
+        registry.register(Box::new(emitter_wait.clone()))?;
+        registry.register(Box::new(kv_scan_duration.clone()))?;
+        registry.register(Box::new(flows_in_store.clone()))?;
+
+        Ok(Self {
+            registry,
+            flow_executions,
+            emitter_wait,
+            kv_scan_duration,
+            flows_in_store,
+        })
+    }
+
+    /// Registry adapters add their own collectors to.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn record_execution(&self, flow_id: i64, outcome: &str) {
+        self.flow_executions
+            .with_label_values(&[flow_id.to_string().as_str(), outcome])
+            .inc();
+    }
+
+    pub fn observe_emitter_wait(&self, outcome: &str, elapsed: Duration) {
+        self.emitter_wait
+            .with_label_values(&[outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_kv_scan(&self, elapsed: Duration, flows: usize) {
+        self.kv_scan_duration.observe(elapsed.as_secs_f64());
+        self.flows_in_store.set(flows as i64);
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use prometheus::proto::{Metric, MetricFamily};
+    use std::time::Duration;
+
+    fn family<'a>(families: &'a [MetricFamily], name: &str) -> &'a MetricFamily {
+        families
+            .iter()
+            .find(|family| family.name() == name)
+            .unwrap_or_else(|| panic!("metric {} not registered", name))
+    }
+
+    fn labels(metric: &Metric) -> Vec<(&str, &str)> {
+        metric
+            .get_label()
+            .iter()
+            .map(|label| (label.name(), label.value()))
+            .collect()
+    }
+
+    #[test]
+    fn recorded_executions_and_scans_are_gathered() {
+        let metrics = Metrics::new("REST").unwrap();
+        metrics.record_execution(42, "success");
+        metrics.record_execution(42, "success");
+        metrics.record_execution(7, "timeout");
+        metrics.observe_kv_scan(Duration::from_millis(20), 3);
+
+        let families = metrics.registry().gather();
+
+        let executions = family(&families, "draco_flow_executions_total");
+        let mut counts: Vec<_> = executions
+            .get_metric()
+            .iter()
+            .map(|metric| (labels(metric), metric.get_counter().get_value()))
+            .collect();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            counts,
+            vec![
+                (
+                    vec![
+                        ("flow_id", "42"),
+                        ("outcome", "success"),
+                        ("variant", "rest")
+                    ],
+                    2.0
+                ),
+                (
+                    vec![
+                        ("flow_id", "7"),
+                        ("outcome", "timeout"),
+                        ("variant", "rest")
+                    ],
+                    1.0
+                ),
+            ]
+        );
+
+        let scans = family(&families, "draco_kv_scan_duration_seconds");
+        let scan = &scans.get_metric()[0];
+        assert_eq!(labels(scan), vec![("variant", "rest")]);
+        assert_eq!(scan.get_histogram().get_sample_count(), 1);
+        assert!((scan.get_histogram().get_sample_sum() - 0.02).abs() < 1e-9);
+
+        let flows = &family(&families, "draco_flows_in_store").get_metric()[0];
+        assert_eq!(labels(flows), vec![("variant", "rest")]);
+        assert_eq!(flows.get_gauge().get_value(), 3.0);
+    }
+}
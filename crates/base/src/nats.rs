@@ -0,0 +1,443 @@
+//! Connection options for NATS: authentication, TLS and reconnect behaviour.
+//!
+//! Exactly one authentication method may be configured: user and password,
+//! a token, an NKey seed or a `.creds` file. TLS is used when the server
+//! requires it, the URL uses the `tls://` scheme or `NATS_TLS_REQUIRED` is set;
+//! the CA and client certificates are added to the TLS configuration.
+
+use crate::retry::RetryPolicy;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Default)]
+pub struct NatsOptions {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    /// NKey seed (`SU...`), signs the nonce sent by the server.
+    pub nkey_seed: Option<String>,
+    /// `.creds` file holding a user JWT and its NKey seed.
+    pub credentials_file: Option<PathBuf>,
+    /// PEM file with additional root certificates to trust.
+    pub tls_ca_file: Option<PathBuf>,
+    /// PEM file with the client certificate for mutual TLS.
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM file with the private key of the client certificate.
+    pub tls_key_file: Option<PathBuf>,
+    pub tls_required: bool,
+    /// Name of the connection shown by the server (e.g. in `nats server report connections`).
+    pub connection_name: Option<String>,
+    /// Reconnect attempts after the connection was lost. `0` means unlimited.
+    pub max_reconnects: usize,
+    pub reconnect_initial_delay: Duration,
+    pub reconnect_max_delay: Duration,
+}
+
+/// Invalid combination of [`NatsOptions`].
+#[derive(Debug)]
+pub enum NatsOptionsError {
+    /// More than one authentication method is configured.
+    ConflictingAuth(Vec<&'static str>),
+    /// A user without a password or the other way round.
+    IncompleteUserPassword,
+    /// A client certificate without a key or the other way round.
+    IncompleteClientCertificate,
+    MissingFile {
+        option: &'static str,
+        path: PathBuf,
+    },
+}
+
+impl std::fmt::Display for NatsOptionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ConflictingAuth(methods) => write!(
+                f,
+                "only one NATS authentication method may be configured, found: {}",
+                methods.join(", ")
+            ),
+            Self::IncompleteUserPassword => {
+                write!(f, "NATS_USER and NATS_PASSWORD have to be set together")
+            }
+            Self::IncompleteClientCertificate => write!(
+                f,
+                "NATS_TLS_CERT_FILE and NATS_TLS_KEY_FILE have to be set together"
+            ),
+            Self::MissingFile { option, path } => {
+                write!(f, "{} `{}` does not exist", option, path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for NatsOptionsError {}
+
+impl NatsOptions {
+    pub fn from_env(draco_variant: &str) -> Self {
+        let reconnect_initial_delay_ms =
+            code0_flow::flow_config::env_with_default("NATS_RECONNECT_DELAY_MS", 100_u64);
+        let reconnect_max_delay_ms =
+            code0_flow::flow_config::env_with_default("NATS_RECONNECT_MAX_DELAY_MS", 8_000_u64);
+
+        Self {
+            user: optional_env("NATS_USER"),
+            password: optional_env("NATS_PASSWORD"),
+            token: optional_env("NATS_TOKEN"),
+            nkey_seed: optional_env("NATS_NKEY"),
+            credentials_file: optional_env("NATS_CREDS_FILE").map(PathBuf::from),
+            tls_ca_file: optional_env("NATS_TLS_CA_FILE").map(PathBuf::from),
+            tls_cert_file: optional_env("NATS_TLS_CERT_FILE").map(PathBuf::from),
+            tls_key_file: optional_env("NATS_TLS_KEY_FILE").map(PathBuf::from),
+            tls_required: code0_flow::flow_config::env_with_default("NATS_TLS_REQUIRED", false),
+            connection_name: Some(code0_flow::flow_config::env_with_default(
+                "NATS_CONNECTION_NAME",
+                format!("draco-{}", draco_variant.to_lowercase()),
+            ))
+            .filter(|name| !name.is_empty()),
+            max_reconnects: code0_flow::flow_config::env_with_default(
+                "NATS_MAX_RECONNECTS",
+                0_usize,
+            ),
+            reconnect_initial_delay: Duration::from_millis(reconnect_initial_delay_ms),
+            reconnect_max_delay: Duration::from_millis(reconnect_max_delay_ms),
+        }
+    }
+
+    /// Checks for conflicting or incomplete options.
+    ///
+    /// Called once before connecting, so misconfiguration fails fast instead
+    /// of being retried.
+    pub fn validate(&self) -> Result<(), NatsOptionsError> {
+        let methods = [
+            (
+                "user/password",
+                self.user.is_some() || self.password.is_some(),
+            ),
+            ("token", self.token.is_some()),
+            ("nkey", self.nkey_seed.is_some()),
+            ("credentials file", self.credentials_file.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, configured)| configured.then_some(name))
+        .collect::<Vec<_>>();
+
+        if methods.len() > 1 {
+            return Err(NatsOptionsError::ConflictingAuth(methods));
+        }
+        if self.user.is_some() != self.password.is_some() {
+            return Err(NatsOptionsError::IncompleteUserPassword);
+        }
+        if self.tls_cert_file.is_some() != self.tls_key_file.is_some() {
+            return Err(NatsOptionsError::IncompleteClientCertificate);
+        }
+
+        let files = [
+            ("NATS_CREDS_FILE", &self.credentials_file),
+            ("NATS_TLS_CA_FILE", &self.tls_ca_file),
+            ("NATS_TLS_CERT_FILE", &self.tls_cert_file),
+            ("NATS_TLS_KEY_FILE", &self.tls_key_file),
+        ];
+        for (option, path) in files {
+            if let Some(path) = path
+                && !path.exists()
+            {
+                return Err(NatsOptionsError::MissingFile {
+                    option,
+                    path: path.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the `async_nats` options for one connection attempt.
+    ///
+    /// Only fails if the credentials file can't be read or parsed.
+    pub async fn connect_options(&self) -> std::io::Result<async_nats::ConnectOptions> {
+        let mut options = async_nats::ConnectOptions::new();
+
+        if let (Some(user), Some(password)) = (&self.user, &self.password) {
+            options = options.user_and_password(user.clone(), password.clone());
+        }
+        if let Some(token) = &self.token {
+            options = options.token(token.clone());
+        }
+        if let Some(seed) = &self.nkey_seed {
+            options = options.nkey(seed.clone());
+        }
+        if let Some(path) = &self.credentials_file {
+            options = options.credentials_file(path).await?;
+        }
+
+        if let Some(ca_file) = &self.tls_ca_file {
+            options = options.add_root_certificates(ca_file.clone());
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert_file, &self.tls_key_file) {
+            options = options.add_client_certificate(cert.clone(), key.clone());
+        }
+        options = options.require_tls(self.tls_required);
+
+        if let Some(name) = &self.connection_name {
+            options = options.name(name);
+        }
+
+        let reconnect = RetryPolicy {
+            max_attempts: 0,
+            initial_backoff: self.reconnect_initial_delay,
+            max_backoff: self.reconnect_max_delay,
+            jitter: 0.2,
+        };
+        Ok(options
+            .max_reconnects(self.max_reconnects)
+            .reconnect_delay_callback(move |attempts| reconnect.backoff(attempts as u32)))
+    }
+}
+
+/// Secrets are never printed, only whether they are set.
+impl std::fmt::Debug for NatsOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secret = |value: &Option<String>| value.as_ref().map(|_| "<redacted>");
+        f.debug_struct("NatsOptions")
+            .field("user", &self.user)
+            .field("password", &secret(&self.password))
+            .field("token", &secret(&self.token))
+            .field("nkey_seed", &secret(&self.nkey_seed))
+            .field("credentials_file", &self.credentials_file)
+            .field("tls_ca_file", &self.tls_ca_file)
+            .field("tls_cert_file", &self.tls_cert_file)
+            .field("tls_key_file", &self.tls_key_file)
+            .field("tls_required", &self.tls_required)
+            .field("connection_name", &self.connection_name)
+            .field("max_reconnects", &self.max_reconnects)
+            .field("reconnect_initial_delay", &self.reconnect_initial_delay)
+            .field("reconnect_max_delay", &self.reconnect_max_delay)
+            .finish()
+    }
+}
+
+fn optional_env(name: &str) -> Option<String> {
+    Some(code0_flow::flow_config::env_with_default(
+        name,
+        String::new(),
+    ))
+    .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NatsOptions, NatsOptionsError};
+    use std::time::Duration;
+
+    #[test]
+    fn incomplete_options_are_rejected() {
+        let user_only = NatsOptions {
+            user: Some("draco".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            user_only.validate(),
+            Err(NatsOptionsError::IncompleteUserPassword)
+        ));
+
+        let cert_only = NatsOptions {
+            tls_cert_file: Some(std::env::temp_dir()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            cert_only.validate(),
+            Err(NatsOptionsError::IncompleteClientCertificate)
+        ));
+
+        assert!(NatsOptions::default().validate().is_ok());
+    }
+
+    #[test]
+    fn options_are_read_from_the_environment() {
+        let vars = [
+            ("NATS_NKEY", "SUASEED"),
+            ("NATS_TLS_CA_FILE", "/etc/nats/ca.pem"),
+            ("NATS_TLS_CERT_FILE", "/etc/nats/client.pem"),
+            ("NATS_TLS_KEY_FILE", "/etc/nats/client-key.pem"),
+            ("NATS_TLS_REQUIRED", "true"),
+            ("NATS_USER", ""),
+            ("NATS_MAX_RECONNECTS", "3"),
+            ("NATS_RECONNECT_DELAY_MS", "250"),
+        ];
+        // SAFETY: no other test reads or writes the `NATS_*` variables.
+        for (name, value) in vars {
+            unsafe { std::env::set_var(name, value) };
+        }
+        let options = NatsOptions::from_env("REST");
+        for (name, _) in vars {
+            unsafe { std::env::remove_var(name) };
+        }
+
+        assert_eq!(options.nkey_seed.as_deref(), Some("SUASEED"));
+        assert_eq!(options.tls_ca_file, Some("/etc/nats/ca.pem".into()));
+        assert_eq!(options.tls_cert_file, Some("/etc/nats/client.pem".into()));
+        assert_eq!(
+            options.tls_key_file,
+            Some("/etc/nats/client-key.pem".into())
+        );
+        assert!(options.tls_required);
+        // Empty variables count as unset.
+        assert_eq!(options.user, None);
+        assert_eq!(options.connection_name.as_deref(), Some("draco-rest"));
+        assert_eq!(options.max_reconnects, 3);
+        assert_eq!(options.reconnect_initial_delay, Duration::from_millis(250));
+        assert_eq!(options.reconnect_max_delay, Duration::from_secs(8));
+    }
+
+    #[test]
+    fn user_password_conflicts_with_nkey() {
+        let options = NatsOptions {
+            user: Some("draco".into()),
+            password: Some("secret".into()),
+            nkey_seed: Some("SUASEED".into()),
+            ..Default::default()
+        };
+
+        match options.validate() {
+            Err(NatsOptionsError::ConflictingAuth(methods)) => {
+                assert_eq!(methods, vec!["user/password", "nkey"]);
+            }
+            other => panic!("expected conflicting auth, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_tls_files_are_named() {
+        let options = NatsOptions {
+            tls_ca_file: Some("/nonexistent/ca.pem".into()),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            options.validate(),
+            Err(NatsOptionsError::MissingFile {
+                option: "NATS_TLS_CA_FILE",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn every_conflicting_auth_method_is_named() {
+        let options = NatsOptions {
+            token: Some("token".into()),
+            nkey_seed: Some("SUASEED".into()),
+            credentials_file: Some("/nonexistent/draco.creds".into()),
+            ..Default::default()
+        };
+
+        match options.validate() {
+            Err(NatsOptionsError::ConflictingAuth(methods)) => {
+                assert_eq!(methods, vec!["token", "nkey", "credentials file"]);
+            }
+            other => panic!("expected conflicting auth, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn debug_output_redacts_secrets() {
+        let options = NatsOptions {
+            user: Some("draco".into()),
+            password: Some("hunter2".into()),
+            token: Some("s3cr3t".into()),
+            ..Default::default()
+        };
+
+        let debug = format!("{:?}", options);
+        assert!(debug.contains("draco"));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("s3cr3t"));
+    }
+
+    #[tokio::test]
+    async fn connect_options_carry_name_and_tls() {
+        let options = NatsOptions {
+            connection_name: Some("draco-rest".into()),
+            tls_required: true,
+            ..Default::default()
+        };
+
+        let debug = format!("{:?}", options.connect_options().await.unwrap());
+        assert!(debug.contains(r#""name": Some("draco-rest")"#), "{}", debug);
+        assert!(debug.contains(r#""tls_required": true"#), "{}", debug);
+    }
+
+    #[tokio::test]
+    async fn unreadable_credentials_file_fails_the_connect_options() {
+        let options = NatsOptions {
+            credentials_file: Some("/nonexistent/draco.creds".into()),
+            ..Default::default()
+        };
+
+        assert!(options.connect_options().await.is_err());
+    }
+}
+
+/// Locally started `nats-server` for tests. Tests using it should be
+/// `#[ignore]`d, as the binary is usually not installed.
+#[cfg(feature = "testing")]
+pub mod testing {
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+
+    /// JetStream enabled server on a free local port, killed on drop.
+    pub struct NatsServer {
+        process: Child,
+        pub url: String,
+        store_dir: PathBuf,
+    }
+
+    impl NatsServer {
+        /// Starts the server with additional arguments, e.g. for authentication.
+        ///
+        /// # Panics
+        ///
+        /// If the binary can't be started or doesn't listen within five seconds.
+        pub fn start(args: &[&str]) -> Self {
+            let binary = std::env::var("NATS_SERVER_BIN").unwrap_or_else(|_| "nats-server".into());
+            let port = TcpListener::bind("127.0.0.1:0")
+                .and_then(|listener| listener.local_addr())
+                .expect("no free port")
+                .port();
+            let store_dir =
+                std::env::temp_dir().join(format!("draco-nats-{}", uuid::Uuid::new_v4()));
+
+            let process = Command::new(&binary)
+                .args(["-a", "127.0.0.1", "-p", &port.to_string(), "-js", "-sd"])
+                .arg(&store_dir)
+                .args(args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap_or_else(|err| panic!("could not start `{}`: {}", binary, err));
+
+            let server = Self {
+                process,
+                url: format!("nats://127.0.0.1:{}", port),
+                store_dir,
+            };
+            for _ in 0..50 {
+                if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                    return server;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            panic!("nats-server did not start listening on port {}", port);
+        }
+    }
+
+    impl Drop for NatsServer {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+            let _ = std::fs::remove_dir_all(&self.store_dir);
+        }
+    }
+}
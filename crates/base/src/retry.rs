@@ -0,0 +1,144 @@
+//! Exponential backoff with jitter for connecting to NATS and Aquila on startup.
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts including the first one. `0` is treated as `1`.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Fraction of the backoff that is randomized, `0.0` to `1.0`.
+    ///
+    /// With `0.2` a backoff of 1s becomes anything between 0.8s and 1.2s, so
+    /// adapters restarted together don't reconnect in lockstep.
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following the given (1-based) failed attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let base = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // Uniform factor in [1 - jitter, 1 + jitter].
+        let factor = 1.0 - jitter + 2.0 * jitter * random_fraction();
+        base.mul_f64(factor).min(self.max_backoff)
+    }
+
+    /// Runs `operation` until it succeeds or the attempts are used up.
+    ///
+    /// Returns the last error together with the number of attempts made.
+    pub async fn run<T, E, F, Fut>(&self, what: &str, mut operation: F) -> Result<T, (E, u32)>
+    where
+        E: std::fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let max_attempts = self.max_attempts.max(1);
+        let mut attempt = 1;
+
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt >= max_attempts => return Err((err, attempt)),
+                Err(err) => {
+                    let backoff = self.backoff(attempt);
+                    log::warn!(
+                        "Connecting to {} failed (attempt {}/{}): {}, retrying in {}ms",
+                        what,
+                        attempt,
+                        max_attempts,
+                        err,
+                        backoff.as_millis()
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// A value in `[0, 1)` from the randomly seeded std hasher, which is good
+/// enough to spread out retries.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = policy(0.0);
+
+        let backoffs: Vec<_> = [1, 2, 3, 4, 5, 64]
+            .into_iter()
+            .map(|attempt| policy.backoff(attempt).as_millis())
+            .collect();
+
+        assert_eq!(backoffs, vec![100, 200, 400, 800, 1000, 1000]);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction_and_the_maximum() {
+        let policy = policy(0.2);
+
+        for _ in 0..1000 {
+            let backoff = policy.backoff(2);
+            assert!(backoff >= Duration::from_millis(160), "{:?}", backoff);
+            assert!(backoff <= Duration::from_millis(240), "{:?}", backoff);
+            assert!(policy.backoff(4) <= Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn jitter_is_clamped_to_the_full_backoff() {
+        let policy = policy(5.0);
+
+        for _ in 0..1000 {
+            assert!(policy.backoff(1) <= Duration::from_millis(200));
+        }
+    }
+
+    #[tokio::test]
+    async fn run_retries_until_the_attempts_are_used_up() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::ZERO,
+            ..policy(0.0)
+        };
+
+        let mut calls = 0;
+        let result = policy
+            .run("test", || {
+                calls += 1;
+                let calls = calls;
+                async move { if calls < 3 { Err("down") } else { Ok(calls) } }
+            })
+            .await;
+        assert_eq!(result, Ok(3));
+
+        let result: Result<(), _> = policy.run("test", || async { Err("down") }).await;
+        assert_eq!(result, Err(("down", 3)));
+    }
+}
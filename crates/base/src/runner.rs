@@ -1,26 +1,69 @@
 use crate::{
+    admin::{self, AdminState},
     client::DracoRuntimeStatusService,
     config::AdapterConfig,
+    diagnostics::Diagnostics,
+    health::{ComponentStatus, Readiness},
+    in_flight::InFlight,
+    logging,
+    metrics::Metrics,
     store::AdapterStore,
+    telemetry,
     traits::{LoadConfig, Server as AdapterServer},
 };
 use code0_flow::flow_service::{FlowUpdateService, ModuleDefinitionAppendix};
-use std::{sync::Arc, time::Duration};
-use tokio::{signal, task::JoinHandle, time::sleep};
+use opentelemetry::trace::SpanKind;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use tokio::{signal, sync::watch, task::JoinHandle, time::sleep};
 use tonic::transport::Server;
-use tonic_health::pb::health_server::HealthServer;
 
 /// Context passed to adapter server implementations containing all shared resources
 pub struct ServerContext<C: LoadConfig> {
     pub server_config: Arc<C>,
     pub adapter_config: Arc<AdapterConfig>,
     pub adapter_store: Arc<AdapterStore>,
+    pub metrics: Arc<Metrics>,
+    /// Health of the runner's and the adapter's components, see [`crate::health`].
+    pub readiness: Readiness,
+    /// Problems found in the flows, see [`crate::diagnostics`].
+    pub diagnostics: Diagnostics,
+    /// Work that is finished before the runner exits, see [`crate::in_flight`].
+    pub in_flight: InFlight,
+    /// Requested once the adapter server should stop accepting work.
+    pub shutdown: Shutdown,
 }
 
+/// Asks the adapter server to stop accepting work, see
+/// [`Server::run`](crate::traits::Server::run).
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Completes once the shutdown was requested, or the runner is gone.
+    pub async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+}
+
+const INIT_COMPONENT: &str = "init";
+const DEFINITIONS_COMPONENT: &str = "definitions";
+const NATS_COMPONENT: &str = "nats";
+const DRAIN_COMPONENT: &str = "drain";
+
 /// Main server runner that manages the complete adapter lifecycle
 pub struct ServerRunner<C: LoadConfig> {
     context: ServerContext<C>,
     server: Box<dyn AdapterServer<C>>,
+    tracer_provider: Option<SdkTracerProvider>,
+    shutdown: watch::Sender<bool>,
 }
 
 impl<C: LoadConfig> ServerRunner<C> {
@@ -29,29 +72,60 @@ impl<C: LoadConfig> ServerRunner<C> {
     }
 
     pub async fn new<S: AdapterServer<C>>(server: S) -> anyhow::Result<Self> {
-        env_logger::Builder::from_default_env()
-            .filter_level(log::LevelFilter::Debug)
-            .init();
-
         code0_flow::flow_config::load_env_file();
 
         let adapter_config = AdapterConfig::from_env();
+        logging::init(&adapter_config)?;
         let server_config = C::load();
-        let adapter_store = AdapterStore::from_url(
-            adapter_config.nats_url.clone(),
-            adapter_config.nats_bucket.clone(),
+        let tracer_provider = telemetry::init_tracing(&adapter_config)?;
+        let metrics = Arc::new(Metrics::new(&adapter_config.draco_variant)?);
+        let in_flight = InFlight::new();
+        let adapter_store = telemetry::in_span(
+            "draco.runner.connect_store",
+            SpanKind::Client,
+            AdapterStore::from_url(
+                adapter_config.nats_url.clone(),
+                adapter_config.nats_bucket.clone(),
+                &adapter_config.nats_options,
+                Arc::clone(&metrics),
+                in_flight.clone(),
+                adapter_config.connect_retry_policy(),
+            ),
         )
-        .await;
+        .await?;
 
+        let adapter_store = Arc::new(adapter_store);
+        let readiness = Readiness::new();
+        readiness.set(INIT_COMPONENT, ComponentStatus::Starting);
+        if !adapter_config.is_static() {
+            readiness.set(DEFINITIONS_COMPONENT, ComponentStatus::Starting);
+        }
+        let store = Arc::clone(&adapter_store);
+        readiness.add_probe(NATS_COMPONENT, move || {
+            if store.is_connected() {
+                ComponentStatus::Ready
+            } else {
+                ComponentStatus::Degraded(String::from("not connected to NATS"))
+            }
+        });
+
+        let (shutdown, requested) = watch::channel(false);
         let context = ServerContext {
-            adapter_store: Arc::new(adapter_store),
+            adapter_store,
             adapter_config: Arc::new(adapter_config),
             server_config: Arc::new(server_config),
+            metrics,
+            readiness,
+            diagnostics: Diagnostics::new(),
+            in_flight,
+            shutdown: Shutdown { requested },
         };
 
         Ok(Self {
             context,
             server: Box::new(server),
+            tracer_provider,
+            shutdown,
         })
     }
 
@@ -61,6 +135,50 @@ impl<C: LoadConfig> ServerRunner<C> {
         let mut runtime_status_heartbeat_task: Option<JoinHandle<()>> = None;
         log::info!("Starting Draco Variant: {}", config.draco_variant);
 
+        let mut health_task = if config.with_health_service {
+            let (health_service, sync_health) = self.context.readiness.grpc_service();
+            let address = format!("{}:{}", config.grpc_host, config.grpc_port).parse()?;
+
+            log::info!(
+                "Health server starting at {}:{}",
+                config.grpc_host,
+                config.grpc_port
+            );
+
+            Some(tokio::spawn(async move {
+                let serve = Server::builder().add_service(health_service).serve(address);
+
+                tokio::select! {
+                    res = serve => match res {
+                        Err(err) => log::error!("Health server error: {:?}", err),
+                        Ok(()) => log::info!("Health server stopped gracefully"),
+                    },
+                    _ = sync_health => {}
+                }
+            }))
+        } else {
+            None
+        };
+
+        let admin_task = if let Some(admin_port) = config.admin_port {
+            let listener = admin::bind(&config.admin_host, admin_port).await?;
+            let state = Arc::new(AdminState {
+                metrics: Arc::clone(&self.context.metrics),
+                readiness: self.context.readiness.clone(),
+                diagnostics: self.context.diagnostics.clone(),
+                token: config.admin_token.clone(),
+            });
+
+            log::info!(
+                "Admin server starting at {}:{}",
+                config.admin_host,
+                admin_port
+            );
+            Some(tokio::spawn(admin::serve(listener, state)))
+        } else {
+            None
+        };
+
         if !config.is_static() {
             runtime_status_service = Some(Arc::new(
                 DracoRuntimeStatusService::from_url(
@@ -69,8 +187,9 @@ impl<C: LoadConfig> ServerRunner<C> {
                     config.draco_variant.clone(),
                     Duration::from_secs(config.aquila_grpc_connect_timeout_secs),
                     Duration::from_secs(config.aquila_grpc_request_timeout_secs),
+                    &config.connect_retry_policy(),
                 )
-                .await,
+                .await?,
             ));
 
             if let Some(ser) = &runtime_status_service {
@@ -93,90 +212,69 @@ impl<C: LoadConfig> ServerRunner<C> {
             .with_definition_source(service_name)
             .with_appendix(appendix);
 
-            let mut success = false;
-            let mut count = 1;
-            while !success {
-                success = definition_service.send_with_status().await;
-                if success {
-                    break;
-                }
-
-                log::warn!(
-                    "Updating definitions failed, trying again in 2 secs (retry number {})",
-                    count
-                );
-                count += 1;
-                sleep(Duration::from_secs(3)).await;
-            }
-        }
-
-        let health_task = if config.with_health_service {
-            let health_service =
-                code0_flow::flow_health::HealthService::new(config.nats_url.clone());
-            let address = format!("{}:{}", config.grpc_host, config.grpc_port).parse()?;
-
-            log::info!(
-                "Health server starting at {}:{}",
-                config.grpc_host,
-                config.grpc_port
-            );
+            telemetry::in_span("draco.runner.update_definitions", SpanKind::Client, async {
+                let mut success = false;
+                let mut count = 1;
+                while !success {
+                    success = definition_service.send_with_status().await;
+                    if success {
+                        break;
+                    }
 
-            Some(tokio::spawn(async move {
-                if let Err(err) = Server::builder()
-                    .add_service(HealthServer::new(health_service))
-                    .serve(address)
-                    .await
-                {
-                    log::error!("Health server error: {:?}", err);
-                } else {
-                    log::info!("Health server stopped gracefully");
+                    log::warn!(
+                        "Updating definitions failed, trying again in 2 secs (retry number {})",
+                        count
+                    );
+                    telemetry::set_attribute(opentelemetry::KeyValue::new("draco.retries", count));
+                    count += 1;
+                    sleep(Duration::from_secs(3)).await;
                 }
-            }))
-        } else {
-            None
-        };
+            })
+            .await;
+            self.context
+                .readiness
+                .set(DEFINITIONS_COMPONENT, ComponentStatus::Ready);
+        }
 
         let ServerRunner {
             mut server,
             context,
+            tracer_provider,
+            shutdown,
         } = self;
         // Init the adapter server (e.g. create underlying HTTP server)
-        server.init(&context).await?;
+        if let Err(err) = telemetry::in_span(
+            "draco.runner.init_server",
+            SpanKind::Internal,
+            server.init(&context),
+        )
+        .await
+        {
+            context
+                .readiness
+                .set(INIT_COMPONENT, ComponentStatus::Failed(err.to_string()));
+            return Err(err);
+        }
+        context
+            .readiness
+            .set(INIT_COMPONENT, ComponentStatus::Ready);
 
         if let Some(ser) = &runtime_status_service {
-            ser.update_runtime_status_by_status(
-                tucana::shared::module_status::StatusVariant::Running,
-            )
-            .await;
-
-            if config.adapter_status_update_interval_seconds > 0 {
-                let status_service = Arc::clone(ser);
-                let update_interval_seconds = config.adapter_status_update_interval_seconds;
-                runtime_status_heartbeat_task = Some(tokio::spawn(async move {
-                    let mut interval =
-                        tokio::time::interval(Duration::from_secs(update_interval_seconds));
-                    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
-
-                    // First tick is immediate; consume it so heartbeats start after the interval.
-                    interval.tick().await;
-
-                    loop {
-                        interval.tick().await;
-                        status_service
-                            .update_runtime_status_by_status(
-                                tucana::shared::module_status::StatusVariant::Running,
-                            )
-                            .await;
-                    }
-                }));
-
-                log::info!(
-                    "Runtime status heartbeat started (interval={}s)",
-                    update_interval_seconds
-                );
-            } else {
-                log::info!("Runtime status heartbeat is disabled");
-            }
+            let heartbeat = match config.adapter_status_update_interval_seconds {
+                0 => {
+                    log::info!("Runtime status heartbeat is disabled");
+                    None
+                }
+                seconds => {
+                    log::info!("Runtime status heartbeat started (interval={}s)", seconds);
+                    Some(Duration::from_secs(seconds))
+                }
+            };
+            runtime_status_heartbeat_task = Some(tokio::spawn(report_runtime_status(
+                Arc::clone(ser),
+                context.adapter_store.subscribe_connection(),
+                heartbeat,
+            )));
         };
         log::info!("Draco successfully initialized.");
 
@@ -192,62 +290,87 @@ impl<C: LoadConfig> ServerRunner<C> {
         #[cfg(not(unix))]
         let sigterm = std::future::pending::<()>();
 
-        match health_task {
-            Some(mut ht) => {
-                tokio::select! {
-                    // Main adapter server loop finished on its own
-                    res = server.run(&context) => {
-                        log::warn!("Adapter server finished, shutting down");
-                        ht.abort();
-                        res?;
-                    }
+        let health_ended = async {
+            match health_task.as_mut() {
+                Some(task) => {
+                    let _ = task.await;
+                }
+                None => std::future::pending().await,
+            }
+        };
 
-                    // Health server ended first
-                    _ = &mut ht => {
-                        log::warn!("Health server task finished, shutting down adapter");
-                        server.shutdown(&context).await?;
-                    }
+        // The server keeps running until it was asked to stop accepting, so
+        // it is only dropped once it returned or ignored the request.
+        let mut run = server.run(&context);
+        let stop = tokio::select! {
+            // Main adapter server loop finished on its own
+            res = &mut run => {
+                log::warn!("Adapter server finished, shutting down");
+                Stop::Finished(res)
+            }
 
-                    // Ctrl+C
-                    _ = signal::ctrl_c() => {
-                        log::info!("Ctrl+C/Exit signal received, shutting down adapter");
-                        server.shutdown(&context).await?;
-                        ht.abort();
-                    }
-                    _ = sigterm => {
-                        log::info!("SIGTERM received, shutting down adapter");
-                        server.shutdown(&context).await?;
-                        ht.abort();
-                    }
-                }
+            // Health server ended first
+            _ = health_ended => {
+                log::warn!("Health server task finished, shutting down adapter");
+                Stop::Requested
             }
-            None => {
-                tokio::select! {
-                   // Adapter server loop ends on its own
-                   res = server.run(&context) => {
-                       log::warn!("Adapter server finished");
-                       res?;
-                   }
-
-                   // Ctrl+C
-                   _ = signal::ctrl_c() => {
-                       log::info!("Ctrl+C/Exit signal received, shutting down adapter");
-                       server.shutdown(&context).await?;
-                   }
-                   _ = sigterm => {
-                       log::info!("SIGTERM received, shutting down adapter");
-                       server.shutdown(&context).await?;
-                   }
-                }
+
+            // Ctrl+C
+            _ = signal::ctrl_c() => {
+                log::info!("Ctrl+C/Exit signal received, shutting down adapter");
+                Stop::Requested
             }
-        }
+            _ = sigterm => {
+                log::info!("SIGTERM received, shutting down adapter");
+                Stop::Requested
+            }
+        };
+
         if let Some(handle) = runtime_status_heartbeat_task.take() {
             handle.abort();
-            if let Err(err) = handle.await {
-                if !err.is_cancelled() {
-                    log::warn!("Runtime status heartbeat task ended unexpectedly: {}", err);
-                }
+            if let Err(err) = handle.await
+                && !err.is_cancelled()
+            {
+                log::warn!("Runtime status heartbeat task ended unexpectedly: {}", err);
+            }
+        }
+
+        // Not ready is reported while the health endpoints still serve it and
+        // the server still accepts, see `stop_running`.
+        stop_accepting(&context.readiness, runtime_status_service.as_deref()).await;
+        let drain_timeout = Duration::from_secs(config.drain_timeout_secs);
+        let result = match stop {
+            Stop::Finished(result) => {
+                drop(run);
+                result
             }
+            Stop::Requested => {
+                let stopped = stop_running(
+                    &mut run,
+                    &shutdown,
+                    Duration::from_secs(config.shutdown_delay_secs),
+                    drain_timeout,
+                )
+                .await;
+                drop(run);
+                stopped.and(server.shutdown(&context).await)
+            }
+        };
+        if let Err(err) = &result {
+            log::error!("Adapter server failed: {:#}", err);
+        }
+
+        // In-flight work is finished on every exit, including failures.
+        Self::drain(&context, drain_timeout).await;
+        if let Err(err) = server.drained(&context).await {
+            log::warn!("Failed to flush after draining: {:#}", err);
+        }
+
+        if let Some(handle) = health_task {
+            handle.abort();
+        }
+        if let Some(handle) = admin_task {
+            handle.abort();
         }
 
         if let Some(ser) = &runtime_status_service {
@@ -257,7 +380,234 @@ impl<C: LoadConfig> ServerRunner<C> {
             .await;
         };
 
+        if let Some(provider) = tracer_provider
+            && let Err(err) = provider.shutdown()
+        {
+            log::warn!("Failed to flush pending spans: {}", err);
+        }
+
         log::info!("Draco shutdown complete");
-        Ok(())
+        result
+    }
+
+    /// Waits for in-flight work to finish.
+    async fn drain(context: &ServerContext<C>, timeout: Duration) {
+        if context.in_flight.is_empty() {
+            return;
+        }
+
+        log::info!(
+            "Draining {} in-flight task(s), waiting up to {}s",
+            context.in_flight.len(),
+            timeout.as_secs()
+        );
+        match context.in_flight.wait_idle(timeout).await {
+            Ok(()) => log::info!("All in-flight work finished"),
+            Err(abandoned) => {
+                log::warn!(
+                    "Drain timeout reached, abandoning {} in-flight task(s)",
+                    abandoned.len()
+                );
+                for task in abandoned {
+                    log::warn!("Abandoned: {}", task);
+                }
+            }
+        }
+    }
+}
+
+/// Reports the adapter as not ready, so no new work is routed to it.
+async fn stop_accepting(
+    readiness: &Readiness,
+    runtime_status_service: Option<&DracoRuntimeStatusService>,
+) {
+    readiness.set(
+        DRAIN_COMPONENT,
+        ComponentStatus::Degraded(String::from("shutting down")),
+    );
+
+    // The runtime status has no dedicated draining state; `NotReady` keeps
+    // Aquila from treating the adapter as available while it drains.
+    if let Some(ser) = runtime_status_service {
+        ser.update_runtime_status_by_status(tucana::shared::module_status::StatusVariant::NotReady)
+            .await;
+    }
+}
+
+type RunFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+
+/// Lets the adapter server accept work for `delay`, so load balancers notice
+/// it is not ready before it stops accepting, then asks it to stop.
+///
+/// A server that does not return within `timeout` is given up on.
+async fn stop_running(
+    run: &mut RunFuture<'_>,
+    shutdown: &watch::Sender<bool>,
+    delay: Duration,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    if !delay.is_zero() {
+        log::info!("Accepting work for another {}s", delay.as_secs());
+        tokio::select! {
+            res = &mut *run => return res,
+            _ = sleep(delay) => {}
+        }
+    }
+
+    shutdown.send_replace(true);
+    match tokio::time::timeout(timeout, run).await {
+        Ok(res) => res,
+        Err(_) => {
+            log::warn!(
+                "Adapter server did not stop accepting within {}s",
+                timeout.as_secs()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Why the adapter server stopped running.
+enum Stop {
+    /// The server's run loop returned.
+    Finished(anyhow::Result<()>),
+    /// A signal or the end of the health server asked it to shut down.
+    Requested,
+}
+
+/// Reports the runtime status to Aquila: `Running` while connected to NATS and
+/// `NotReady` while the client reconnects.
+///
+/// The status is sent right away, on every connection change and, if enabled,
+/// as a heartbeat.
+async fn report_runtime_status(
+    service: Arc<DracoRuntimeStatusService>,
+    mut connection: watch::Receiver<bool>,
+    heartbeat: Option<Duration>,
+) {
+    let mut interval = heartbeat.map(|period| {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        interval
+    });
+    let mut store_dropped = false;
+
+    loop {
+        let status = if *connection.borrow_and_update() {
+            tucana::shared::module_status::StatusVariant::Running
+        } else {
+            tucana::shared::module_status::StatusVariant::NotReady
+        };
+        service.update_runtime_status_by_status(status).await;
+
+        let tick = async {
+            match interval.as_mut() {
+                Some(interval) => {
+                    interval.tick().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = tick => {}
+            changed = connection.changed(), if !store_dropped => {
+                store_dropped = changed.is_err();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RunFuture, stop_accepting, stop_running};
+    use crate::{
+        admin::{self, AdminState},
+        diagnostics::Diagnostics,
+        health::{ComponentStatus, Readiness},
+        metrics::Metrics,
+    };
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::watch,
+    };
+
+    async fn status_of(addr: SocketAddr, path: &str) -> u16 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!("GET {path} HTTP/1.1\r\nHost: admin\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response[9..12].parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn readyz_reports_not_ready_while_still_accepting() {
+        let readiness = Readiness::new();
+        readiness.set("http_listener", ComponentStatus::Ready);
+        let listener = admin::bind("127.0.0.1", 0).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(admin::serve(
+            listener,
+            Arc::new(AdminState {
+                metrics: Arc::new(Metrics::new("test").unwrap()),
+                readiness: readiness.clone(),
+                diagnostics: Diagnostics::new(),
+                token: None,
+            }),
+        ));
+        assert_eq!(status_of(addr, "/readyz").await, 200);
+
+        let (shutdown, mut requested) = watch::channel(false);
+        let (accepting, still_accepting) = watch::channel(true);
+        let mut run: RunFuture<'static> = Box::pin(async move {
+            let _ = requested.wait_for(|requested| *requested).await;
+            accepting.send_replace(false);
+            Ok(())
+        });
+
+        stop_accepting(&readiness, None).await;
+        let stopping = tokio::spawn(async move {
+            stop_running(
+                &mut run,
+                &shutdown,
+                Duration::from_millis(500),
+                Duration::from_secs(5),
+            )
+            .await
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(status_of(addr, "/readyz").await, 503);
+        assert_eq!(status_of(addr, "/healthz").await, 200);
+        assert!(*still_accepting.borrow());
+
+        stopping.await.unwrap().unwrap();
+        assert!(!*still_accepting.borrow());
+        assert_eq!(status_of(addr, "/readyz").await, 503);
+    }
+
+    #[tokio::test]
+    async fn a_server_ignoring_the_shutdown_is_given_up_on() {
+        let (shutdown, _requested) = watch::channel(false);
+        let mut run: RunFuture<'static> = Box::pin(std::future::pending());
+
+        let stopped = stop_running(
+            &mut run,
+            &shutdown,
+            Duration::ZERO,
+            Duration::from_millis(100),
+        )
+        .await;
+
+        assert!(stopped.is_ok());
+        assert!(*shutdown.borrow());
     }
 }
@@ -0,0 +1,28 @@
+//! Comparison of secrets shared by the adapters and the admin server.
+
+use ring::digest;
+use subtle::ConstantTimeEq;
+
+/// Compares two secrets without leaking where (or whether) they differ.
+///
+/// Both sides are hashed first so that the comparison always runs over
+/// equally sized inputs and does not reveal the length of the expected secret.
+pub fn constant_time_eq(provided: &[u8], expected: &[u8]) -> bool {
+    let provided = digest::digest(&digest::SHA256, provided);
+    let expected = digest::digest(&digest::SHA256, expected);
+
+    provided.as_ref().ct_eq(expected.as_ref()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::constant_time_eq;
+
+    #[test]
+    fn constant_time_eq_compares_contents() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret-but-longer"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
@@ -1,7 +1,18 @@
+use crate::context::{self, ExecutionContext, REQUEST_ID_HEADER, current_request_id};
+use crate::in_flight::InFlight;
+use crate::metrics::Metrics;
+use crate::nats::{NatsOptions, NatsOptionsError};
+use crate::retry::RetryPolicy;
+use crate::telemetry;
 use crate::traits::IdentifiableFlow;
 use async_nats::jetstream::kv::Config;
 use futures_lite::StreamExt;
+use opentelemetry::{KeyValue, trace::SpanKind};
 use prost::Message;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
 use tucana::shared::{
     ExecutionFlow, Struct, ValidationFlow, Value,
     value::Kind::{self, StructValue},
@@ -13,6 +24,10 @@ const EMITTER_WAIT_TIMEOUT_SECONDS: u64 = 30;
 pub struct AdapterStore {
     client: async_nats::Client,
     kv: async_nats::jetstream::kv::Store,
+    metrics: Arc<Metrics>,
+    in_flight: InFlight,
+    /// Follows the connection events of `client`.
+    connected: Arc<watch::Sender<bool>>,
 }
 
 pub enum FlowIdentifyResult {
@@ -28,14 +43,134 @@ pub enum FlowExecutionResult {
     TransportError,
 }
 
+impl FlowExecutionResult {
+    /// Stable name of the outcome, used for span attributes.
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            Self::Ongoing(_) => "ongoing",
+            Self::Failed => "failed",
+            Self::FinishedWithoutOngoing => "finished_without_ongoing",
+            Self::TransportError => "transport_error",
+        }
+    }
+}
+
+/// Change of a flow in the store, see [`AdapterStore::watch_flows`].
+pub enum FlowChange {
+    /// The flow under `key` was added or replaced.
+    Upsert { key: String, flow: ValidationFlow },
+    /// The flow under `key` was deleted.
+    Removed { key: String },
+}
+
+/// Failure to set up the [`AdapterStore`].
+#[derive(Debug)]
+pub enum StoreError {
+    InvalidOptions(NatsOptionsError),
+    Connect {
+        url: String,
+        attempts: u32,
+        source: async_nats::ConnectError,
+    },
+    CreateBucket {
+        bucket: String,
+        source: async_nats::jetstream::context::CreateKeyValueError,
+    },
+    GetBucket {
+        bucket: String,
+        source: async_nats::jetstream::context::KeyValueError,
+    },
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidOptions(source) => write!(f, "invalid NATS options: {}", source),
+            Self::Connect {
+                url,
+                attempts,
+                source,
+            } => write!(
+                f,
+                "could not connect to NATS at `{}` after {} attempt(s): {}",
+                url, attempts, source
+            ),
+            Self::CreateBucket { bucket, source } => {
+                write!(f, "could not create NATS bucket `{}`: {}", bucket, source)
+            }
+            Self::GetBucket { bucket, source } => {
+                write!(f, "could not open NATS bucket `{}`: {}", bucket, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidOptions(source) => Some(source),
+            Self::Connect { source, .. } => Some(source),
+            Self::CreateBucket { source, .. } => Some(source),
+            Self::GetBucket { source, .. } => Some(source),
+        }
+    }
+}
+
 impl AdapterStore {
-    pub async fn from_url(url: String, bucket: String) -> Self {
-        let client = match async_nats::connect(url).await {
+    /// Whether the NATS client is currently connected.
+    pub fn is_connected(&self) -> bool {
+        self.client.connection_state() == async_nats::connection::State::Connected
+    }
+
+    /// Notifies about lost and re-established NATS connections.
+    ///
+    /// The value is `true` while connected. The client reconnects on its own,
+    /// so a disconnect is temporary unless the reconnect attempts run out.
+    pub fn subscribe_connection(&self) -> watch::Receiver<bool> {
+        self.connected.subscribe()
+    }
+
+    pub async fn from_url(
+        url: String,
+        bucket: String,
+        options: &NatsOptions,
+        metrics: Arc<Metrics>,
+        in_flight: InFlight,
+        retry: RetryPolicy,
+    ) -> Result<Self, StoreError> {
+        options.validate().map_err(StoreError::InvalidOptions)?;
+
+        let connected = Arc::new(watch::Sender::new(false));
+        let address = url.as_str();
+        let client = match retry
+            .run("NATS", || {
+                let events = Arc::clone(&connected);
+                async move {
+                    options
+                        .connect_options()
+                        .await?
+                        .event_callback(move |event| {
+                            let events = Arc::clone(&events);
+                            async move { on_connection_event(&events, event) }
+                        })
+                        .connect(address)
+                        .await
+                }
+            })
+            .await
+        {
             Ok(client) => {
                 log::info!("Successfully connected to NATS");
+                connected.send_replace(true);
                 client
             }
-            Err(err) => panic!("Failed to connect to NATS server: {:?}", err),
+            Err((source, attempts)) => {
+                return Err(StoreError::Connect {
+                    url,
+                    attempts,
+                    source,
+                });
+            }
         };
 
         let stream = async_nats::jetstream::new(client.clone());
@@ -50,18 +185,68 @@ impl AdapterStore {
             Ok(_) => {
                 log::info!("Successfully created NATS bucket/bucket already exists");
             }
-            Err(err) => panic!("Failed to create NATS bucket: {:?}", err),
+            Err(source) => return Err(StoreError::CreateBucket { bucket, source }),
         }
 
-        let kv = match stream.get_key_value(bucket).await {
+        let kv = match stream.get_key_value(bucket.clone()).await {
             Ok(kv) => {
                 log::info!("Successfully got NATS bucket");
                 kv
             }
-            Err(err) => panic!("Failed to get key-value store: {}", err),
+            Err(source) => return Err(StoreError::GetBucket { bucket, source }),
         };
 
-        Self { client, kv }
+        Ok(Self {
+            client,
+            kv,
+            metrics,
+            in_flight,
+            connected,
+        })
+    }
+
+    /// Opens the KV bucket `bucket`, creating it if it doesn't exist.
+    ///
+    /// For state an adapter keeps next to the flows, e.g. the last fire times
+    /// of the cron adapter.
+    pub async fn key_value(
+        &self,
+        bucket: &str,
+    ) -> Result<async_nats::jetstream::kv::Store, StoreError> {
+        self.expiring_key_value(bucket, std::time::Duration::ZERO)
+            .await
+    }
+
+    /// Like [`AdapterStore::key_value`], but entries expire after `max_age`.
+    /// A zero `max_age` keeps them forever.
+    pub async fn expiring_key_value(
+        &self,
+        bucket: &str,
+        max_age: std::time::Duration,
+    ) -> Result<async_nats::jetstream::kv::Store, StoreError> {
+        let stream = async_nats::jetstream::new(self.client.clone());
+
+        if let Err(source) = stream
+            .create_key_value(Config {
+                bucket: bucket.to_string(),
+                max_age,
+                ..Default::default()
+            })
+            .await
+        {
+            return Err(StoreError::CreateBucket {
+                bucket: bucket.to_string(),
+                source,
+            });
+        }
+
+        stream
+            .get_key_value(bucket)
+            .await
+            .map_err(|source| StoreError::GetBucket {
+                bucket: bucket.to_string(),
+                source,
+            })
     }
 
     /// get_possible_flow_matches
@@ -88,16 +273,92 @@ impl AdapterStore {
         pattern: String,
         id: I,
     ) -> FlowIdentifyResult {
+        telemetry::in_span(
+            "draco.kv.scan",
+            SpanKind::Client,
+            self.scan_flow_matches(pattern, id),
+        )
+        .await
+    }
+
+    /// Streams the flows whose keys match `subject` (a NATS subject like `CRON.>`).
+    ///
+    /// The current flows are yielded first, followed by every later change.
+    /// Entries that can't be decoded are skipped. The stream ends when the
+    /// underlying watch fails; callers re-watch and start over from the
+    /// current state.
+    pub async fn watch_flows(
+        &self,
+        subject: &str,
+    ) -> Result<futures_lite::stream::Boxed<FlowChange>, async_nats::jetstream::kv::WatchError>
+    {
+        let watch = self.kv.watch_with_history(subject).await?;
+
+        Ok(watch
+            .map_while(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    log::warn!("Flow watch failed: {}", err);
+                    None
+                }
+            })
+            .filter_map(|entry| match entry.operation {
+                async_nats::jetstream::kv::Operation::Put => {
+                    match ValidationFlow::decode(entry.value) {
+                        Ok(flow) => Some(FlowChange::Upsert {
+                            key: entry.key,
+                            flow,
+                        }),
+                        Err(err) => {
+                            log::error!("Failed to decode flow under key {}: {}", entry.key, err);
+                            None
+                        }
+                    }
+                }
+                async_nats::jetstream::kv::Operation::Delete
+                | async_nats::jetstream::kv::Operation::Purge => {
+                    Some(FlowChange::Removed { key: entry.key })
+                }
+            })
+            .boxed())
+    }
+
+    /// Keys of the flows currently in the store that start with `prefix`.
+    ///
+    /// Used next to [`Self::watch_flows`] to find flows deleted while a
+    /// previous watch was down, as the new watch doesn't replay them.
+    pub async fn flow_keys(&self, prefix: &str) -> Result<HashSet<String>, async_nats::Error> {
+        let mut keys = self.kv.keys().await?.boxed();
+        let mut matching = HashSet::new();
+        while let Some(key) = keys.try_next().await? {
+            if key.starts_with(prefix) {
+                matching.insert(key);
+            }
+        }
+        Ok(matching)
+    }
+
+    async fn scan_flow_matches<I: IdentifiableFlow>(
+        &self,
+        pattern: String,
+        id: I,
+    ) -> FlowIdentifyResult {
+        telemetry::set_attribute(KeyValue::new("draco.kv.pattern", pattern.clone()));
+
+        let started = Instant::now();
+        let mut flows_in_store = 0;
         let mut collector = Vec::new();
         let mut keys = match self.kv.keys().await {
             Ok(keys) => keys.boxed(),
             Err(err) => {
                 log::error!("Failed to get keys: {}", err);
+                telemetry::set_error(format!("failed to get keys: {}", err));
                 return FlowIdentifyResult::None;
             }
         };
 
         while let Ok(Some(key)) = keys.try_next().await {
+            flows_in_store += 1;
             if !Self::is_matching_key(&pattern, &key) {
                 continue;
             }
@@ -112,6 +373,10 @@ impl AdapterStore {
             }
         }
 
+        telemetry::set_attribute(KeyValue::new("draco.kv.matches", collector.len() as i64));
+        self.metrics
+            .observe_kv_scan(started.elapsed(), flows_in_store);
+
         match collector.len() {
             0 => FlowIdentifyResult::None,
             1 => FlowIdentifyResult::Single(collector[0].clone()),
@@ -144,9 +409,43 @@ impl AdapterStore {
         flow: ValidationFlow,
         input_value: Option<Value>,
     ) -> FlowExecutionResult {
-        // TODO: Replace body vaidation with triangulus when its ready
+        let flow_id = flow.flow_id;
         let execution_id = uuid::Uuid::new_v4().to_string();
+        let execution = ExecutionContext {
+            flow_id,
+            execution_id: execution_id.clone(),
+        };
+        // Keeps the runner from exiting before the result arrived.
+        let _in_flight = self
+            .in_flight
+            .track(format!("flow {} execution {}", flow_id, execution_id));
+
+        let execute = telemetry::in_span("draco.flow.execute", SpanKind::Internal, async {
+            let result = self.execute_flow(flow, execution_id, input_value).await;
+            self.metrics.record_execution(flow_id, result.outcome());
+            telemetry::set_attribute(KeyValue::new("draco.execution.outcome", result.outcome()));
+            if !matches!(
+                result,
+                FlowExecutionResult::Ongoing(_) | FlowExecutionResult::FinishedWithoutOngoing
+            ) {
+                telemetry::set_error(result.outcome());
+            }
+            result
+        });
+
+        context::with_execution(execution, execute).await
+    }
+
+    async fn execute_flow(
+        &self,
+        flow: ValidationFlow,
+        execution_id: String,
+        input_value: Option<Value>,
+    ) -> FlowExecutionResult {
+        // TODO: Replace body vaidation with triangulus when its ready
         let flow_id = flow.flow_id;
+        telemetry::set_attribute(KeyValue::new("draco.flow_id", flow_id));
+        telemetry::set_attribute(KeyValue::new("draco.execution_id", execution_id.clone()));
         let execution_flow: ExecutionFlow =
             Self::convert_validation_flow(flow, input_value.clone());
         let bytes = execution_flow.encode_to_vec();
@@ -164,7 +463,7 @@ impl AdapterStore {
             input_value
         );
 
-        let mut subscriber = match self.client.subscribe(emitter_topic.clone()).await {
+        let subscriber = match self.client.subscribe(emitter_topic.clone()).await {
             Ok(subscriber) => subscriber,
             Err(err) => {
                 log::error!(
@@ -177,11 +476,19 @@ impl AdapterStore {
             }
         };
 
-        if let Err(err) = self
-            .client
-            .publish(execution_topic.clone(), bytes.into())
-            .await
-        {
+        let publish = telemetry::in_span("draco.execution.publish", SpanKind::Producer, async {
+            telemetry::set_attribute(KeyValue::new(
+                "messaging.destination.name",
+                execution_topic.clone(),
+            ));
+
+            self.client
+                .publish_with_headers(execution_topic.clone(), execution_headers(), bytes.into())
+                .await
+        })
+        .await;
+
+        if let Err(err) = publish {
             log::error!(
                 "Failed to publish flow {} to execution topic '{}': {:?}",
                 flow_id,
@@ -191,6 +498,23 @@ impl AdapterStore {
             return FlowExecutionResult::TransportError;
         }
 
+        let started = Instant::now();
+        let result = telemetry::in_span(
+            "draco.emitter.wait",
+            SpanKind::Consumer,
+            Self::wait_for_emitter(subscriber, emitter_topic),
+        )
+        .await;
+        self.metrics
+            .observe_emitter_wait(result.outcome(), started.elapsed());
+
+        result
+    }
+
+    async fn wait_for_emitter(
+        mut subscriber: async_nats::Subscriber,
+        emitter_topic: String,
+    ) -> FlowExecutionResult {
         loop {
             let next_message = tokio::time::timeout(
                 std::time::Duration::from_secs(EMITTER_WAIT_TIMEOUT_SECONDS),
@@ -248,7 +572,7 @@ impl AdapterStore {
         }
     }
 
-    fn is_matching_key(pattern: &String, key: &String) -> bool {
+    fn is_matching_key(pattern: &str, key: &str) -> bool {
         let split_pattern = pattern.split(".");
         let split_key = key.split(".").collect::<Vec<&str>>();
         let zip = split_pattern.into_iter().zip(split_key);
@@ -299,3 +623,68 @@ impl AdapterStore {
         Some((emit_type_str.clone(), payload.clone()))
     }
 }
+
+/// Headers of a published execution: the request id and the `traceparent`
+/// of the current span, so the runtime continues this trace.
+fn execution_headers() -> async_nats::HeaderMap {
+    let mut headers = async_nats::HeaderMap::new();
+    if let Some(request_id) = current_request_id() {
+        headers.insert(REQUEST_ID_HEADER, request_id);
+    }
+    telemetry::inject_context(&mut headers);
+    headers
+}
+
+fn on_connection_event(connected: &watch::Sender<bool>, event: async_nats::Event) {
+    match event {
+        async_nats::Event::Connected => {
+            log::info!("NATS connection established");
+            connected.send_replace(true);
+        }
+        async_nats::Event::Disconnected => {
+            log::warn!("Lost the NATS connection, reconnecting");
+            connected.send_replace(false);
+        }
+        async_nats::Event::Closed => {
+            log::error!("NATS connection closed, giving up reconnecting");
+            connected.send_replace(false);
+        }
+        other => log::warn!("NATS event: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::execution_headers;
+    use crate::{context, telemetry};
+    use opentelemetry::{
+        Context,
+        trace::{SpanKind, TraceContextExt},
+    };
+
+    #[tokio::test]
+    async fn executions_carry_the_publish_span_and_request_id() {
+        telemetry::testing::in_memory_exporter();
+
+        let (headers, span) = context::with_request_id(
+            String::from("req-1"),
+            telemetry::in_span("draco.execution.publish", SpanKind::Producer, async {
+                let span = Context::current().span().span_context().clone();
+                (execution_headers(), span)
+            }),
+        )
+        .await;
+
+        assert!(span.is_valid());
+        assert_eq!(
+            headers.get("traceparent").map(|value| value.as_str()),
+            Some(format!("00-{}-{}-01", span.trace_id(), span.span_id()).as_str())
+        );
+        assert_eq!(
+            headers
+                .get(context::REQUEST_ID_HEADER)
+                .map(|value| value.as_str()),
+            Some("req-1")
+        );
+    }
+}
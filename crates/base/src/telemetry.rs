@@ -0,0 +1,246 @@
+//! OpenTelemetry tracing for adapters.
+//!
+//! The [`ServerRunner`](crate::runner::ServerRunner) installs a tracer provider
+//! according to [`AdapterConfig::trace_exporter`] and the W3C trace context
+//! propagator. Adapters use [`extract_context`] to continue a trace from an
+//! incoming request and [`in_span`] to instrument their work. The
+//! [`AdapterStore`](crate::store::AdapterStore) injects the current context into
+//! the NATS headers of every execution so the runtime can continue the trace.
+
+use crate::config::AdapterConfig;
+use opentelemetry::{
+    Context, KeyValue, global,
+    propagation::{Extractor, Injector},
+    trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer},
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    Resource,
+    error::OTelSdkResult,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+};
+use std::{borrow::Cow, future::Future, str::FromStr};
+
+const TRACER_NAME: &str = "draco";
+
+/// Where finished spans are exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceExporter {
+    /// OTLP over gRPC to [`AdapterConfig::otlp_endpoint`].
+    Otlp,
+    /// One line per span on stdout.
+    Stdout,
+    /// Tracing disabled. Incoming trace context is still propagated.
+    None,
+}
+
+impl FromStr for TraceExporter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "otlp" => Ok(Self::Otlp),
+            "stdout" | "console" => Ok(Self::Stdout),
+            "none" | "" => Ok(Self::None),
+            other => Err(format!("unknown trace exporter '{}'", other)),
+        }
+    }
+}
+
+/// Installs the global propagator and tracer provider.
+///
+/// The returned provider has to be shut down on exit to flush pending spans.
+pub fn init_tracing(config: &AdapterConfig) -> anyhow::Result<Option<SdkTracerProvider>> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let builder = SdkTracerProvider::builder().with_resource(
+        Resource::builder()
+            .with_service_name(format!("draco-{}", config.draco_variant.to_lowercase()))
+            .build(),
+    );
+
+    let provider = match config.trace_exporter {
+        TraceExporter::None => return Ok(None),
+        TraceExporter::Stdout => builder.with_simple_exporter(StdoutSpanExporter).build(),
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(config.otlp_endpoint.clone())
+                .build()
+                .map_err(|e| anyhow::anyhow!("failed to create OTLP span exporter: {}", e))?;
+            builder.with_batch_exporter(exporter).build()
+        }
+    };
+
+    log::info!(
+        "Tracing enabled: exporter={:?} endpoint={}",
+        config.trace_exporter,
+        config.otlp_endpoint
+    );
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+/// Extracts the remote trace context (`traceparent`/`tracestate`) from a carrier.
+pub fn extract_context(carrier: &dyn Extractor) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(carrier))
+}
+
+/// Starts a span as child of `parent` and returns the context holding it.
+///
+/// The span ends once the last clone of the returned context is dropped.
+pub fn start_span(name: impl Into<Cow<'static, str>>, kind: SpanKind, parent: &Context) -> Context {
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .start_with_context(&tracer, parent);
+
+    parent.with_span(span)
+}
+
+/// Runs `future` inside a new child span of the current context.
+pub async fn in_span<F: Future>(
+    name: impl Into<Cow<'static, str>>,
+    kind: SpanKind,
+    future: F,
+) -> F::Output {
+    let cx = start_span(name, kind, &Context::current());
+    future.with_context(cx).await
+}
+
+/// Adds an attribute to the span of the current context.
+pub fn set_attribute(attribute: KeyValue) {
+    Context::current().span().set_attribute(attribute);
+}
+
+/// Marks the span of the current context as failed.
+pub fn set_error(description: impl Into<Cow<'static, str>>) {
+    Context::current()
+        .span()
+        .set_status(Status::error(description));
+}
+
+/// Writes the current trace context into NATS message headers.
+pub fn inject_context(headers: &mut async_nats::HeaderMap) {
+    let cx = Context::current();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut NatsHeaderInjector(headers))
+    });
+}
+
+struct NatsHeaderInjector<'a>(&'a mut async_nats::HeaderMap);
+
+impl Injector for NatsHeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key, value);
+    }
+}
+
+#[derive(Debug)]
+struct StdoutSpanExporter;
+
+impl SpanExporter for StdoutSpanExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        for span in batch {
+            let duration = span
+                .end_time
+                .duration_since(span.start_time)
+                .unwrap_or_default();
+            let attributes = span
+                .attributes
+                .iter()
+                .map(|attribute| format!(" {}={}", attribute.key, attribute.value))
+                .collect::<String>();
+
+            println!(
+                "span name={} kind={:?} trace_id={} span_id={} parent_span_id={} duration_ms={} status={:?}{}",
+                span.name,
+                span.span_kind,
+                span.span_context.trace_id(),
+                span.span_context.span_id(),
+                span.parent_span_id,
+                duration.as_millis(),
+                span.status,
+                attributes
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// In-memory span export for tests.
+#[cfg(feature = "testing")]
+pub mod testing {
+    use opentelemetry::global;
+    use opentelemetry_sdk::{
+        propagation::TraceContextPropagator,
+        trace::{InMemorySpanExporter, SdkTracerProvider},
+    };
+    use std::sync::OnceLock;
+
+    static EXPORTER: OnceLock<InMemorySpanExporter> = OnceLock::new();
+
+    /// Installs a global tracer provider that keeps finished spans in memory.
+    ///
+    /// The provider is shared by every test in the process, so tests should
+    /// filter the finished spans by something unique to them, e.g. a trace id.
+    pub fn in_memory_exporter() -> InMemorySpanExporter {
+        EXPORTER
+            .get_or_init(|| {
+                let exporter = InMemorySpanExporter::default();
+                let provider = SdkTracerProvider::builder()
+                    .with_simple_exporter(exporter.clone())
+                    .build();
+
+                global::set_text_map_propagator(TraceContextPropagator::new());
+                global::set_tracer_provider(provider);
+                exporter
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inject_context;
+    use opentelemetry::{
+        Context,
+        trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    };
+
+    #[test]
+    fn injects_the_traceparent_of_the_current_span() {
+        super::testing::in_memory_exporter();
+        let span = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+
+        let mut headers = async_nats::HeaderMap::new();
+        {
+            let _guard = Context::new().with_remote_span_context(span).attach();
+            inject_context(&mut headers);
+        }
+
+        assert_eq!(
+            headers.get("traceparent").map(|value| value.as_str()),
+            Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        );
+    }
+
+    #[test]
+    fn injects_nothing_without_a_span() {
+        super::testing::in_memory_exporter();
+
+        let mut headers = async_nats::HeaderMap::new();
+        inject_context(&mut headers);
+
+        assert!(headers.get("traceparent").is_none());
+    }
+}
@@ -64,8 +64,18 @@ pub trait LoadConfig: Sized + Clone + Send + Sync + 'static {
 ///
 /// The server lifecycle follows this pattern:
 /// 1. `init()` - Perform one-time setup and initialization
-/// 2. `run()` - Execute the main server loop (typically runs indefinitely)
+/// 2. `run()` - Execute the main server loop until [`ServerContext::shutdown`]
+///    is requested
 /// 3. `shutdown()` - Clean up resources and perform graceful shutdown
+/// 4. `drained()` - Flush what in-flight work left behind, once it finished
+///
+/// # Health
+///
+/// The adapter is reported ready once `init()` succeeded and every component
+/// registered on [`ServerContext::readiness`] is ready. Implementations report
+/// their own components (listeners, schedulers, ...) there, e.g. as
+/// [`ComponentStatus::Starting`](crate::health::ComponentStatus::Starting) in
+/// `init()` and as `Ready` once `run()` accepts work.
 ///
 /// # Example
 ///
@@ -145,8 +155,10 @@ pub trait Server<C: LoadConfig>: Send + Sync + 'static {
     /// - Event processing loops
     /// - Periodic task execution
     ///
-    /// The method should be designed to run continuously and handle graceful
-    /// shutdown when interrupted by external signals.
+    /// The method should be designed to run continuously and return once
+    /// [`ServerContext::shutdown`] is requested. The runner reports the adapter
+    /// as not ready and waits for the configured shutdown delay before, so the
+    /// server keeps accepting work until load balancers stopped routing to it.
     ///
     /// # Parameters
     ///
@@ -166,8 +178,8 @@ pub trait Server<C: LoadConfig>: Send + Sync + 'static {
     /// Perform graceful shutdown and cleanup operations.
     ///
     /// This method is called when the server receives a shutdown signal (such as
-    /// SIGTERM or SIGINT). It should perform cleanup operations to ensure a
-    /// graceful shutdown:
+    /// SIGTERM or SIGINT), once `run()` returned. It should perform cleanup
+    /// operations to ensure a graceful shutdown:
     ///
     /// - Close database connections and network sockets
     /// - Flush pending writes or queued operations
@@ -191,6 +203,15 @@ pub trait Server<C: LoadConfig>: Send + Sync + 'static {
     /// This method should return an error if critical cleanup operations fail,
     /// though the server will still terminate regardless of the result.
     async fn shutdown(&mut self, ctx: &ServerContext<C>) -> anyhow::Result<()>;
+
+    /// Called once the in-flight work was drained after `run()` ended, or the
+    /// drain timeout passed.
+    ///
+    /// Use it to flush output written by in-flight work, such as logs that
+    /// are written in the background. Does nothing by default.
+    async fn drained(&mut self, _ctx: &ServerContext<C>) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 /// A trait for identifying and matching validation flows.
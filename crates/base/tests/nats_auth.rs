@@ -0,0 +1,366 @@
+//! Connects the store to a locally started `nats-server` with authentication.
+//!
+//! Tests that need a server are ignored by default, run them with
+//! `cargo test -- --ignored`. Set `NATS_SERVER_BIN` to use a binary outside of
+//! `PATH`.
+
+use base::in_flight::InFlight;
+use base::metrics::Metrics;
+use base::nats::testing::NatsServer;
+use base::nats::{NatsOptions, NatsOptionsError};
+use base::retry::RetryPolicy;
+use base::store::{AdapterStore, StoreError};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use nkeys::KeyPair as NKeyPair;
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedIssuer, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn single_attempt() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+        jitter: 0.0,
+    }
+}
+
+async fn connect(url: &str, options: &NatsOptions) -> Result<AdapterStore, StoreError> {
+    AdapterStore::from_url(
+        url.to_string(),
+        "nats_auth_test".to_string(),
+        options,
+        Arc::new(Metrics::new("test").unwrap()),
+        InFlight::new(),
+        single_attempt(),
+    )
+    .await
+}
+
+#[tokio::test]
+#[ignore = "needs a `nats-server` binary"]
+async fn connects_with_user_and_password() {
+    let server = NatsServer::start(&["--user", "draco", "--pass", "secret"]);
+
+    let options = NatsOptions {
+        user: Some("draco".into()),
+        password: Some("secret".into()),
+        ..Default::default()
+    };
+    let store = connect(&server.url, &options).await.unwrap();
+    assert!(store.is_connected());
+
+    let wrong = NatsOptions {
+        password: Some("wrong".into()),
+        ..options
+    };
+    assert!(matches!(
+        connect(&server.url, &wrong).await,
+        Err(StoreError::Connect { attempts: 1, .. })
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs a `nats-server` binary"]
+async fn connects_with_token() {
+    let server = NatsServer::start(&["--auth", "s3cr3t"]);
+
+    let options = NatsOptions {
+        token: Some("s3cr3t".into()),
+        connection_name: Some("draco-test".into()),
+        ..Default::default()
+    };
+    assert!(connect(&server.url, &options).await.is_ok());
+
+    let anonymous = NatsOptions::default();
+    assert!(matches!(
+        connect(&server.url, &anonymous).await,
+        Err(StoreError::Connect { .. })
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs a `nats-server` binary"]
+async fn connects_with_tls_and_a_client_certificate() {
+    let files = TempFiles::new();
+    let pki = Pki::generate(&files);
+    let server = NatsServer::start(&[
+        "--tls",
+        "--tlscert",
+        path_arg(&pki.server_cert),
+        "--tlskey",
+        path_arg(&pki.server_key),
+        "--tlscacert",
+        path_arg(&pki.ca),
+        "--tlsverify",
+    ]);
+
+    let options = NatsOptions {
+        tls_required: true,
+        tls_ca_file: Some(pki.ca.clone()),
+        tls_cert_file: Some(pki.client_cert.clone()),
+        tls_key_file: Some(pki.client_key.clone()),
+        ..Default::default()
+    };
+    let store = connect(&server.url, &options).await.unwrap();
+    assert!(store.is_connected());
+
+    // The server verifies client certificates, so the CA alone isn't enough.
+    let without_certificate = NatsOptions {
+        tls_cert_file: None,
+        tls_key_file: None,
+        ..options.clone()
+    };
+    assert!(matches!(
+        connect(&server.url, &without_certificate).await,
+        Err(StoreError::Connect { .. })
+    ));
+
+    let untrusted_server = NatsOptions {
+        tls_ca_file: None,
+        ..options
+    };
+    assert!(matches!(
+        connect(&server.url, &untrusted_server).await,
+        Err(StoreError::Connect { .. })
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs a `nats-server` binary"]
+async fn connects_with_nkey_seed() {
+    let files = TempFiles::new();
+    let user = NKeyPair::new_user();
+    let config = files.write(
+        "nkey.conf",
+        &format!(
+            "authorization {{ users = [ {{ nkey: {} }} ] }}\n",
+            user.public_key()
+        ),
+    );
+    let server = NatsServer::start(&["-c", path_arg(&config)]);
+
+    let options = NatsOptions {
+        nkey_seed: Some(user.seed().unwrap()),
+        ..Default::default()
+    };
+    let store = connect(&server.url, &options).await.unwrap();
+    assert!(store.is_connected());
+
+    let unknown = NatsOptions {
+        nkey_seed: Some(NKeyPair::new_user().seed().unwrap()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        connect(&server.url, &unknown).await,
+        Err(StoreError::Connect { .. })
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs a `nats-server` binary"]
+async fn connects_with_credentials_file() {
+    let files = TempFiles::new();
+    let operator = NKeyPair::new_operator();
+    let account = NKeyPair::new_account();
+    let user = NKeyPair::new_user();
+
+    let operator_jwt = jwt(&operator, &operator, json!({ "type": "operator" }));
+    let account_jwt = jwt(
+        &operator,
+        &account,
+        json!({
+            "type": "account",
+            // JetStream is enabled by any storage limit other than 0.
+            "limits": {
+                "subs": -1, "data": -1, "payload": -1, "imports": -1, "exports": -1,
+                "wildcards": true, "conn": -1, "leaf": -1,
+                "mem_storage": -1, "disk_storage": -1, "streams": -1, "consumer": -1
+            }
+        }),
+    );
+    let user_jwt = jwt(
+        &account,
+        &user,
+        json!({ "type": "user", "subs": -1, "data": -1, "payload": -1 }),
+    );
+
+    let config = files.write(
+        "operator.conf",
+        &format!(
+            "operator: {}\nresolver: MEMORY\nresolver_preload: {{\n  {}: {}\n}}\n",
+            operator_jwt,
+            account.public_key(),
+            account_jwt
+        ),
+    );
+    let server = NatsServer::start(&["-c", path_arg(&config)]);
+
+    let credentials = files.write("user.creds", &credentials_file(&user_jwt, &user));
+    let options = NatsOptions {
+        credentials_file: Some(credentials),
+        ..Default::default()
+    };
+    let store = connect(&server.url, &options).await.unwrap();
+    assert!(store.is_connected());
+
+    // A JWT that doesn't belong to the seed fails the signature check.
+    let stolen_jwt = files.write(
+        "stolen.creds",
+        &credentials_file(&user_jwt, &NKeyPair::new_user()),
+    );
+    let stolen = NatsOptions {
+        credentials_file: Some(stolen_jwt),
+        ..Default::default()
+    };
+    assert!(matches!(
+        connect(&server.url, &stolen).await,
+        Err(StoreError::Connect { .. })
+    ));
+}
+
+#[tokio::test]
+async fn rejects_conflicting_auth_before_connecting() {
+    let options = NatsOptions {
+        user: Some("draco".into()),
+        password: Some("secret".into()),
+        token: Some("s3cr3t".into()),
+        ..Default::default()
+    };
+
+    // No server is needed, the options are checked before the first attempt.
+    match connect("nats://127.0.0.1:1", &options).await {
+        Err(StoreError::InvalidOptions(NatsOptionsError::ConflictingAuth(methods))) => {
+            assert_eq!(methods, vec!["user/password", "token"]);
+        }
+        _ => panic!("expected conflicting auth methods"),
+    }
+}
+
+#[tokio::test]
+async fn rejects_missing_credentials_file() {
+    let options = NatsOptions {
+        credentials_file: Some("/nonexistent/draco.creds".into()),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        connect("nats://127.0.0.1:1", &options).await,
+        Err(StoreError::InvalidOptions(NatsOptionsError::MissingFile {
+            option: "NATS_CREDS_FILE",
+            ..
+        }))
+    ));
+}
+
+/// Directory for the files of one test, removed on drop.
+struct TempFiles {
+    dir: PathBuf,
+}
+
+impl TempFiles {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("draco-nats-auth-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn path_arg(path: &Path) -> &str {
+    path.to_str().expect("temp paths are UTF-8")
+}
+
+/// CA, server and client certificates for a TLS server verifying clients.
+struct Pki {
+    ca: PathBuf,
+    server_cert: PathBuf,
+    server_key: PathBuf,
+    client_cert: PathBuf,
+    client_key: PathBuf,
+}
+
+impl Pki {
+    fn generate(files: &TempFiles) -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+
+        let issue = |names: Vec<String>, usage: ExtendedKeyUsagePurpose| {
+            let mut params = CertificateParams::new(names).unwrap();
+            params.extended_key_usages = vec![usage];
+            let key = KeyPair::generate().unwrap();
+            let certificate = params.signed_by(&key, &ca).unwrap();
+            (certificate.pem(), key.serialize_pem())
+        };
+        let (server_cert, server_key) = issue(
+            vec!["127.0.0.1".into(), "localhost".into()],
+            ExtendedKeyUsagePurpose::ServerAuth,
+        );
+        let (client_cert, client_key) =
+            issue(vec!["draco".into()], ExtendedKeyUsagePurpose::ClientAuth);
+
+        Self {
+            ca: files.write("ca.pem", &ca.pem()),
+            server_cert: files.write("server.pem", &server_cert),
+            server_key: files.write("server-key.pem", &server_key),
+            client_cert: files.write("client.pem", &client_cert),
+            client_key: files.write("client-key.pem", &client_key),
+        }
+    }
+}
+
+/// NATS JWT of `subject` with the `nats` claims, signed by `issuer`.
+fn jwt(issuer: &NKeyPair, subject: &NKeyPair, mut nats: Value) -> String {
+    nats["version"] = json!(2);
+    let issued_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let claims = json!({
+        "jti": uuid::Uuid::new_v4().simple().to_string().to_uppercase(),
+        "iat": issued_at,
+        "iss": issuer.public_key(),
+        "sub": subject.public_key(),
+        "name": "draco-test",
+        "nats": nats,
+    });
+
+    let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ed25519-nkey"}"#);
+    let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signature = issuer
+        .sign(format!("{}.{}", header, payload).as_bytes())
+        .unwrap();
+    format!(
+        "{}.{}.{}",
+        header,
+        payload,
+        URL_SAFE_NO_PAD.encode(signature)
+    )
+}
+
+/// `.creds` file as written by `nsc` for `user_jwt` and the seed of `user`.
+fn credentials_file(user_jwt: &str, user: &NKeyPair) -> String {
+    format!(
+        "-----BEGIN NATS USER JWT-----\n{}\n------END NATS USER JWT------\n\n\
+         -----BEGIN USER NKEY SEED-----\n{}\n------END USER NKEY SEED------\n",
+        user_jwt,
+        user.seed().unwrap()
+    )
+}